@@ -26,12 +26,95 @@
 #[cfg(all(feature = "defmt", feature = "log"))]
 compile_error!("You may not enable both `defmt` and `log` features.");
 
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Maximum number of log lines below error level emitted per poll cycle.
+///
+/// Further lines in the same cycle are suppressed, and summarized once the
+/// next cycle starts; see [`new_poll_cycle`](crate::new_poll_cycle). Nothing
+/// is suppressed until the driver starts the first cycle.
+pub const MAX_LOGS_PER_CYCLE: usize = 32;
+
+/// Limits the number of log lines emitted per poll cycle.
+///
+/// Only atomic loads and stores are used, so this also works on targets
+/// without atomic read-modify-write operations. Concurrent logging may
+/// miscount slightly, which is acceptable for throttling.
+///
+/// Throttling is off until the first call to
+/// [`new_cycle`](#method.new_cycle), so drivers that never mark their poll
+/// cycles keep every line.
+pub(crate) struct LogGovernor {
+    enabled: AtomicBool,
+    emitted: AtomicUsize,
+    suppressed: AtomicUsize,
+}
+
+impl LogGovernor {
+    pub(crate) const fn new() -> Self {
+        LogGovernor {
+            enabled: AtomicBool::new(false),
+            emitted: AtomicUsize::new(0),
+            suppressed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Return whether another log line may be emitted in the current cycle.
+    #[cfg_attr(not(any(feature = "log", feature = "defmt")), allow(dead_code))]
+    pub(crate) fn admit(&self) -> bool {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return true;
+        }
+        let emitted = self.emitted.load(Ordering::Relaxed);
+        if emitted < MAX_LOGS_PER_CYCLE {
+            self.emitted.store(emitted + 1, Ordering::Relaxed);
+            true
+        } else {
+            let suppressed = self.suppressed.load(Ordering::Relaxed);
+            self.suppressed
+                .store(suppressed.saturating_add(1), Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Start a new cycle, and return the number of lines suppressed in the
+    /// previous one.
+    pub(crate) fn new_cycle(&self) -> usize {
+        self.enabled.store(true, Ordering::Relaxed);
+        self.emitted.store(0, Ordering::Relaxed);
+        let suppressed = self.suppressed.load(Ordering::Relaxed);
+        self.suppressed.store(0, Ordering::Relaxed);
+        suppressed
+    }
+}
+
+/// Return the governor of the crate's log lines.
+///
+/// Tests run concurrently, so each test thread gets a governor of its own.
+pub(crate) fn governor() -> &'static LogGovernor {
+    #[cfg(not(test))]
+    {
+        static GOVERNOR: LogGovernor = LogGovernor::new();
+        &GOVERNOR
+    }
+    #[cfg(test)]
+    {
+        std::thread_local! {
+            static GOVERNOR: &'static LogGovernor =
+                std::boxed::Box::leak(std::boxed::Box::new(LogGovernor::new()));
+        }
+        GOVERNOR.with(|governor| *governor)
+    }
+}
+
+// Tests run on the host, where the core macros report values through
+// `Debug` and the values need not implement `defmt::Format`.
 macro_rules! assert {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::assert!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::assert!($($x)*);
         }
     };
@@ -40,9 +123,9 @@ macro_rules! assert {
 macro_rules! assert_eq {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::assert_eq!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::assert_eq!($($x)*);
         }
     };
@@ -51,9 +134,9 @@ macro_rules! assert_eq {
 macro_rules! assert_ne {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::assert_ne!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::assert_ne!($($x)*);
         }
     };
@@ -62,9 +145,9 @@ macro_rules! assert_ne {
 macro_rules! debug_assert {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::debug_assert!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::debug_assert!($($x)*);
         }
     };
@@ -73,9 +156,9 @@ macro_rules! debug_assert {
 macro_rules! debug_assert_eq {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::debug_assert_eq!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::debug_assert_eq!($($x)*);
         }
     };
@@ -84,9 +167,9 @@ macro_rules! debug_assert_eq {
 macro_rules! debug_assert_ne {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::debug_assert_ne!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::debug_assert_ne!($($x)*);
         }
     };
@@ -129,9 +212,17 @@ macro_rules! trace {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             #[cfg(feature = "log")]
-            ::log::trace!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::log::trace!($s $(, $x)*);
+                }
+            }
             #[cfg(feature = "defmt")]
-            ::defmt::trace!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::defmt::trace!($s $(, $x)*);
+                }
+            }
             #[cfg(not(any(feature = "log", feature="defmt")))]
             let _ = ($( & $x ),*);
         }
@@ -142,9 +233,17 @@ macro_rules! debug {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             #[cfg(feature = "log")]
-            ::log::debug!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::log::debug!($s $(, $x)*);
+                }
+            }
             #[cfg(feature = "defmt")]
-            ::defmt::debug!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::defmt::debug!($s $(, $x)*);
+                }
+            }
             #[cfg(not(any(feature = "log", feature="defmt")))]
             let _ = ($( & $x ),*);
         }
@@ -155,9 +254,17 @@ macro_rules! info {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             #[cfg(feature = "log")]
-            ::log::info!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::log::info!($s $(, $x)*);
+                }
+            }
             #[cfg(feature = "defmt")]
-            ::defmt::info!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::defmt::info!($s $(, $x)*);
+                }
+            }
             #[cfg(not(any(feature = "log", feature="defmt")))]
             let _ = ($( & $x ),*);
         }
@@ -168,9 +275,17 @@ macro_rules! warn {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             #[cfg(feature = "log")]
-            ::log::warn!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::log::warn!($s $(, $x)*);
+                }
+            }
             #[cfg(feature = "defmt")]
-            ::defmt::warn!($s $(, $x)*);
+            {
+                if $crate::fmt::governor().admit() {
+                    ::defmt::warn!($s $(, $x)*);
+                }
+            }
             #[cfg(not(any(feature = "log", feature="defmt")))]
             let _ = ($( & $x ),*);
         }
@@ -217,9 +332,11 @@ macro_rules! unwrap {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct NoneError;
 
+#[allow(dead_code)]
 pub trait Try {
     type Ok;
     type Error;
@@ -245,3 +362,74 @@ impl<T, E> Try for Result<T, E> {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_governor_suppresses_after_threshold() {
+        let governor = LogGovernor::new();
+
+        // Nothing is suppressed before the first cycle
+        for _ in 0..2 * MAX_LOGS_PER_CYCLE {
+            assert!(governor.admit());
+        }
+        assert_eq!(governor.new_cycle(), 0);
+
+        for _ in 0..MAX_LOGS_PER_CYCLE {
+            assert!(governor.admit());
+        }
+        assert!(!governor.admit());
+        assert!(!governor.admit());
+
+        assert_eq!(governor.new_cycle(), 2);
+        assert_eq!(governor.new_cycle(), 0);
+
+        assert!(governor.admit());
+        assert_eq!(governor.new_cycle(), 0);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_lines_throttled_per_cycle() {
+        use std::format;
+
+        crate::test_log::install();
+        let summaries = || {
+            crate::test_log::lines()
+                .iter()
+                .filter(|line| line.ends_with("further socket logs suppressed"))
+                .count()
+        };
+
+        // Until the driver starts a cycle, every line is logged
+        for i in 0..MAX_LOGS_PER_CYCLE + 5 {
+            debug!("line {}", i);
+        }
+        assert_eq!(crate::test_log::lines().len(), MAX_LOGS_PER_CYCLE + 5);
+
+        crate::new_poll_cycle();
+        assert_eq!(summaries(), 0);
+        for i in 0..MAX_LOGS_PER_CYCLE + 3 {
+            debug!("cycle line {}", i);
+        }
+        let lines = crate::test_log::lines();
+        let cycle_lines = lines.iter().filter(|line| line.starts_with("cycle line"));
+        assert_eq!(cycle_lines.count(), MAX_LOGS_PER_CYCLE);
+        assert_eq!(
+            lines.last(),
+            Some(&format!("cycle line {}", MAX_LOGS_PER_CYCLE - 1))
+        );
+
+        // The suppressed lines are summarized once, by the next cycle
+        crate::new_poll_cycle();
+        assert_eq!(summaries(), 1);
+        assert_eq!(
+            crate::test_log::lines().last().map(|line| line.as_str()),
+            Some("3 further socket logs suppressed")
+        );
+        crate::new_poll_cycle();
+        assert_eq!(summaries(), 1);
+    }
+}
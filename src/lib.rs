@@ -3,28 +3,77 @@
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+mod local_addr;
 mod meta;
+mod orphan;
+mod port_allocator;
 mod ref_;
 mod ring_buffer;
 mod set;
 pub mod tcp;
+#[cfg(feature = "socket-tcp")]
 pub mod tcp_listener;
 pub mod udp;
+#[cfg(feature = "socket-udp")]
 pub mod udp_listener;
 
+use embassy_time::{Duration, Instant};
+
+/// The address types of every socket and listener of the crate.
+pub use no_std_net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+pub use self::local_addr::LocalAddr;
 pub(crate) use self::meta::Meta as SocketMeta;
+pub use self::orphan::OrphanPolicy;
+pub use self::port_allocator::PortAllocator;
 pub use self::ring_buffer::RingBuffer;
 
 #[cfg(feature = "socket-tcp")]
-pub use tcp::{State as TcpState, TcpSocket};
+pub use tcp::{
+    KeepAlive, RemoteEndpoint, Shutdown, State as TcpState, TcpEvent, TcpSocket, TcpSocketConfig,
+};
+
+#[cfg(feature = "socket-tcp")]
+pub use tcp_listener::TcpListener;
+#[cfg(feature = "socket-udp")]
+pub use udp_listener::UdpListener;
+
+#[cfg(all(feature = "socket-tcp", feature = "stats"))]
+pub use tcp::TcpStats;
+#[cfg(all(feature = "socket-udp", feature = "stats"))]
+pub use udp::UdpStats;
 
 #[cfg(feature = "socket-udp")]
-pub use udp::{State as UdpState, UdpSocket};
+pub use udp::{
+    State as UdpState, TruncationPolicy, UdpDestination, UdpMetadata, UdpSocket, UdpSocketConfig,
+};
 
-pub use self::set::{Handle as SocketHandle, Set as SocketSet};
+pub use self::set::{
+    Handle as SocketHandle, PollAction, RemovalReason, Set as SocketSet, SetEvent, MAX_SET_EVENTS,
+};
 
 pub use self::ref_::Ref as SocketRef;
 
+pub use self::fmt::MAX_LOGS_PER_CYCLE;
+
+/// Mark the start of a new poll cycle of the driver.
+///
+/// Once this has been called, at most [`MAX_LOGS_PER_CYCLE`] log lines below
+/// error level are emitted by this crate per cycle. If any were suppressed in
+/// the previous cycle, a single summary line is logged instead. Drivers that
+/// never call this are not throttled.
+pub fn new_poll_cycle() {
+    let suppressed = fmt::governor().new_cycle();
+    if suppressed > 0 {
+        #[cfg(feature = "log")]
+        ::log::info!("{} further socket logs suppressed", suppressed);
+        #[cfg(feature = "defmt")]
+        ::defmt::info!("{} further socket logs suppressed", suppressed);
+    }
+}
+
 /// The error type for the networking stack.
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -52,8 +101,97 @@ pub enum Error {
     Timeout,
 }
 
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Error::Exhausted => "buffer exhausted",
+            Error::Illegal => "operation not permitted in the current state",
+            Error::Unaddressable => "endpoint unaddressable",
+            Error::SocketClosed => "socket closed",
+            Error::BadLength => "bad length",
+            Error::NotBound => "socket not bound",
+            Error::ListenerError => "listener error",
+            Error::SocketSetFull => "socket set full",
+            Error::InvalidSocket => "invalid socket",
+            Error::DuplicateSocket => "duplicate socket",
+            Error::Timeout => "timed out",
+        })
+    }
+}
+
+impl core::error::Error for Error {}
+
 type Result<T> = core::result::Result<T, Error>;
 
+/// Returned when received data did not fully fit in a socket's receive buffer.
+///
+/// Converts into [`Error::Exhausted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxOverflow {
+    /// The number of bytes that did fit, and were enqueued.
+    pub stored: usize,
+    /// The number of bytes that were dropped.
+    pub dropped: usize,
+}
+
+impl From<RxOverflow> for Error {
+    fn from(_: RxOverflow) -> Self {
+        Error::Exhausted
+    }
+}
+
+/// The error type of [`TcpListener`](tcp_listener::TcpListener) and
+/// [`UdpListener`](udp_listener::UdpListener).
+///
+/// Converts into [`Error::ListenerError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ListenerError {
+    /// The handle is not bound to a port.
+    NotBound,
+    /// The handle is already bound to a port.
+    AlreadyBound,
+    /// The port is already bound, by another handle.
+    PortInUse,
+    /// The listener cannot hold more bindings, or more peers on the port.
+    Full,
+    /// No incoming connection is pending yet; try again later.
+    WouldBlock,
+    /// The queue of incoming connections is full, so the connection was
+    /// dropped.
+    BacklogFull,
+    /// The peer was refused by the filter of the port, so the connection was
+    /// dropped.
+    Rejected,
+    /// The backlog is 0, or larger than the listener can hold.
+    InvalidBacklog,
+}
+
+impl From<ListenerError> for Error {
+    fn from(_: ListenerError) -> Self {
+        Error::ListenerError
+    }
+}
+
+/// Statistics of a bound listener port.
+#[cfg(feature = "stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ListenerStats {
+    /// Incoming connections taken by the application.
+    pub accepted: u32,
+    /// Incoming connections refused, by a peer filter or explicitly.
+    pub rejected: u32,
+    /// Incoming connections dropped, as the queue was full.
+    pub dropped: u32,
+    /// Pending connections dropped, as they were queued for too long; only
+    /// a TCP listener expires them.
+    pub expired: u32,
+    /// Largest number of connections queued at once.
+    pub peak_backlog: usize,
+}
+
 /// A network socket.
 ///
 /// This enumeration abstracts the various types of sockets based on the IP protocol.
@@ -66,11 +204,12 @@ type Result<T> = core::result::Result<T, Error>;
 /// [SocketSet::get]: struct.SocketSet.html#method.get
 #[non_exhaustive]
 #[derive(Debug)]
-pub enum Socket<const L: usize> {
+#[allow(clippy::large_enum_variant)]
+pub enum Socket<const L: usize, const TXL: usize = L> {
     #[cfg(feature = "socket-udp")]
-    Udp(UdpSocket<L>),
+    Udp(UdpSocket<L, TXL>),
     #[cfg(feature = "socket-tcp")]
-    Tcp(TcpSocket<L>),
+    Tcp(TcpSocket<L, TXL>),
 }
 
 #[non_exhaustive]
@@ -81,13 +220,136 @@ pub enum SocketType {
     Tcp,
 }
 
-impl<const L: usize> Socket<L> {
+/// The reason a connection was closed.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CloseReason {
+    /// The socket was closed by the remote end, or by the module on its behalf.
+    RemoteFin,
+    /// The socket was shut down by the application.
+    LocalClose,
+    /// The connection attempt timed out.
+    ConnectTimeout,
+    /// The maximum number of connection attempts failed.
+    ConnectFailed,
+    /// The socket was recycled after its read timeout expired.
+    ReadTimeout,
+    /// The connection was recycled after going without traffic for too long.
+    IdleTimeout,
+}
+
+/// A simplified view of a socket's connection state, for application code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConnectionStatus {
+    /// The connection is being established.
+    Connecting,
+    /// The socket can exchange data with its remote endpoint.
+    Connected,
+    /// The socket is not connected, optionally with the reason it was closed.
+    Disconnected { reason: Option<CloseReason> },
+}
+
+/// A step of tearing down a socket set or listener, in the order performed.
+///
+/// Drivers owning both a [`SocketSet`] and listeners should tear down the set
+/// first, so that sockets can still be looked up in the listeners while their
+/// teardown events are handled; [`teardown`] does so for a whole stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TeardownEvent {
+    /// A socket was removed from its set.
+    Socket {
+        handle: SocketHandle,
+        ty: SocketType,
+    },
+    /// A pending incoming connection was dropped from a listener.
+    Pending {
+        listener: SocketHandle,
+        handle: SocketHandle,
+    },
+    /// A peer mapping of a UDP listener was dropped, so its connection socket
+    /// is no longer in use; see [`UdpListener::insert_peer`].
+    Peer {
+        listener: SocketHandle,
+        handle: SocketHandle,
+    },
+    /// A listener binding was removed.
+    Listener { handle: SocketHandle, port: u16 },
+}
+
+/// Tear down a socket set and its listeners, calling `f` for every socket of
+/// the set, then for every pending connection and peer of the listeners, and
+/// finally for every listener binding.
+///
+/// The listeners still hold their bindings while the socket and connection
+/// events are handled, so a socket can be told apart from a server socket.
+/// `f` should neither allocate nor panic.
+#[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+pub fn teardown<
+    const N: usize,
+    const L: usize,
+    const TXL: usize,
+    const TN: usize,
+    const TL: usize,
+    const TP: usize,
+    const UN: usize,
+    const UL: usize,
+    const UP: usize,
+    F,
+>(
+    set: SocketSet<N, L, TXL>,
+    mut tcp: TcpListener<TN, TL, TP>,
+    mut udp: UdpListener<UN, UL, UP>,
+    mut f: F,
+) where
+    F: FnMut(TeardownEvent),
+{
+    set.teardown(&mut f);
+    tcp.drop_pending(&mut f);
+    udp.drop_connections(&mut f);
+    tcp.drop_bindings(&mut f);
+    udp.drop_bindings(&mut f);
+}
+
+impl<const L: usize, const TXL: usize> Socket<L, TXL> {
     /// Return the socket handle.
     #[inline]
     pub fn handle(&self) -> SocketHandle {
         self.meta().handle
     }
 
+    /// Change the handle of the socket, and its module id to match; see
+    /// [`Set::rebind_handle`](crate::SocketSet::rebind_handle) for sockets in a
+    /// set.
+    #[deprecated(note = "the set assigns handles; use `set_module_id` to follow the module's id")]
+    pub fn update_handle(&mut self, handle: SocketHandle) {
+        self.set_handle(handle);
+        self.set_module_id(Some(handle.id()));
+    }
+
+    /// Change the handle of the socket, leaving its module id as is.
+    pub(crate) fn set_handle(&mut self, handle: SocketHandle) {
+        self.meta_mut().update(handle);
+    }
+
+    /// Return the socket id the module assigned to this socket, if any.
+    pub fn module_id(&self) -> Option<u8> {
+        self.meta().module_id
+    }
+
+    /// Set the socket id the module assigned to this socket; see
+    /// [`TcpSocket::set_module_id`].
+    pub fn set_module_id(&mut self, module_id: Option<u8>) {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.set_module_id(module_id),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.set_module_id(module_id),
+        }
+    }
+
     pub(crate) fn meta(&self) -> &SocketMeta {
         match self {
             #[cfg(feature = "socket-udp")]
@@ -97,112 +359,327 @@ impl<const L: usize> Socket<L> {
         }
     }
 
+    fn meta_mut(&mut self) -> &mut SocketMeta {
+        match self {
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(ref mut socket) => &mut socket.meta,
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(ref mut socket) => &mut socket.meta,
+        }
+    }
+
     pub fn get_type(&self) -> SocketType {
         match self {
+            #[cfg(feature = "socket-tcp")]
             Socket::Tcp(_) => SocketType::Tcp,
+            #[cfg(feature = "socket-udp")]
             Socket::Udp(_) => SocketType::Udp,
         }
     }
 
-    pub fn should_update_available_data(&mut self) -> bool {
+    /// Return the connection status of the socket, as seen by the application.
+    pub fn connection_status(&self) -> ConnectionStatus {
         match self {
-            Socket::Tcp(s) => s.should_update_available_data(),
-            Socket::Udp(s) => s.should_update_available_data(),
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.connection_status(),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.connection_status(),
+        }
+    }
+
+    /// Return the IP address of the remote endpoint, if the socket is
+    /// connected to one. A TCP socket connected by hostname has none.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.endpoint().and_then(|endpoint| endpoint.ip()),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.endpoint(),
+        }
+    }
+
+    /// Return the local port the socket is bound to, if known.
+    pub fn local_port(&self) -> Option<u16> {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.local_port(),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.local_port(),
+        }
+    }
+
+    pub fn should_update_available_data(&mut self, ts: Instant) -> bool {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.should_update_available_data(ts),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.should_update_available_data(ts),
+        }
+    }
+
+    pub fn time_until_next_check(&self, ts: Instant) -> Option<Duration> {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.time_until_next_check(ts),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.time_until_next_check(ts),
         }
     }
 
     pub fn available_data(&self) -> usize {
         match self {
+            #[cfg(feature = "socket-tcp")]
             Socket::Tcp(s) => s.get_available_data(),
+            #[cfg(feature = "socket-udp")]
             Socket::Udp(s) => s.get_available_data(),
         }
     }
 
-    pub fn recycle(&self) -> bool {
+    pub fn recycle(&mut self, ts: Instant) -> bool {
         match self {
-            Socket::Tcp(s) => s.recycle(),
-            Socket::Udp(s) => s.recycle(),
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.recycle(ts),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.recycle(ts),
         }
     }
 
-    pub fn closed_by_remote(&mut self) {
+    pub fn closed_by_remote(&mut self, ts: Instant) {
         match self {
-            Socket::Tcp(s) => s.closed_by_remote(),
-            Socket::Udp(s) => s.closed_by_remote(),
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.closed_by_remote(ts),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.closed_by_remote(ts),
         }
     }
 
     pub fn set_available_data(&mut self, available_data: usize) {
         match self {
+            #[cfg(feature = "socket-tcp")]
             Socket::Tcp(s) => s.set_available_data(available_data),
+            #[cfg(feature = "socket-udp")]
             Socket::Udp(s) => s.set_available_data(available_data),
         }
     }
 
+    /// Enqueue received data; for a UDP socket, this is the legacy path
+    /// that does not record datagram boundaries. See
+    /// [`UdpSocket::rx_enqueue_slice`].
     pub fn rx_enqueue_slice(&mut self, data: &[u8]) -> usize {
         match self {
+            #[cfg(feature = "socket-tcp")]
             Socket::Tcp(s) => s.rx_enqueue_slice(data),
+            #[cfg(feature = "socket-udp")]
             Socket::Udp(s) => s.rx_enqueue_slice(data),
         }
     }
 
+    /// Enqueue a datagram received from `remote`. A TCP socket has no
+    /// datagram boundaries, so this is the same as
+    /// [`rx_enqueue_slice`](#method.rx_enqueue_slice) there.
+    pub fn rx_enqueue_datagram(&mut self, data: &[u8], remote: SocketAddr) -> usize {
+        #[cfg(not(feature = "socket-udp"))]
+        let _ = remote;
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.rx_enqueue_slice(data),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.rx_enqueue_datagram(data, remote),
+        }
+    }
+
+    /// Enqueue received data, with the sender if it is known.
+    ///
+    /// A UDP socket stores the data as a datagram when `remote` is given,
+    /// and through the legacy stream path otherwise. A TCP socket ignores
+    /// `remote`.
+    pub fn rx_enqueue(&mut self, data: &[u8], remote: Option<SocketAddr>) -> usize {
+        match (self, remote) {
+            #[cfg(feature = "socket-tcp")]
+            (Socket::Tcp(s), _) => s.rx_enqueue_slice(data),
+            #[cfg(feature = "socket-udp")]
+            (Socket::Udp(s), Some(remote)) => s.rx_enqueue_datagram(data, remote),
+            #[cfg(feature = "socket-udp")]
+            (Socket::Udp(s), None) => s.rx_enqueue_slice(data),
+        }
+    }
+
+    pub fn rx_enqueue_slice_checked(
+        &mut self,
+        data: &[u8],
+    ) -> core::result::Result<usize, RxOverflow> {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.rx_enqueue_slice_checked(data),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.rx_enqueue_slice_checked(data),
+        }
+    }
+
+    pub fn take_dropped_bytes(&mut self) -> usize {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.take_dropped_bytes(),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.take_dropped_bytes(),
+        }
+    }
+
+    pub fn max_read_len(&self, at_chunk_limit: usize) -> usize {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.max_read_len(at_chunk_limit),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.max_read_len(at_chunk_limit),
+        }
+    }
+
     pub fn rx_window(&self) -> usize {
         match self {
+            #[cfg(feature = "socket-tcp")]
             Socket::Tcp(s) => s.rx_window(),
+            #[cfg(feature = "socket-udp")]
             Socket::Udp(s) => s.rx_window(),
         }
     }
 
     pub fn can_recv(&self) -> bool {
         match self {
+            #[cfg(feature = "socket-tcp")]
             Socket::Tcp(s) => s.can_recv(),
+            #[cfg(feature = "socket-udp")]
             Socket::Udp(s) => s.can_recv(),
         }
     }
+
+    /// Check whether a payload of `len` bytes would be received whole.
+    ///
+    /// For a UDP socket, this includes the room needed for the datagram's
+    /// metadata, see [`UdpSocket::can_recv_bytes`].
+    pub fn can_recv_bytes(&self, len: usize) -> bool {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.can_recv_bytes(len),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.can_recv_bytes(len),
+        }
+    }
+
+    pub fn may_send(&self) -> bool {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.may_send(),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.may_send(),
+        }
+    }
+
+    /// Check whether data can be queued to be sent now; see
+    /// [`TcpSocket::can_send`] and [`UdpSocket::can_send`].
+    pub fn can_send(&self) -> bool {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.can_send(),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.can_send(),
+        }
+    }
+
+    /// Return the amount of octets queued in the transmit buffer.
+    pub fn send_queue(&self) -> usize {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.send_queue(),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.send_queue(),
+        }
+    }
+
+    /// Return whether queued data should be written to the module now.
+    ///
+    /// See [`TcpSocket::should_flush_tx`]; a UDP socket is flushed whenever
+    /// it may send and has a datagram queued.
+    pub fn should_flush_tx(&mut self, ts: Instant) -> bool {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.should_flush_tx(ts),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => {
+                let _ = ts;
+                s.may_send() && s.send_queue() > 0
+            }
+        }
+    }
+
+    /// Register a waker to be woken when data is received.
+    pub fn register_rx_waker(&mut self, waker: &core::task::Waker) {
+        match self {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(s) => s.register_rx_waker(waker),
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(s) => s.register_rx_waker(waker),
+        }
+    }
 }
 
 /// A conversion trait for network sockets.
-pub trait AnySocket<const L: usize>: Sized {
-    fn downcast(socket_ref: SocketRef<'_, Socket<L>>) -> Result<SocketRef<'_, Self>>;
+pub trait AnySocket<const L: usize, const TXL: usize = L>: Sized {
+    fn downcast(socket_ref: SocketRef<'_, Socket<L, TXL>>) -> Result<SocketRef<'_, Self>>;
+    fn downcast_ref(socket: &Socket<L, TXL>) -> Option<&Self>;
 }
 
 #[cfg(feature = "socket-tcp")]
-impl<const L: usize> AnySocket<L> for TcpSocket<L> {
-    fn downcast(ref_: SocketRef<'_, Socket<L>>) -> Result<SocketRef<'_, Self>> {
+impl<const L: usize, const TXL: usize> AnySocket<L, TXL> for TcpSocket<L, TXL> {
+    #[allow(unreachable_patterns)]
+    fn downcast(ref_: SocketRef<'_, Socket<L, TXL>>) -> Result<SocketRef<'_, Self>> {
         match SocketRef::into_inner(ref_) {
             Socket::Tcp(ref mut socket) => Ok(SocketRef::new(socket)),
             _ => Err(Error::Illegal),
         }
     }
+
+    #[allow(unreachable_patterns)]
+    fn downcast_ref(socket: &Socket<L, TXL>) -> Option<&Self> {
+        match socket {
+            Socket::Tcp(socket) => Some(socket),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(feature = "socket-udp")]
-impl<const L: usize> AnySocket<L> for UdpSocket<L> {
-    fn downcast(ref_: SocketRef<'_, Socket<L>>) -> Result<SocketRef<'_, Self>> {
+impl<const L: usize, const TXL: usize> AnySocket<L, TXL> for UdpSocket<L, TXL> {
+    #[allow(unreachable_patterns)]
+    fn downcast(ref_: SocketRef<'_, Socket<L, TXL>>) -> Result<SocketRef<'_, Self>> {
         match SocketRef::into_inner(ref_) {
             Socket::Udp(ref mut socket) => Ok(SocketRef::new(socket)),
             _ => Err(Error::Illegal),
         }
     }
+
+    #[allow(unreachable_patterns)]
+    fn downcast_ref(socket: &Socket<L, TXL>) -> Option<&Self> {
+        match socket {
+            Socket::Udp(socket) => Some(socket),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
 #[cfg(feature = "defmt")]
 mod test_helpers {
-    use core::ptr::NonNull;
-
     #[defmt::global_logger]
     struct Logger;
-    impl defmt::Write for Logger {
-        fn write(&mut self, _bytes: &[u8]) {}
-    }
 
     unsafe impl defmt::Logger for Logger {
-        fn acquire() -> Option<NonNull<dyn defmt::Write>> {
-            Some(NonNull::from(&Logger as &dyn defmt::Write))
-        }
+        fn acquire() {}
+
+        unsafe fn flush() {}
 
-        unsafe fn release(_: NonNull<dyn defmt::Write>) {}
+        unsafe fn release() {}
+
+        unsafe fn write(_bytes: &[u8]) {}
     }
 
     defmt::timestamp!("");
@@ -212,3 +689,147 @@ mod test_helpers {
         panic!()
     }
 }
+
+/// A logger capturing the lines of the `log` backend, for tests.
+#[cfg(all(test, feature = "log"))]
+pub(crate) mod test_log {
+    use std::string::{String, ToString};
+    use std::sync::{Mutex, Once};
+    use std::thread::{self, ThreadId};
+    use std::vec::Vec;
+
+    static LINES: Mutex<Vec<(ThreadId, String)>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LINES
+                .lock()
+                .unwrap()
+                .push((thread::current().id(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Install the capturing logger, if not installed yet.
+    pub(crate) fn install() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    /// Return the lines logged so far by the calling thread, as tests log
+    /// concurrently.
+    pub(crate) fn lines() -> Vec<String> {
+        let id = thread::current().id();
+        LINES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(thread, _)| *thread == id)
+            .map(|(_, line)| line.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn error_display() {
+        for (error, message) in [
+            (Error::Exhausted, "buffer exhausted"),
+            (
+                Error::Illegal,
+                "operation not permitted in the current state",
+            ),
+            (Error::Unaddressable, "endpoint unaddressable"),
+            (Error::SocketClosed, "socket closed"),
+            (Error::BadLength, "bad length"),
+            (Error::NotBound, "socket not bound"),
+            (Error::ListenerError, "listener error"),
+            (Error::SocketSetFull, "socket set full"),
+            (Error::InvalidSocket, "invalid socket"),
+            (Error::DuplicateSocket, "duplicate socket"),
+            (Error::Timeout, "timed out"),
+        ] {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn teardown_stack_order() {
+        let peer = |port| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), port));
+        let ts = Instant::from_secs(0);
+
+        let mut set = SocketSet::<4, 64>::new();
+        let tcp_server = set.add(TcpSocket::new(0)).unwrap();
+        let udp_server = set.add(UdpSocket::new(1)).unwrap();
+        let udp_peer = set.add(UdpSocket::new(2)).unwrap();
+
+        let mut tcp = TcpListener::<2, 2>::new();
+        tcp.bind_port(tcp_server, 80, 2).unwrap();
+        tcp.push_incoming(80, SocketHandle::new(3), peer(4000), ts)
+            .unwrap();
+
+        let mut udp = UdpListener::<2, 4>::new();
+        udp.bind_port(udp_server, 5000).unwrap();
+        udp.incoming(5000)
+            .unwrap()
+            .enqueue((SocketHandle::new(4), peer(4001)))
+            .unwrap();
+        udp.insert_peer(5000, peer(4002), udp_peer).unwrap();
+
+        let mut events = std::vec::Vec::new();
+        teardown(set, tcp, udp, |event| events.push(event));
+
+        assert_eq!(
+            events,
+            [
+                TeardownEvent::Socket {
+                    handle: tcp_server,
+                    ty: SocketType::Tcp
+                },
+                TeardownEvent::Socket {
+                    handle: udp_server,
+                    ty: SocketType::Udp
+                },
+                TeardownEvent::Socket {
+                    handle: udp_peer,
+                    ty: SocketType::Udp
+                },
+                TeardownEvent::Pending {
+                    listener: tcp_server,
+                    handle: SocketHandle::new(3)
+                },
+                TeardownEvent::Pending {
+                    listener: udp_server,
+                    handle: SocketHandle::new(4)
+                },
+                TeardownEvent::Peer {
+                    listener: udp_server,
+                    handle: udp_peer
+                },
+                TeardownEvent::Listener {
+                    handle: tcp_server,
+                    port: 80
+                },
+                TeardownEvent::Listener {
+                    handle: udp_server,
+                    port: 5000
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use hash32::Hash;
+use heapless::FnvIndexMap;
+use no_std_net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+/// A local address to look up a binding of a listener by.
+///
+/// A bare port matches the wildcard binding of the port, i.e. the one to the
+/// unspecified address, or else the only binding of the port. If a port has
+/// several bindings to specific addresses and no wildcard binding, a bare
+/// port is ambiguous and matches none of them.
+///
+/// A full address matches the binding to exactly that address, or else the
+/// wildcard binding of its port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalAddr {
+    Port(u16),
+    Addr(SocketAddr),
+}
+
+impl From<u16> for LocalAddr {
+    fn from(port: u16) -> Self {
+        LocalAddr::Port(port)
+    }
+}
+
+impl From<SocketAddr> for LocalAddr {
+    fn from(addr: SocketAddr) -> Self {
+        LocalAddr::Addr(addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SocketAddrWrapper(pub(crate) SocketAddr);
+
+impl SocketAddrWrapper {
+    /// The wildcard address of `port`.
+    pub(crate) fn any(port: u16) -> Self {
+        Self(SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::new(0, 0, 0, 0),
+            port,
+        )))
+    }
+
+    fn is_wildcard(&self) -> bool {
+        self.0.ip().is_unspecified()
+    }
+}
+
+impl Hash for SocketAddrWrapper {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: hash32::Hasher,
+    {
+        match self.0 {
+            SocketAddr::V4(ip) => {
+                ip.ip().octets().hash(state);
+                ip.port().hash(state);
+            }
+            SocketAddr::V6(ip) => {
+                ip.ip().octets().hash(state);
+                ip.port().hash(state);
+            }
+        }
+    }
+}
+
+/// Find the binding in `bound` that `local` matches, see [`LocalAddr`].
+pub(crate) fn resolve<V, const N: usize>(
+    bound: &FnvIndexMap<SocketAddrWrapper, V, N>,
+    local: LocalAddr,
+) -> Option<SocketAddrWrapper> {
+    let port = match local {
+        LocalAddr::Addr(addr) => {
+            let key = SocketAddrWrapper(addr);
+            if bound.contains_key(&key) {
+                return Some(key);
+            }
+            addr.port()
+        }
+        LocalAddr::Port(port) => port,
+    };
+
+    if let Some(wildcard) = bound
+        .keys()
+        .find(|key| key.0.port() == port && key.is_wildcard())
+    {
+        return Some(*wildcard);
+    }
+    let mut on_port = bound.keys().filter(|key| key.0.port() == port);
+    match local {
+        LocalAddr::Port(_) => match (on_port.next(), on_port.next()) {
+            (Some(only), None) => Some(*only),
+            _ => None,
+        },
+        LocalAddr::Addr(_) => None,
+    }
+}
+
+/// Returns true if binding `addr` would clash with a binding in `bound`: the
+/// same address, or a second wildcard binding of the port.
+pub(crate) fn conflicts<V, const N: usize>(
+    bound: &FnvIndexMap<SocketAddrWrapper, V, N>,
+    addr: SocketAddr,
+) -> bool {
+    let addr = SocketAddrWrapper(addr);
+    bound.keys().any(|key| {
+        *key == addr || (key.0.port() == addr.0.port() && key.is_wildcard() && addr.is_wildcard())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8, port: u16) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, last), port))
+    }
+
+    #[test]
+    fn resolve_bindings() {
+        let mut bound = FnvIndexMap::<SocketAddrWrapper, (), 4>::new();
+        bound.insert(SocketAddrWrapper(addr(1, 80)), ()).unwrap();
+        bound.insert(SocketAddrWrapper(addr(2, 80)), ()).unwrap();
+        bound.insert(SocketAddrWrapper(addr(1, 443)), ()).unwrap();
+
+        // Exact matches
+        assert_eq!(
+            resolve(&bound, addr(2, 80).into()),
+            Some(SocketAddrWrapper(addr(2, 80)))
+        );
+        assert_eq!(resolve(&bound, addr(3, 80).into()), None);
+
+        // A bare port is ambiguous with several specific bindings
+        assert_eq!(resolve(&bound, 80.into()), None);
+        assert_eq!(
+            resolve(&bound, 443.into()),
+            Some(SocketAddrWrapper(addr(1, 443)))
+        );
+        assert_eq!(resolve(&bound, 8080.into()), None);
+
+        // The wildcard binding catches the rest
+        bound.insert(SocketAddrWrapper::any(80), ()).unwrap();
+        assert_eq!(resolve(&bound, 80.into()), Some(SocketAddrWrapper::any(80)));
+        assert_eq!(
+            resolve(&bound, addr(3, 80).into()),
+            Some(SocketAddrWrapper::any(80))
+        );
+        assert_eq!(
+            resolve(&bound, addr(1, 80).into()),
+            Some(SocketAddrWrapper(addr(1, 80)))
+        );
+    }
+
+    #[test]
+    fn conflicting_bindings() {
+        let mut bound = FnvIndexMap::<SocketAddrWrapper, (), 4>::new();
+        bound.insert(SocketAddrWrapper(addr(1, 80)), ()).unwrap();
+        bound.insert(SocketAddrWrapper::any(443), ()).unwrap();
+
+        assert!(conflicts(&bound, addr(1, 80)));
+        assert!(!conflicts(&bound, addr(2, 80)));
+        assert!(!conflicts(&bound, SocketAddrWrapper::any(80).0));
+        assert!(!conflicts(&bound, addr(1, 443)));
+        assert!(conflicts(&bound, SocketAddrWrapper::any(443).0));
+        assert!(conflicts(
+            &bound,
+            SocketAddr::new(
+                no_std_net::Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0).into(),
+                443
+            )
+        ));
+    }
+}
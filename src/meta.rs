@@ -9,6 +9,10 @@ pub struct Meta {
     /// Handle of this socket within its enclosing `SocketSet`.
     /// Mainly useful for debug output.
     pub(crate) handle: SocketHandle,
+    /// Socket id the module assigned to this socket, used to route URCs.
+    /// Unlike the handle, this changes when the module reassigns ids, e.g.
+    /// after a reboot.
+    pub(crate) module_id: Option<u8>,
 }
 
 impl Meta {
@@ -0,0 +1,147 @@
+use super::{RingBuffer, SocketHandle};
+use core::cmp;
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+/// Maximum number of separate chunks of data a [`Set`](crate::SocketSet) can
+/// hold for unknown handles.
+const MAX_CHUNKS: usize = 8;
+
+/// What a socket set does with data received for a handle it does not hold.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// Refuse the data with `Error::InvalidSocket`.
+    #[default]
+    Reject,
+    /// Silently discard the data, counting the dropped bytes.
+    Drop,
+    /// Hold up to `max_bytes` of data, and hand it to a socket with a matching
+    /// handle when one is added within `ttl` of the data arriving.
+    ///
+    /// The held data is bounded by `max_bytes` and by the socket buffer size
+    /// of the set, whichever is smaller; the oldest data is evicted first.
+    Hold { max_bytes: usize, ttl: Duration },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    handle: SocketHandle,
+    received: Instant,
+    len: usize,
+}
+
+/// Data held for handles that are not (yet) present in a socket set.
+#[derive(Debug, Default)]
+pub(crate) struct OrphanBuffer<const L: usize> {
+    data: RingBuffer<u8, L>,
+    chunks: Deque<Chunk, MAX_CHUNKS>,
+    dropped: usize,
+}
+
+impl<const L: usize> OrphanBuffer<L> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            data: RingBuffer::new(),
+            chunks: Deque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Return the number of orphaned bytes dropped so far.
+    pub(crate) fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Count `len` bytes as dropped.
+    pub(crate) fn drop_bytes(&mut self, len: usize) {
+        self.dropped = self.dropped.saturating_add(len);
+    }
+
+    /// Hold `data` received for `handle`, evicting older data as needed to
+    /// stay within `max_bytes`. Returns the number of bytes held.
+    pub(crate) fn hold(
+        &mut self,
+        handle: SocketHandle,
+        data: &[u8],
+        ts: Instant,
+        max_bytes: usize,
+    ) -> usize {
+        let max_bytes = cmp::min(max_bytes, L);
+        if data.len() > max_bytes {
+            self.drop_bytes(data.len());
+            return 0;
+        }
+        if data.is_empty() {
+            return 0;
+        }
+
+        while self.data.len() + data.len() > max_bytes || self.chunks.is_full() {
+            self.evict_oldest();
+        }
+
+        self.data.enqueue_slice(data);
+        self.chunks
+            .push_back(Chunk {
+                handle,
+                received: ts,
+                len: data.len(),
+            })
+            .ok();
+        data.len()
+    }
+
+    /// Discard all data that has been held for longer than `ttl`.
+    pub(crate) fn expire(&mut self, ts: Instant, ttl: Duration) {
+        while let Some(chunk) = self.chunks.front() {
+            let expired = ts
+                .checked_duration_since(chunk.received)
+                .map(|age| age >= ttl)
+                .unwrap_or(false);
+            if !expired {
+                break;
+            }
+            self.evict_oldest();
+        }
+    }
+
+    /// Call `f` with all data held for `handle`, in the order it was received,
+    /// and remove it. Data held for other handles is kept in order.
+    pub(crate) fn flush<F>(&mut self, handle: SocketHandle, mut f: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        for _ in 0..self.chunks.len() {
+            let chunk = match self.chunks.pop_front() {
+                Some(chunk) => chunk,
+                None => break,
+            };
+
+            let mut remaining = chunk.len;
+            while remaining > 0 {
+                let mut buf = [0; 32];
+                let len = cmp::min(remaining, buf.len());
+                let len = self.data.dequeue_slice(&mut buf[..len]);
+                if len == 0 {
+                    break;
+                }
+                if chunk.handle == handle {
+                    f(&buf[..len]);
+                } else {
+                    self.data.enqueue_slice(&buf[..len]);
+                }
+                remaining -= len;
+            }
+
+            if chunk.handle != handle {
+                self.chunks.push_back(chunk).ok();
+            }
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(chunk) = self.chunks.pop_front() {
+            self.data.dequeue_allocated(chunk.len);
+            self.drop_bytes(chunk.len);
+        }
+    }
+}
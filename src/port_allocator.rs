@@ -0,0 +1,132 @@
+use core::ops::Range;
+use heapless::Vec;
+
+use crate::ListenerError;
+
+/// Allocator of local ports from an ephemeral range, for client sockets that
+/// need a deterministic source port.
+///
+/// A [`TcpListener`](crate::TcpListener) or [`UdpListener`](crate::UdpListener)
+/// created with `with_port_allocator` owns its allocator, and reserves every
+/// server port it binds in it, so [`allocate`](#method.allocate) never hands
+/// them out. At most `N` ports of the range are tracked at a time.
+#[derive(Debug, Clone)]
+pub struct PortAllocator<const N: usize> {
+    range: Range<u16>,
+    in_use: Vec<u16, N>,
+}
+
+impl<const N: usize> PortAllocator<N> {
+    /// Create an allocator handing out ports from `range`.
+    pub const fn new(range: Range<u16>) -> Self {
+        Self {
+            range,
+            in_use: Vec::new(),
+        }
+    }
+
+    /// Return the range ports are allocated from.
+    pub fn range(&self) -> Range<u16> {
+        self.range.clone()
+    }
+
+    /// Allocate the lowest free port of the range.
+    ///
+    /// Returns `None` if every port of the range is in use, or if `N` ports
+    /// are already tracked.
+    pub fn allocate(&mut self) -> Option<u16> {
+        if self.in_use.is_full() {
+            return None;
+        }
+        let port = self.range.clone().find(|port| !self.is_in_use(*port))?;
+        // Room was checked above.
+        let _ = self.in_use.push(port);
+        Some(port)
+    }
+
+    /// Reserve `port`, so it is not allocated.
+    ///
+    /// Ports outside of the range are never allocated, so reserving them
+    /// always succeeds without tracking them. Returns
+    /// `Err(ListenerError::PortInUse)` if the port is in use, and
+    /// `Err(ListenerError::Full)` if `N` ports are already tracked.
+    pub fn reserve(&mut self, port: u16) -> Result<(), ListenerError> {
+        if !self.range.contains(&port) {
+            return Ok(());
+        }
+        if self.is_in_use(port) {
+            return Err(ListenerError::PortInUse);
+        }
+        self.in_use.push(port).map_err(|_| ListenerError::Full)
+    }
+
+    /// Release `port`, so it can be allocated again.
+    ///
+    /// Releasing a port of the range that is not in use, e.g. releasing it
+    /// twice, returns `Err(ListenerError::NotBound)` and changes nothing.
+    pub fn release(&mut self, port: u16) -> Result<(), ListenerError> {
+        if !self.range.contains(&port) {
+            return Ok(());
+        }
+        let index = self
+            .in_use
+            .iter()
+            .position(|p| *p == port)
+            .ok_or(ListenerError::NotBound)?;
+        self.in_use.swap_remove(index);
+        Ok(())
+    }
+
+    /// Returns true if `port` is allocated or reserved.
+    pub fn is_in_use(&self, port: u16) -> bool {
+        self.in_use.contains(&port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_lowest_free() {
+        let mut ports = PortAllocator::<4>::new(49152..49155);
+        assert_eq!(ports.allocate(), Some(49152));
+        assert_eq!(ports.allocate(), Some(49153));
+        assert_eq!(ports.release(49152), Ok(()));
+        assert_eq!(ports.allocate(), Some(49152));
+    }
+
+    #[test]
+    fn exhaustion() {
+        // The range runs out first
+        let mut ports = PortAllocator::<4>::new(49152..49154);
+        assert_eq!(ports.allocate(), Some(49152));
+        assert_eq!(ports.allocate(), Some(49153));
+        assert_eq!(ports.allocate(), None);
+
+        // The tracked ports run out first
+        let mut ports = PortAllocator::<2>::new(49152..49160);
+        assert_eq!(ports.reserve(49152), Ok(()));
+        assert_eq!(ports.allocate(), Some(49153));
+        assert_eq!(ports.allocate(), None);
+        assert_eq!(ports.reserve(49154), Err(ListenerError::Full));
+    }
+
+    #[test]
+    fn reserve_and_release() {
+        let mut ports = PortAllocator::<4>::new(49152..49155);
+        assert_eq!(ports.reserve(49152), Ok(()));
+        assert_eq!(ports.reserve(49152), Err(ListenerError::PortInUse));
+        assert_eq!(ports.allocate(), Some(49153));
+
+        // Ports outside of the range are not tracked
+        assert_eq!(ports.reserve(80), Ok(()));
+        assert_eq!(ports.reserve(80), Ok(()));
+        assert!(!ports.is_in_use(80));
+        assert_eq!(ports.release(80), Ok(()));
+
+        assert_eq!(ports.release(49152), Ok(()));
+        assert_eq!(ports.release(49152), Err(ListenerError::NotBound));
+        assert!(ports.is_in_use(49153));
+    }
+}
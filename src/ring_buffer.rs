@@ -1,8 +1,87 @@
 use super::{Error, Result};
 use core::cmp;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Range;
 
 use heapless::Vec;
 
+/// Lazily initialized backing storage of a [`RingBuffer`].
+///
+/// Elements are only initialized the first time they are handed out mutably,
+/// so creating a buffer does not have to touch all `N` elements up front.
+/// The initialized elements always form a prefix of the storage.
+struct Storage<T, const N: usize> {
+    elements: [MaybeUninit<T>; N],
+    initialized: usize,
+}
+
+impl<T, const N: usize> Storage<T, N> {
+    const fn new() -> Self {
+        Storage {
+            elements: [const { MaybeUninit::uninit() }; N],
+            initialized: 0,
+        }
+    }
+
+    /// Return the number of leading elements that have been initialized.
+    fn initialized(&self) -> usize {
+        self.initialized
+    }
+
+    /// Initialize every element up to (but not including) `end`.
+    fn init(&mut self, end: usize)
+    where
+        T: Default,
+    {
+        assert!(end <= N);
+        while self.initialized < end {
+            self.elements[self.initialized].write(T::default());
+            self.initialized += 1;
+        }
+    }
+
+    /// Return the elements in `range`, which must already be initialized.
+    ///
+    /// # Panics
+    /// This function panics if `range` reaches past the initialized elements.
+    fn get(&self, range: Range<usize>) -> &[T] {
+        assert!(range.end <= self.initialized);
+        let elements = &self.elements[range];
+        // SAFETY: every element below `self.initialized` has been written, and
+        // `MaybeUninit<T>` has the same layout as `T`.
+        unsafe { &*(elements as *const [MaybeUninit<T>] as *const [T]) }
+    }
+
+    /// Return all initialized elements.
+    #[cfg(test)]
+    fn as_slice(&self) -> &[T] {
+        self.get(0..self.initialized)
+    }
+
+    /// Return the elements in `range`, initializing them first if needed.
+    fn get_mut(&mut self, range: Range<usize>) -> &mut [T]
+    where
+        T: Default,
+    {
+        self.init(range.end);
+        let elements = &mut self.elements[range];
+        // SAFETY: `init` has written every element in `range`, and
+        // `MaybeUninit<T>` has the same layout as `T`.
+        unsafe { &mut *(elements as *mut [MaybeUninit<T>] as *mut [T]) }
+    }
+}
+
+impl<T, const N: usize> Drop for Storage<T, N> {
+    fn drop(&mut self) {
+        for element in &mut self.elements[..self.initialized] {
+            // SAFETY: every element below `self.initialized` has been written,
+            // and is dropped exactly once here.
+            unsafe { element.assume_init_drop() };
+        }
+    }
+}
+
 /// A ring buffer.
 ///
 /// This ring buffer implementation provides many ways to interact with it:
@@ -13,22 +92,39 @@ use heapless::Vec;
 ///
 /// This implementation is suitable for both simple uses such as a FIFO queue
 /// of UDP packets, and advanced ones such as a TCP reassembly buffer.
-#[derive(Debug, Default)]
+///
+/// The storage is initialized lazily, the first time each element is written
+/// or handed out mutably, so creating a buffer is cheap regardless of `N`.
+/// Elements that have never been written are never exposed.
 pub struct RingBuffer<T, const N: usize> {
-    storage: Vec<T, N>,
+    storage: Storage<T, N>,
     read_at: usize,
     length: usize,
 }
 
+impl<T: Default + Clone, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> fmt::Debug for RingBuffer<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RingBuffer")
+            .field("capacity", &N)
+            .field("read_at", &self.read_at)
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
 impl<T: Default + Clone, const N: usize> RingBuffer<T, N> {
-    /// Create a ring buffer with the given storage.
+    /// Create an empty ring buffer.
     ///
-    /// During creation, every element in `storage` is reset.
-    pub fn new() -> RingBuffer<T, N> {
-        let mut storage = Vec::new();
-        storage.resize_default(N).ok();
+    /// This does not initialize any of the underlying storage.
+    pub const fn new() -> RingBuffer<T, N> {
         RingBuffer {
-            storage,
+            storage: Storage::new(),
             read_at: 0,
             length: 0,
         }
@@ -53,7 +149,7 @@ impl<T: Default + Clone, const N: usize> RingBuffer<T, N> {
 
     /// Return the maximum number of elements in the ring buffer.
     pub fn capacity(&self) -> usize {
-        self.storage.capacity()
+        N
     }
 
     /// Return the current number of elements in the ring buffer.
@@ -114,7 +210,7 @@ impl<T: Default + Clone, const N: usize> RingBuffer<T, N> {
         }
 
         let index = self.get_idx_unchecked(self.length);
-        match f(&mut self.storage[index]) {
+        match f(&mut self.storage.get_mut(index..index + 1)[0]) {
             Ok(result) => {
                 self.length += 1;
                 Ok(result)
@@ -142,7 +238,7 @@ impl<T: Default + Clone, const N: usize> RingBuffer<T, N> {
         }
 
         let next_at = self.get_idx_unchecked(1);
-        match f(&mut self.storage[self.read_at]) {
+        match f(&mut self.storage.get_mut(self.read_at..self.read_at + 1)[0]) {
             Ok(result) => {
                 self.length -= 1;
                 self.read_at = next_at;
@@ -171,6 +267,16 @@ impl<T: Default + core::fmt::Debug + Clone, const N: usize> RingBuffer<T, N> {
     /// This function panics if the amount of elements returned by `f` is larger
     /// than the size of the slice passed into it.
     pub fn enqueue_many_with<'b, R, F>(&'b mut self, f: F) -> (usize, R)
+    where
+        F: FnOnce(&'b mut [T]) -> (usize, R),
+    {
+        self.enqueue_at_most_with(usize::MAX, f)
+    }
+
+    /// Like [`enqueue_many_with`](#method.enqueue_many_with), but never hands
+    /// out more than `limit` elements, so no more storage than needed is
+    /// initialized.
+    fn enqueue_at_most_with<'b, R, F>(&'b mut self, limit: usize, f: F) -> (usize, R)
     where
         F: FnOnce(&'b mut [T]) -> (usize, R),
     {
@@ -181,8 +287,8 @@ impl<T: Default + core::fmt::Debug + Clone, const N: usize> RingBuffer<T, N> {
         }
 
         let write_at = self.get_idx(self.length);
-        let max_size = self.contiguous_window();
-        let (size, result) = f(&mut self.storage[write_at..write_at + max_size]);
+        let max_size = cmp::min(self.contiguous_window(), limit);
+        let (size, result) = f(self.storage.get_mut(write_at..write_at + max_size));
         assert!(size <= max_size);
         self.length += size;
         (size, result)
@@ -194,7 +300,7 @@ impl<T: Default + core::fmt::Debug + Clone, const N: usize> RingBuffer<T, N> {
     /// This function may return a slice smaller than the given size
     /// if the free space in the buffer is not contiguous.
     pub fn enqueue_many(&mut self, size: usize) -> &mut [T] {
-        self.enqueue_many_with(|buf| {
+        self.enqueue_at_most_with(size, |buf| {
             let size = cmp::min(size, buf.len());
             (size, &mut buf[..size])
         })
@@ -207,12 +313,12 @@ impl<T: Default + core::fmt::Debug + Clone, const N: usize> RingBuffer<T, N> {
     where
         T: Copy,
     {
-        let (size_1, data) = self.enqueue_many_with(|buf| {
+        let (size_1, data) = self.enqueue_at_most_with(data.len(), |buf| {
             let size = cmp::min(buf.len(), data.len());
             buf[..size].copy_from_slice(&data[..size]);
             (size, &data[size..])
         });
-        let (size_2, ()) = self.enqueue_many_with(|buf| {
+        let (size_2, ()) = self.enqueue_at_most_with(data.len(), |buf| {
             let size = cmp::min(buf.len(), data.len());
             buf[..size].copy_from_slice(&data[..size]);
             (size, ())
@@ -232,7 +338,7 @@ impl<T: Default + core::fmt::Debug + Clone, const N: usize> RingBuffer<T, N> {
     {
         let capacity = self.capacity();
         let max_size = cmp::min(self.len(), capacity - self.read_at);
-        let (size, result) = f(&mut self.storage[self.read_at..self.read_at + max_size]);
+        let (size, result) = f(self.storage.get_mut(self.read_at..self.read_at + max_size));
         assert!(size <= max_size);
         self.read_at = if capacity > 0 {
             (self.read_at + size) % capacity
@@ -250,13 +356,14 @@ impl<T: Default + core::fmt::Debug + Clone, const N: usize> RingBuffer<T, N> {
         let capacity = self.capacity();
         let size1 = cmp::min(self.len(), capacity - self.read_at);
         let size2 = self.len() - size1;
+        self.storage.init(self.read_at + size1);
         let (size, result) = if size2 != 0 {
             f(
-                &self.storage[self.read_at..self.read_at + size1],
-                Some(&self.storage[..size2]),
+                self.storage.get(self.read_at..self.read_at + size1),
+                Some(self.storage.get(0..size2)),
             )
         } else {
-            f(&self.storage[self.read_at..self.read_at + size1], None)
+            f(self.storage.get(self.read_at..self.read_at + size1), None)
         };
 
         assert!(size <= size1 + size2);
@@ -300,6 +407,24 @@ impl<T: Default + core::fmt::Debug + Clone, const N: usize> RingBuffer<T, N> {
         });
         size_1 + size_2
     }
+
+    /// Dequeue as many elements from the buffer as fit into the remaining
+    /// capacity of the given vector, append them, and return the amount of
+    /// elements appended.
+    pub fn dequeue_into_vec<const M: usize>(&mut self, out: &mut Vec<T, M>) -> usize
+    where
+        T: Copy,
+    {
+        let mut append = |buf: &mut [T]| {
+            let size = cmp::min(buf.len(), out.capacity() - out.len());
+            // Cannot fail; `size` is bounded by the remaining capacity
+            out.extend_from_slice(&buf[..size]).ok();
+            (size, size)
+        };
+        let (_, size_1) = self.dequeue_many_with(&mut append);
+        let (_, size_2) = self.dequeue_many_with(&mut append);
+        size_1 + size_2
+    }
 }
 
 /// This is the "random access" ring buffer interface: it operates with element slices,
@@ -324,7 +449,7 @@ impl<T: Default + Clone, const N: usize> RingBuffer<T, N> {
             size = until_end
         }
 
-        &mut self.storage[start_at..start_at + size]
+        self.storage.get_mut(start_at..start_at + size)
     }
 
     /// Write as many elements from the given slice into unallocated buffer elements
@@ -355,6 +480,11 @@ impl<T: Default + Clone, const N: usize> RingBuffer<T, N> {
     /// Panics if the number of elements given exceeds the number of unallocated elements.
     pub fn enqueue_unallocated(&mut self, count: usize) {
         assert!(count <= self.window());
+        // Elements that were never written must still be initialized before
+        // they become readable.
+        let write_at = self.get_idx(self.length);
+        self.storage
+            .init(cmp::min(write_at + count, self.capacity()));
         self.length += count;
     }
 
@@ -371,13 +501,14 @@ impl<T: Default + Clone, const N: usize> RingBuffer<T, N> {
         if size > clamped_length {
             size = clamped_length
         }
-        // We can't contiguously dequeue past the end of the storage.
-        let until_end = self.capacity() - start_at;
+        // We can't contiguously dequeue past the end of the storage, nor
+        // expose elements that were never written.
+        let until_end = self.storage.initialized().saturating_sub(start_at);
         if size > until_end {
             size = until_end
         }
 
-        &self.storage[start_at..start_at + size]
+        self.storage.get(start_at..start_at + size)
     }
 
     /// Read as many elements from allocated buffer elements into the given slice
@@ -453,12 +584,16 @@ mod tests {
             Err(Error::Exhausted)
         );
 
-        ring.enqueue_one_with(|e| Ok(e)).unwrap();
+        ring.enqueue_one_with(Ok).unwrap();
         assert!(!ring.is_empty());
         assert!(!ring.is_full());
 
         for i in 1..5 {
-            ring.enqueue_one_with(|e| Ok(*e = i)).unwrap();
+            ring.enqueue_one_with(|e| {
+                *e = i;
+                Ok(())
+            })
+            .unwrap();
             assert!(!ring.is_empty());
         }
         assert!(ring.is_full());
@@ -516,7 +651,7 @@ mod tests {
             (2, true)
         );
         assert_eq!(ring.len(), 2);
-        assert_eq!(&ring.storage[..], b"ab..........");
+        assert_eq!(ring.storage.as_slice(), b"ab..........");
 
         ring.enqueue_many_with(|buf| {
             assert_eq!(buf.len(), 12 - 2);
@@ -524,7 +659,7 @@ mod tests {
             (2, ())
         });
         assert_eq!(ring.len(), 4);
-        assert_eq!(&ring.storage[..], b"abcdXX......");
+        assert_eq!(ring.storage.as_slice(), b"abcdXX......");
 
         ring.enqueue_many_with(|buf| {
             assert_eq!(buf.len(), 12 - 4);
@@ -532,13 +667,13 @@ mod tests {
             (4, ())
         });
         assert_eq!(ring.len(), 8);
-        assert_eq!(&ring.storage[..], b"abcdefgh....");
+        assert_eq!(ring.storage.as_slice(), b"abcdefgh....");
 
         for _ in 0..4 {
             *ring.dequeue_one().unwrap() = b'.';
         }
         assert_eq!(ring.len(), 4);
-        assert_eq!(&ring.storage[..], b"....efgh....");
+        assert_eq!(ring.storage.as_slice(), b"....efgh....");
 
         ring.enqueue_many_with(|buf| {
             assert_eq!(buf.len(), 12 - 8);
@@ -546,7 +681,7 @@ mod tests {
             (4, ())
         });
         assert_eq!(ring.len(), 8);
-        assert_eq!(&ring.storage[..], b"....efghijkl");
+        assert_eq!(ring.storage.as_slice(), b"....efghijkl");
 
         ring.enqueue_many_with(|buf| {
             assert_eq!(buf.len(), 4);
@@ -554,13 +689,13 @@ mod tests {
             (4, ())
         });
         assert_eq!(ring.len(), 12);
-        assert_eq!(&ring.storage[..], b"abcdefghijkl");
+        assert_eq!(ring.storage.as_slice(), b"abcdefghijkl");
 
         for _ in 0..4 {
             *ring.dequeue_one().unwrap() = b'.';
         }
         assert_eq!(ring.len(), 8);
-        assert_eq!(&ring.storage[..], b"abcd....ijkl");
+        assert_eq!(ring.storage.as_slice(), b"abcd....ijkl");
     }
 
     #[test]
@@ -569,11 +704,11 @@ mod tests {
 
         ring.enqueue_many(8).copy_from_slice(b"abcdefgh");
         assert_eq!(ring.len(), 8);
-        assert_eq!(&ring.storage[..], b"abcdefgh....");
+        assert_eq!(ring.storage.as_slice(), b"abcdefgh....");
 
         ring.enqueue_many(8).copy_from_slice(b"ijkl");
         assert_eq!(ring.len(), 12);
-        assert_eq!(&ring.storage[..], b"abcdefghijkl");
+        assert_eq!(ring.storage.as_slice(), b"abcdefghijkl");
     }
 
     #[test]
@@ -582,17 +717,17 @@ mod tests {
 
         assert_eq!(ring.enqueue_slice(b"abcdefgh"), 8);
         assert_eq!(ring.len(), 8);
-        assert_eq!(&ring.storage[..], b"abcdefgh....");
+        assert_eq!(ring.storage.as_slice(), b"abcdefgh....");
 
         for _ in 0..4 {
             *ring.dequeue_one().unwrap() = b'.';
         }
         assert_eq!(ring.len(), 4);
-        assert_eq!(&ring.storage[..], b"....efgh....");
+        assert_eq!(ring.storage.as_slice(), b"....efgh....");
 
         assert_eq!(ring.enqueue_slice(b"ijklabcd"), 8);
         assert_eq!(ring.len(), 12);
-        assert_eq!(&ring.storage[..], b"abcdefghijkl");
+        assert_eq!(ring.storage.as_slice(), b"abcdefghijkl");
     }
 
     #[test]
@@ -611,7 +746,7 @@ mod tests {
             (4, true)
         );
         assert_eq!(ring.len(), 8);
-        assert_eq!(&ring.storage[..], b"....efghijkl");
+        assert_eq!(ring.storage.as_slice(), b"....efghijkl");
 
         ring.dequeue_many_with(|buf| {
             assert_eq!(buf, b"efghijkl");
@@ -619,7 +754,7 @@ mod tests {
             (4, ())
         });
         assert_eq!(ring.len(), 4);
-        assert_eq!(&ring.storage[..], b"........ijkl");
+        assert_eq!(ring.storage.as_slice(), b"........ijkl");
 
         assert_eq!(ring.enqueue_slice(b"abcd"), 4);
         assert_eq!(ring.len(), 8);
@@ -635,7 +770,7 @@ mod tests {
             (4, ())
         });
         assert_eq!(ring.len(), 0);
-        assert_eq!(&ring.storage[..], b"............");
+        assert_eq!(ring.storage.as_slice(), b"............");
     }
 
     #[test]
@@ -698,7 +833,7 @@ mod tests {
             buf.copy_from_slice(b"........");
         }
         assert_eq!(ring.len(), 4);
-        assert_eq!(&ring.storage[..], b"........ijkl");
+        assert_eq!(ring.storage.as_slice(), b"........ijkl");
 
         {
             let buf = ring.dequeue_many(8);
@@ -706,7 +841,7 @@ mod tests {
             buf.copy_from_slice(b"....");
         }
         assert_eq!(ring.len(), 0);
-        assert_eq!(&ring.storage[..], b"............");
+        assert_eq!(ring.storage.as_slice(), b"............");
     }
 
     #[test]
@@ -732,6 +867,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_buffer_dequeue_into_vec() {
+        let mut ring: RingBuffer<u8, 12> = RingBuffer::from_slice(&[b'.'; 12]);
+
+        assert_eq!(ring.enqueue_slice(b"abcdefghijkl"), 12);
+
+        {
+            let mut vec: Vec<u8, 8> = Vec::new();
+            vec.push(b'-').unwrap();
+            assert_eq!(ring.dequeue_into_vec(&mut vec), 7);
+            assert_eq!(&vec[..], b"-abcdefg");
+            assert_eq!(ring.dequeue_into_vec(&mut vec), 0);
+            assert_eq!(ring.len(), 5);
+        }
+
+        assert_eq!(ring.enqueue_slice(b"abcd"), 4);
+
+        {
+            let mut vec: Vec<u8, 16> = Vec::new();
+            assert_eq!(ring.dequeue_into_vec(&mut vec), 9);
+            assert_eq!(&vec[..], b"hijklabcd");
+            assert_eq!(ring.len(), 0);
+        }
+    }
+
     #[test]
     fn test_buffer_get_unallocated() {
         let mut ring: RingBuffer<u8, 12> = RingBuffer::from_slice(&[b'.'; 12]);
@@ -742,7 +902,7 @@ mod tests {
             let buf = ring.get_unallocated(0, 4);
             buf.copy_from_slice(b"abcd");
         }
-        assert_eq!(&ring.storage[..], b"abcd........");
+        assert_eq!(ring.storage.as_slice(), b"abcd........");
 
         ring.enqueue_many(4);
         assert_eq!(ring.len(), 4);
@@ -751,18 +911,18 @@ mod tests {
             let buf = ring.get_unallocated(4, 8);
             buf.copy_from_slice(b"ijkl");
         }
-        assert_eq!(&ring.storage[..], b"abcd....ijkl");
+        assert_eq!(ring.storage.as_slice(), b"abcd....ijkl");
 
         ring.enqueue_many(8).copy_from_slice(b"EFGHIJKL");
         ring.dequeue_many(4).copy_from_slice(b"abcd");
         assert_eq!(ring.len(), 8);
-        assert_eq!(&ring.storage[..], b"abcdEFGHIJKL");
+        assert_eq!(ring.storage.as_slice(), b"abcdEFGHIJKL");
 
         {
             let buf = ring.get_unallocated(0, 8);
             buf.copy_from_slice(b"ABCD");
         }
-        assert_eq!(&ring.storage[..], b"ABCDEFGHIJKL");
+        assert_eq!(ring.storage.as_slice(), b"ABCDEFGHIJKL");
     }
 
     #[test]
@@ -849,4 +1009,108 @@ mod tests {
         let large = ring.enqueue_many(8);
         assert_eq!(large.len(), 8);
     }
+
+    #[test]
+    fn test_buffer_fresh_is_uninitialized() {
+        let mut ring: RingBuffer<u8, 12> = RingBuffer::new();
+        assert_eq!(ring.storage.initialized(), 0);
+
+        assert_eq!(ring.get_allocated(0, 12), b"");
+        let mut data = [b'.'; 4];
+        assert_eq!(ring.read_allocated(0, &mut data), 0);
+        assert_eq!(ring.dequeue_slice(&mut data), 0);
+        assert_eq!(ring.dequeue_many(4), b"");
+        assert_eq!(ring.dequeue_one(), Err(Error::Exhausted));
+        assert_eq!(&data, b"....");
+        assert_eq!(ring.storage.initialized(), 0);
+
+        assert_eq!(ring.enqueue_slice(b"abc"), 3);
+        assert_eq!(ring.storage.initialized(), 3);
+        assert_eq!(ring.get_allocated(0, 12), b"abc");
+    }
+
+    #[test]
+    fn test_buffer_lazy_init_unallocated() {
+        let mut ring: RingBuffer<u8, 12> = RingBuffer::new();
+
+        assert_eq!(ring.get_unallocated(2, 3), &[0, 0, 0]);
+        assert_eq!(ring.storage.initialized(), 5);
+
+        // Skipped elements become readable as default values, never as
+        // uninitialized memory.
+        assert_eq!(ring.write_unallocated(4, b"xy"), 2);
+        ring.enqueue_unallocated(8);
+        assert_eq!(ring.storage.initialized(), 8);
+        assert_eq!(ring.get_allocated(0, 12), b"\x00\x00\x00\x00xy\x00\x00");
+    }
+
+    #[test]
+    fn test_buffer_get_allocated_bounded_by_written() {
+        let mut ring: RingBuffer<u8, 12> = RingBuffer::new();
+        ring.enqueue_slice(b"abcd");
+        // Pretend more is allocated than was ever written.
+        ring.length = 8;
+        assert_eq!(ring.get_allocated(0, 8), b"abcd");
+        assert_eq!(ring.get_allocated(4, 4), b"");
+    }
+
+    #[test]
+    fn test_buffer_partial_init_matrix() {
+        for initialized in 0..=8 {
+            for read_at in 0..initialized.max(1) {
+                let mut ring: RingBuffer<u8, 8> = RingBuffer::new();
+                ring.storage.init(initialized);
+                ring.read_at = read_at;
+
+                // Enqueue wraps into the initialized prefix, or extends the
+                // initialized region, depending on `read_at`.
+                assert_eq!(ring.enqueue_one().map(|e| *e = b'a'), Ok(()));
+                let written = 1 + ring.enqueue_slice(b"bcdefg");
+                assert_eq!(written, 7);
+
+                let mut data = [0; 8];
+                assert_eq!(ring.read_allocated(0, &mut data), 7);
+                assert_eq!(&data[..7], b"abcdefg");
+
+                let (size, ()) = ring.dequeue_many_with_wrapping(|a, b| {
+                    let mut joined: Vec<u8, 8> = Vec::new();
+                    joined.extend_from_slice(a).unwrap();
+                    joined.extend_from_slice(b.unwrap_or(&[])).unwrap();
+                    assert_eq!(&joined[..], b"abcdefg");
+                    (2, ())
+                });
+                assert_eq!(size, 2);
+
+                let mut data = [0; 8];
+                assert_eq!(ring.dequeue_slice(&mut data), 5);
+                assert_eq!(&data[..5], b"cdefg");
+                assert!(ring.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_buffer_drops_initialized_elements() {
+        use std::cell::Cell;
+
+        std::thread_local! {
+            static DROPS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        #[derive(Debug, Default, Clone)]
+        struct Counted;
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.with(|drops| drops.set(drops.get() + 1));
+            }
+        }
+
+        {
+            let mut ring: RingBuffer<Counted, 8> = RingBuffer::new();
+            ring.enqueue_many(3);
+            DROPS.with(|drops| drops.set(0));
+        }
+        assert_eq!(DROPS.with(Cell::get), 3);
+    }
 }
@@ -1,40 +1,189 @@
-use super::{AnySocket, Error, Result, Socket, SocketRef, SocketType};
-use atat::atat_derive::AtatLen;
-use heapless::Vec;
-use serde::{Deserialize, Serialize};
+use super::orphan::OrphanBuffer;
+use super::{
+    AnySocket, ConnectionStatus, Error, OrphanPolicy, Result, Socket, SocketRef, SocketType,
+    TeardownEvent,
+};
+use atat::AtatLen;
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// A handle, identifying a socket in a set.
-#[derive(
-    Debug,
-    Clone,
-    Copy,
-    PartialEq,
-    Eq,
-    PartialOrd,
-    AtatLen,
-    Ord,
-    hash32_derive::Hash32,
-    Default,
-    Serialize,
-    Deserialize,
-)]
+///
+/// Besides the socket id used by the module, a handle carries the generation
+/// it was given when its socket was added to a [`Set`]. Socket ids are reused
+/// once a socket is removed, but the generation is not, so a stale handle to
+/// a removed socket does not resolve to a new socket with the same id.
+///
+/// Only the id is serialized, as that is what the module knows; a handle
+/// deserialized from a URC can be resolved with [`Set::resolve`]. Likewise,
+/// converting to and from `u8` and displaying a handle use just the id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Handle {
+    id: u8,
+    generation: u8,
+}
+
+impl Handle {
+    /// Create a handle for the given socket id, of the first generation.
+    pub const fn new(id: u8) -> Self {
+        Handle { id, generation: 0 }
+    }
+
+    /// Return the socket id used by the module.
+    pub const fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Return the generation of the handle.
+    pub const fn generation(&self) -> u8 {
+        self.generation
+    }
+
+    /// Return the raw socket id, as used in AT commands; the same as
+    /// [`id`](#method.id).
+    pub const fn raw(&self) -> u8 {
+        self.id
+    }
+}
+
+impl From<u8> for Handle {
+    fn from(id: u8) -> Self {
+        Handle::new(id)
+    }
+}
+
+impl From<Handle> for u8 {
+    fn from(handle: Handle) -> Self {
+        handle.id
+    }
+}
+
+impl core::fmt::Display for Handle {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Handle {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{=u8}", self.id)
+    }
+}
+
+impl hash32::Hash for Handle {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: hash32::Hasher,
+    {
+        self.id.hash(state);
+        self.generation.hash(state)
+    }
+}
+
+impl AtatLen for Handle {
+    const LEN: usize = <u8 as AtatLen>::LEN;
+}
+
+impl Serialize for Handle {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.id)
+    }
+}
+
+impl<'de> Deserialize<'de> for Handle {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        u8::deserialize(deserializer).map(Handle::new)
+    }
+}
+
+/// Number of lifecycle events a socket set keeps until they are popped.
+pub const MAX_SET_EVENTS: usize = 8;
+
+/// Why a socket was removed from a set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RemovalReason {
+    /// The socket was removed explicitly, e.g. with [`Set::remove`].
+    Removed,
+    /// The socket was recycled, after its read timeout expired.
+    Recycled,
+    /// The set was pruned.
+    Pruned,
+}
+
+/// A lifecycle event of a socket set, for the application to observe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SetEvent {
+    /// A socket was removed from the set.
+    SocketRemoved {
+        handle: Handle,
+        ty: SocketType,
+        reason: RemovalReason,
+    },
+}
+
+/// An action a driver should take for a socket, as planned by [`Set::poll`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub struct Handle(pub u8);
+pub enum PollAction {
+    /// Ask the module how much data is available for the socket.
+    CheckAvailable(Handle),
+    /// Remove the socket from the set; see [`Set::recycle`].
+    Recycle(Handle),
+    /// Send the data queued in the socket's transmit buffer.
+    FlushTx(Handle),
+}
 
 /// An extensible set of sockets.
-#[derive(Default, Debug)]
-pub struct Set<const N: usize, const L: usize> {
-    pub sockets: Vec<Option<Socket<L>>, N>,
+///
+/// The set holds its sockets inline, and [`Set::new`] is a `const fn`, so a
+/// set can be placed in a `static`, e.g. with `#[link_section]` to put it in
+/// a specific RAM region.
+pub struct Set<const N: usize, const L: usize, const TXL: usize = L> {
+    /// The socket slots. Writing them directly bypasses the bookkeeping of
+    /// the set, e.g. [`len`](#method.len); use its methods instead.
+    pub sockets: [Option<Socket<L, TXL>>; N],
+    /// Number of occupied slots, and of those the TCP and UDP ones.
+    occupied: usize,
+    tcp_count: usize,
+    udp_count: usize,
+    orphan_policy: OrphanPolicy,
+    orphans: OrphanBuffer<L>,
+    tcp_limit: Option<usize>,
+    udp_limit: Option<usize>,
+    /// Generation given to sockets when added; bumped on every removal.
+    generation: u8,
+    events: Deque<SetEvent, MAX_SET_EVENTS>,
+    lost_events: u32,
+}
+
+impl<const N: usize, const L: usize, const TXL: usize> Default for Set<N, L, TXL> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<const N: usize, const L: usize> Set<N, L> {
-    /// Create a socket set using the provided storage.
-    pub fn new() -> Set<N, L> {
-        let mut sockets = Vec::new();
-        while sockets.len() < N {
-            sockets.push(None).ok();
+impl<const N: usize, const L: usize, const TXL: usize> Set<N, L, TXL> {
+    const EMPTY_SLOT: Option<Socket<L, TXL>> = None;
+
+    /// Create an empty socket set.
+    pub const fn new() -> Set<N, L, TXL> {
+        Set {
+            sockets: [Self::EMPTY_SLOT; N],
+            occupied: 0,
+            tcp_count: 0,
+            udp_count: 0,
+            orphan_policy: OrphanPolicy::Reject,
+            orphans: OrphanBuffer::new(),
+            tcp_limit: None,
+            udp_limit: None,
+            generation: 0,
+            events: Deque::new(),
+            lost_events: 0,
         }
-        Set { sockets }
     }
 
     /// Get the maximum number of sockets the set can hold
@@ -44,7 +193,65 @@ impl<const N: usize, const L: usize> Set<N, L> {
 
     /// Get the current number of initialized sockets, the set is holding
     pub fn len(&self) -> usize {
-        self.sockets.iter().filter(|a| a.is_some()).count()
+        self.occupied
+    }
+
+    /// Get the number of sockets of the given type in the set.
+    pub fn count(&self, ty: SocketType) -> usize {
+        match ty {
+            SocketType::Tcp => self.tcp_count,
+            SocketType::Udp => self.udp_count,
+        }
+    }
+
+    fn count_mut(&mut self, ty: SocketType) -> &mut usize {
+        match ty {
+            SocketType::Tcp => &mut self.tcp_count,
+            SocketType::Udp => &mut self.udp_count,
+        }
+    }
+
+    /// Store `socket` in the empty slot at `index`, and count it.
+    fn fill_slot(&mut self, index: usize, socket: Socket<L, TXL>) {
+        debug_assert!(self.sockets[index].is_none());
+        self.occupied += 1;
+        *self.count_mut(socket.get_type()) += 1;
+        self.sockets[index] = Some(socket);
+    }
+
+    /// Take the socket out of the slot at `index`, if any, and uncount it.
+    fn empty_slot(&mut self, index: usize) -> Option<Socket<L, TXL>> {
+        let socket = self.sockets[index].take()?;
+        self.occupied -= 1;
+        *self.count_mut(socket.get_type()) -= 1;
+        Some(socket)
+    }
+
+    /// Limit the number of sockets of the given type in the set, or lift the
+    /// limit with `None`, which is the default.
+    ///
+    /// Adding a socket beyond the limit fails with
+    /// `Err(Error::SocketSetFull)`. Sockets already in the set are kept.
+    pub fn set_type_limit(&mut self, ty: SocketType, limit: Option<usize>) {
+        match ty {
+            SocketType::Tcp => self.tcp_limit = limit,
+            SocketType::Udp => self.udp_limit = limit,
+        }
+    }
+
+    /// Get the limit on the number of sockets of the given type.
+    pub fn type_limit(&self, ty: SocketType) -> Option<usize> {
+        match ty {
+            SocketType::Tcp => self.tcp_limit,
+            SocketType::Udp => self.udp_limit,
+        }
+    }
+
+    fn check_type_limit(&self, ty: SocketType) -> Result<()> {
+        match self.type_limit(ty) {
+            Some(limit) if self.count(ty) >= limit => Err(Error::SocketSetFull),
+            _ => Ok(()),
+        }
     }
 
     /// Check if the set is currently holding no active sockets
@@ -52,6 +259,27 @@ impl<const N: usize, const L: usize> Set<N, L> {
         self.len() == 0
     }
 
+    /// Check if the set has no room for another socket.
+    pub fn is_full(&self) -> bool {
+        self.free_slots() == 0
+    }
+
+    /// Get the number of sockets that can still be added to the set.
+    pub fn free_slots(&self) -> usize {
+        N - self.occupied
+    }
+
+    /// Get the number of sockets of the given type that can still be added
+    /// to the set, taking its [type limit](#method.set_type_limit) into
+    /// account.
+    pub fn free_slots_of_type(&self, ty: SocketType) -> usize {
+        let free = self.free_slots();
+        match self.type_limit(ty) {
+            Some(limit) => free.min(limit.saturating_sub(self.count(ty))),
+            None => free,
+        }
+    }
+
     /// Get the type of a specific socket in the set.
     ///
     /// Returned as a [`SocketType`]
@@ -64,261 +292,2368 @@ impl<const N: usize, const L: usize> Set<N, L> {
         None
     }
 
+    /// Check if any socket in the set is currently connected.
+    pub fn any_connected(&self) -> bool {
+        self.iter()
+            .any(|(_, s)| s.connection_status() == ConnectionStatus::Connected)
+    }
+
+    /// Check if every socket in the set is disconnected.
+    ///
+    /// Sockets that are still connecting do not count as disconnected.
+    pub fn all_disconnected(&self) -> bool {
+        self.iter()
+            .all(|(_, s)| matches!(s.connection_status(), ConnectionStatus::Disconnected { .. }))
+    }
+
     /// Add a socket to the set with the reference count 1, and return its handle.
+    ///
+    /// The socket is given the current generation, so the returned handle
+    /// should be used from now on, rather than the one it was created with.
     pub fn add<T>(&mut self, socket: T) -> Result<Handle>
     where
-        T: Into<Socket<L>>,
+        T: Into<Socket<L, TXL>>,
     {
         let socket = socket.into();
-        let handle = socket.handle();
+        let id = socket.handle().id;
 
         debug!(
             "[Socket Set] Adding: {} {:?} to: {:?}",
-            handle.0,
+            id,
             socket.get_type(),
             self
         );
 
-        if self.index_of(handle).is_ok() {
+        if self.index_of_id(id).is_ok() {
             return Err(Error::DuplicateSocket);
         }
+        self.check_type_limit(socket.get_type())?;
 
-        self.sockets
-            .iter_mut()
-            .find(|s| s.is_none())
-            .ok_or(Error::SocketSetFull)?
-            .replace(socket);
+        let index = self
+            .sockets
+            .iter()
+            .position(|s| s.is_none())
+            .ok_or(Error::SocketSetFull)?;
 
-        Ok(handle)
+        Ok(self.insert(index, socket))
     }
 
-    /// Get a socket from the set by its handle, as mutable.
-    pub fn get<T: AnySocket<L>>(&mut self, handle: Handle) -> Result<SocketRef<T>> {
-        let index = self.index_of(handle)?;
+    /// Add a socket to the set in the slot at `index`, e.g. to mirror the
+    /// socket ids of the module, and return its handle.
+    ///
+    /// Returns `Err(Error::InvalidSocket)` if `index` is out of range, and
+    /// `Err(Error::Illegal)` if the slot is occupied.
+    pub fn add_at<T>(&mut self, index: usize, socket: T) -> Result<Handle>
+    where
+        T: Into<Socket<L, TXL>>,
+    {
+        let socket = socket.into();
+        let id = socket.handle().id;
 
-        match self.sockets.get_mut(index).ok_or(Error::InvalidSocket)? {
-            Some(socket) => Ok(T::downcast(SocketRef::new(socket))?),
+        debug!(
+            "[Socket Set] Adding: {} {:?} at: {}",
+            id,
+            socket.get_type(),
+            index
+        );
+
+        if self.index_of_id(id).is_ok() {
+            return Err(Error::DuplicateSocket);
+        }
+        self.check_type_limit(socket.get_type())?;
+
+        match self.sockets.get(index) {
+            Some(None) => Ok(self.insert(index, socket)),
+            Some(Some(_)) => Err(Error::Illegal),
             None => Err(Error::InvalidSocket),
         }
     }
 
-    /// Get the index of a given socket in the set.
-    fn index_of(&self, handle: Handle) -> Result<usize> {
-        self.sockets
-            .iter()
-            .position(|i| {
-                i.as_ref()
-                    .map(|s| s.handle().0 == handle.0)
-                    .unwrap_or(false)
-            })
-            .ok_or(Error::InvalidSocket)
+    /// Give `socket` the current generation, deliver data held for its
+    /// module id, and store it in the empty slot at `index`.
+    fn insert(&mut self, index: usize, mut socket: Socket<L, TXL>) -> Handle {
+        let handle = Handle {
+            id: socket.handle().id,
+            generation: self.generation,
+        };
+        socket.set_handle(handle);
+        if let Some(module_id) = socket.module_id() {
+            self.orphans.flush(Handle::new(module_id), |data| {
+                socket.rx_enqueue_slice(data);
+            });
+        }
+        self.fill_slot(index, socket);
+        handle
     }
 
-    /// Remove a socket from the set
-    pub fn remove(&mut self, handle: Handle) -> Result<()> {
-        let index = self.index_of(handle)?;
-        let item: &mut Option<Socket<L>> =
-            self.sockets.get_mut(index).ok_or(Error::InvalidSocket)?;
-
-        debug!(
-            "[Socket Set] Removing socket! {} {:?}",
-            handle.0,
-            item.as_ref().map(|i| i.get_type())
-        );
+    /// Start a new generation, after a socket was removed.
+    fn retire(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
 
-        item.take().ok_or(Error::InvalidSocket)?;
-        Ok(())
+    /// Record the removal of `socket`, discarding the oldest event if the
+    /// queue is full.
+    fn push_removed(&mut self, socket: &Socket<L, TXL>, reason: RemovalReason) {
+        if self.events.is_full() {
+            self.events.pop_front();
+            self.lost_events = self.lost_events.saturating_add(1);
+        }
+        self.events
+            .push_back(SetEvent::SocketRemoved {
+                handle: socket.handle(),
+                ty: socket.get_type(),
+                reason,
+            })
+            .ok();
     }
 
-    /// Prune the sockets in this set.
+    /// Pop the oldest lifecycle event of the set.
     ///
-    /// All sockets are removed and dropped.
-    pub fn prune(&mut self) {
-        debug!("[Socket Set] Pruning: {:?}", self);
-        self.sockets.iter_mut().enumerate().for_each(|(_, slot)| {
-            slot.take();
-        })
+    /// Only the last [`MAX_SET_EVENTS`] events are kept; see
+    /// [`lost_events`](#method.lost_events).
+    pub fn pop_event(&mut self) -> Option<SetEvent> {
+        self.events.pop_front()
     }
 
-    pub fn recycle(&mut self) -> bool {
-        let h = self.iter().find(|(_, s)| s.recycle()).map(|(h, _)| h);
-        if h.is_none() {
-            return false;
-        }
-        self.remove(h.unwrap()).is_ok()
+    /// Get the number of events discarded because they were not popped in
+    /// time.
+    pub fn lost_events(&self) -> u32 {
+        self.lost_events
     }
 
-    /// Iterate every socket in this set.
-    pub fn iter(&self) -> impl Iterator<Item = (Handle, &Socket<L>)> {
-        self.sockets.iter().filter_map(|slot| {
-            if let Some(socket) = slot {
-                Some((Handle(socket.handle().0), socket))
-            } else {
-                None
-            }
-        })
-    }
+    /// Replace the socket with the given handle by `socket`, in the same
+    /// slot, and return the old socket.
+    ///
+    /// The new socket may have a different socket id, as long as no other
+    /// socket in the set has it. Either way, it is given a new generation,
+    /// and `handle` becomes stale; the new handle is that of the new socket.
+    pub fn replace<T>(&mut self, handle: Handle, socket: T) -> Result<Socket<L, TXL>>
+    where
+        T: Into<Socket<L, TXL>>,
+    {
+        let socket = socket.into();
+        let index = self.index_of(handle)?;
+        let id = socket.handle().id;
+        if id != handle.id && self.index_of_id(id).is_ok() {
+            return Err(Error::DuplicateSocket);
+        }
+        if self.socket_type(handle) != Some(socket.get_type()) {
+            self.check_type_limit(socket.get_type())?;
+        }
 
-    /// Iterate every socket in this set, as SocketRef.
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle, SocketRef<Socket<L>>)> {
-        self.sockets.iter_mut().filter_map(|slot| {
-            if let Some(socket) = slot {
-                Some((Handle(socket.handle().0), SocketRef::new(socket)))
-            } else {
-                None
-            }
-        })
+        debug!("[Socket Set] Replacing: {} -> {}", handle.id, id);
+
+        let old = self.empty_slot(index).ok_or(Error::InvalidSocket)?;
+        self.push_removed(&old, RemovalReason::Removed);
+        self.retire();
+        self.insert(index, socket);
+        Ok(old)
     }
-}
 
-#[cfg(feature = "defmt")]
-impl<const N: usize, const L: usize> defmt::Format for Set<N, L> {
-    fn format(&self, fmt: defmt::Formatter) {
-        defmt::write!(fmt, "[");
-        for socket in self.iter() {
-            match socket.1 {
-                Socket::Udp(s) => defmt::write!(fmt, "[{:?}, UDP({:?})],", socket.0, s.state()),
-                Socket::Tcp(s) => defmt::write!(fmt, "[{:?}, TCP({:?})],", socket.0, s.state()),
-            }
+    /// Get the lowest handle not used by a socket in the set.
+    ///
+    /// Returns `None` if the set is full.
+    pub fn next_free_handle(&self) -> Option<Handle> {
+        if self.len() == N {
+            return None;
         }
-        defmt::write!(fmt, "]");
+        (0..=u8::MAX)
+            .find(|id| self.index_of_id(*id).is_err())
+            .map(|id| Handle {
+                id,
+                generation: self.generation,
+            })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{TcpSocket, UdpSocket};
+    /// Create a TCP socket with the lowest free handle, add it to the set,
+    /// and return its handle.
+    ///
+    /// Returns `Err(Error::SocketSetFull)` if the set is full.
+    #[cfg(feature = "socket-tcp")]
+    pub fn add_tcp(&mut self, config: crate::TcpSocketConfig) -> Result<Handle> {
+        let handle = self.next_free_handle().ok_or(Error::SocketSetFull)?;
+        self.add(crate::TcpSocket::<L, TXL>::with_config(handle.id, config))
+    }
 
-    #[test]
-    fn add_socket() {
-        let mut set = Set::<2, 64>::new();
+    /// Create a UDP socket with the lowest free handle, add it to the set,
+    /// and return its handle.
+    ///
+    /// Returns `Err(Error::SocketSetFull)` if the set is full.
+    #[cfg(feature = "socket-udp")]
+    pub fn add_udp(&mut self, config: crate::UdpSocketConfig) -> Result<Handle> {
+        let handle = self.next_free_handle().ok_or(Error::SocketSetFull)?;
+        self.add(crate::UdpSocket::<L, TXL>::with_config(handle.id, config))
+    }
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
-        assert_eq!(set.len(), 2);
+    /// Set what to do with data received for handles that are not in the set.
+    ///
+    /// See [`OrphanPolicy`] for the available options.
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) {
+        self.orphan_policy = policy;
     }
 
-    #[test]
-    fn remove_socket() {
-        let mut set = Set::<2, 64>::new();
+    /// Get the current policy for data received for unknown handles.
+    pub fn orphan_policy(&self) -> OrphanPolicy {
+        self.orphan_policy
+    }
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
-        assert_eq!(set.len(), 2);
+    /// Get the number of bytes received for unknown handles that were dropped,
+    /// either directly or after being held for too long.
+    pub fn dropped_orphan_bytes(&self) -> usize {
+        self.orphans.dropped()
+    }
 
-        assert!(set.remove(Handle(0)).is_ok());
-        assert_eq!(set.len(), 1);
+    /// Discard held data for unknown handles that has outlived its TTL.
+    ///
+    /// This also happens on every call to
+    /// [`rx_enqueue_slice`](#method.rx_enqueue_slice).
+    pub fn expire_orphans(&mut self, ts: Instant) {
+        if let OrphanPolicy::Hold { ttl, .. } = self.orphan_policy {
+            self.orphans.expire(ts, ttl);
+        }
+    }
 
-        assert!(set.get::<TcpSocket<64>>(Handle(0)).is_err());
+    /// Enqueue data received for `handle` into the receive buffer of the
+    /// matching socket, and return the number of bytes stored.
+    ///
+    /// As the data comes from the module, the socket id of `handle` is
+    /// matched against the [module id](Socket::module_id) of the sockets, and
+    /// its generation is ignored. If no socket has the module id, the data is
+    /// handled according to the [orphan policy](#method.set_orphan_policy).
+    pub fn rx_enqueue_slice(&mut self, handle: Handle, data: &[u8], ts: Instant) -> Result<usize> {
+        self.expire_orphans(ts);
 
-        set.get::<UdpSocket<64>>(Handle(1))
-            .expect("failed to get udp socket");
+        match self.index_of_module_id(handle.id) {
+            Ok(index) => match self.sockets.get_mut(index) {
+                Some(Some(socket)) => Ok(socket.rx_enqueue_slice(data)),
+                _ => Err(Error::InvalidSocket),
+            },
+            Err(e) => match self.orphan_policy {
+                OrphanPolicy::Reject => Err(e),
+                OrphanPolicy::Drop => {
+                    self.orphans.drop_bytes(data.len());
+                    Ok(0)
+                }
+                OrphanPolicy::Hold { max_bytes, .. } => {
+                    Ok(self
+                        .orphans
+                        .hold(Handle::new(handle.id), data, ts, max_bytes))
+                }
+            },
+        }
     }
 
-    #[test]
-    fn add_duplicate_socket() {
-        let mut set = Set::<2, 64>::new();
+    /// Set the data available on the module for the socket with the given
+    /// handle, e.g. from a `+UUSORD` URC, whatever its type; see
+    /// [`Socket::set_available_data`].
+    ///
+    /// `len` is clamped to `L`, the capacity of the receive buffers, which
+    /// bounds what a socket can take in before the application reads; the
+    /// rest is reported again by a later poll of the module. It is not
+    /// clamped to the current receive window, which would hide data pending
+    /// on the module while the buffer is full.
+    ///
+    /// As with [`rx_enqueue_slice`](#method.rx_enqueue_slice), the socket id
+    /// of `handle` is matched against the module ids of the sockets.
+    pub fn set_available_data(&mut self, handle: Handle, len: usize) -> Result<()> {
+        let index = self.index_of_module_id(handle.id)?;
+        match self.sockets[index].as_mut() {
+            Some(socket) => {
+                socket.set_available_data(len.min(L));
+                Ok(())
+            }
+            None => Err(Error::InvalidSocket),
+        }
+    }
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(0)), Err(Error::DuplicateSocket));
+    /// Set the data available on the module for several sockets at once; see
+    /// [`set_available_data`](#method.set_available_data).
+    ///
+    /// All updates are applied, even if some fail; the first error is
+    /// returned.
+    pub fn set_available_data_batch<I>(&mut self, updates: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (Handle, usize)>,
+    {
+        let mut result = Ok(());
+        for (handle, len) in updates {
+            let update = self.set_available_data(handle, len);
+            if result.is_ok() {
+                result = update;
+            }
+        }
+        result
     }
 
-    #[test]
-    fn add_socket_to_full_set() {
-        let mut set = Set::<2, 64>::new();
+    /// Get a socket from the set by its handle, as mutable.
+    pub fn get<T: AnySocket<L, TXL>>(&mut self, handle: Handle) -> Result<SocketRef<'_, T>> {
+        let index = self.index_of(handle)?;
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
-        assert_eq!(set.len(), 2);
-        assert_eq!(set.add(UdpSocket::new(2)), Err(Error::SocketSetFull));
+        match self.sockets.get_mut(index).ok_or(Error::InvalidSocket)? {
+            Some(socket) => Ok(T::downcast(SocketRef::new(socket))?),
+            None => Err(Error::InvalidSocket),
+        }
     }
 
-    #[test]
-    fn get_socket() {
-        let mut set = Set::<2, 64>::new();
-
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
-        assert_eq!(set.len(), 2);
+    /// Get two different sockets from the set by their handles, as mutable,
+    /// e.g. to move data from one to the other.
+    ///
+    /// Returns `Err(Error::Illegal)` if `a` and `b` are the same handle, or
+    /// if either socket is not of the requested type.
+    pub fn get_pair<T: AnySocket<L, TXL>, U: AnySocket<L, TXL>>(
+        &mut self,
+        a: Handle,
+        b: Handle,
+    ) -> Result<(SocketRef<'_, T>, SocketRef<'_, U>)> {
+        if a == b {
+            return Err(Error::Illegal);
+        }
+        let index_a = self.index_of(a)?;
+        let index_b = self.index_of(b)?;
 
-        set.get::<TcpSocket<64>>(Handle(0))
-            .expect("failed to get tcp socket");
+        let (low, high) = self.sockets.split_at_mut(index_a.max(index_b));
+        let (first, second) = (&mut low[index_a.min(index_b)], &mut high[0]);
+        let (slot_a, slot_b) = if index_a < index_b {
+            (first, second)
+        } else {
+            (second, first)
+        };
 
-        set.get::<UdpSocket<64>>(Handle(1))
-            .expect("failed to get udp socket");
+        let socket_a = slot_a.as_mut().ok_or(Error::InvalidSocket)?;
+        let socket_b = slot_b.as_mut().ok_or(Error::InvalidSocket)?;
+        Ok((
+            T::downcast(SocketRef::new(socket_a))?,
+            U::downcast(SocketRef::new(socket_b))?,
+        ))
     }
 
-    #[test]
-    fn get_socket_wrong_type() {
-        let mut set = Set::<2, 64>::new();
+    /// Get a socket from the set by its handle, as shared.
+    ///
+    /// Returns `Err(Error::Illegal)` if the socket is not of type `T`.
+    pub fn get_ref<T: AnySocket<L, TXL>>(&self, handle: Handle) -> Result<&T> {
+        let socket = self.socket_ref(handle).ok_or(Error::InvalidSocket)?;
+        T::downcast_ref(socket).ok_or(Error::Illegal)
+    }
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
-        assert_eq!(set.len(), 2);
+    fn socket_ref(&self, handle: Handle) -> Option<&Socket<L, TXL>> {
+        let index = self.index_of(handle).ok()?;
+        self.sockets[index].as_ref()
+    }
 
-        assert!(set.get::<TcpSocket<64>>(Handle(1)).is_err());
+    /// Get the connection status of the socket with the given handle.
+    pub fn state_of(&self, handle: Handle) -> Option<ConnectionStatus> {
+        self.socket_ref(handle).map(Socket::connection_status)
+    }
 
-        set.get::<UdpSocket<64>>(Handle(1))
-            .expect("failed to get udp socket");
+    /// Get the remote endpoint of the socket with the given handle, if it
+    /// has one.
+    pub fn endpoint_of(&self, handle: Handle) -> Option<no_std_net::SocketAddr> {
+        self.socket_ref(handle)?.remote_addr()
     }
 
-    #[test]
-    fn get_socket_type() {
-        let mut set = Set::<2, 64>::new();
+    /// Get the number of bytes the module holds for the socket with the
+    /// given handle.
+    pub fn available_data_of(&self, handle: Handle) -> Option<usize> {
+        self.socket_ref(handle).map(Socket::available_data)
+    }
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
-        assert_eq!(set.len(), 2);
+    /// Reset the TCP socket with the given handle for reuse; see
+    /// [`TcpSocket::reset`](crate::TcpSocket::reset).
+    ///
+    /// Returns `Err(Error::Illegal)` if the handle refers to a UDP socket.
+    #[cfg(feature = "socket-tcp")]
+    pub fn reset(&mut self, handle: Handle) -> Result<()> {
+        self.get::<crate::TcpSocket<L, TXL>>(handle)?.reset();
+        Ok(())
+    }
 
-        assert_eq!(set.socket_type(Handle(0)), Some(SocketType::Tcp));
-        assert_eq!(set.socket_type(Handle(1)), Some(SocketType::Udp));
+    /// Record that the remote end closed the socket with the given handle at
+    /// `ts`, e.g. on a `+UUSOCL` URC, whatever its type; see
+    /// [`Socket::closed_by_remote`].
+    ///
+    /// As with [`rx_enqueue_slice`](#method.rx_enqueue_slice), the socket id
+    /// of `handle` is matched against the module ids of the sockets.
+    pub fn closed_by_remote(&mut self, handle: Handle, ts: Instant) -> Result<()> {
+        let index = self.index_of_module_id(handle.id)?;
+        match self.sockets[index].as_mut() {
+            Some(socket) => {
+                debug!("[Socket Set] Closed by remote: {}", handle.id);
+                socket.closed_by_remote(ts);
+                Ok(())
+            }
+            None => Err(Error::InvalidSocket),
+        }
     }
 
-    #[test]
-    fn replace_socket() {
-        let mut set = Set::<2, 64>::new();
+    /// Register a waker on the socket with the given handle, to be woken when
+    /// data is received for it.
+    ///
+    /// See [`Socket::register_rx_waker`].
+    pub fn register_rx_waker(&mut self, handle: Handle, waker: &core::task::Waker) -> Result<()> {
+        let index = self.index_of(handle)?;
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
-        assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
-        assert_eq!(set.len(), 2);
+        match self.sockets.get_mut(index).ok_or(Error::InvalidSocket)? {
+            Some(socket) => {
+                socket.register_rx_waker(waker);
+                Ok(())
+            }
+            None => Err(Error::InvalidSocket),
+        }
+    }
+
+    /// Return the current handle of the socket with the given module socket
+    /// id, e.g. one received in a URC; see
+    /// [`find_by_module_id`](#method.find_by_module_id).
+    pub fn resolve(&self, id: u8) -> Option<Handle> {
+        self.find_by_module_id(id)
+    }
+
+    /// Return the handle of the socket the module knows by socket id
+    /// `module_id`; see [`Socket::module_id`].
+    pub fn find_by_module_id(&self, module_id: u8) -> Option<Handle> {
+        let index = self.index_of_module_id(module_id).ok()?;
+        self.sockets[index].as_ref().map(|s| s.handle())
+    }
+
+    /// Get the index of a given socket in the set.
+    ///
+    /// Returns `Err(Error::InvalidSocket)` if no socket has the handle,
+    /// including when the handle is stale: the socket with its id is of a
+    /// later generation.
+    fn index_of(&self, handle: Handle) -> Result<usize> {
+        let index = self.index_of_id(handle.id)?;
+        match &self.sockets[index] {
+            Some(socket) if socket.handle() == handle => Ok(index),
+            _ => Err(Error::InvalidSocket),
+        }
+    }
+
+    /// Get the index of the socket with the given socket id in the set.
+    ///
+    /// The module hands out socket ids from zero, so a socket is usually
+    /// stored at the index of its id; that slot is checked before falling
+    /// back to a scan.
+    fn index_of_id(&self, id: u8) -> Result<usize> {
+        let preferred = usize::from(id);
+        if let Some(Some(socket)) = self.sockets.get(preferred) {
+            if socket.handle().id == id {
+                return Ok(preferred);
+            }
+        }
+
+        self.sockets
+            .iter()
+            .position(|i| i.as_ref().map(|s| s.handle().id == id).unwrap_or(false))
+            .ok_or(Error::InvalidSocket)
+    }
+
+    /// Get the index of the socket with the given module id in the set,
+    /// checking the slot of that index first as for
+    /// [`index_of_id`](#method.index_of_id).
+    fn index_of_module_id(&self, module_id: u8) -> Result<usize> {
+        let preferred = usize::from(module_id);
+        if let Some(Some(socket)) = self.sockets.get(preferred) {
+            if socket.module_id() == Some(module_id) {
+                return Ok(preferred);
+            }
+        }
+
+        self.sockets
+            .iter()
+            .position(|i| {
+                i.as_ref()
+                    .map(|s| s.module_id() == Some(module_id))
+                    .unwrap_or(false)
+            })
+            .ok_or(Error::InvalidSocket)
+    }
+
+    /// Change the handle of the socket with handle `old` to `new`, e.g. when
+    /// the module assigned a different socket id than was predicted.
+    ///
+    /// Only the socket id of `new` is used, and the socket keeps its
+    /// generation; the resulting handle is returned. Data buffered for the
+    /// new id while it was not in the set is delivered to the socket. This
+    /// function returns `Err(Error::DuplicateSocket)` if another socket
+    /// already has the id of `new`.
+    ///
+    /// The module id of the socket becomes the id of `new` as well. To follow
+    /// a module id reassigned by the module while keeping the handle, use
+    /// [`Socket::set_module_id`] instead.
+    pub fn rebind_handle(&mut self, old: Handle, new: Handle) -> Result<Handle> {
+        let index = self.index_of(old)?;
+        if old.id == new.id {
+            return Ok(old);
+        }
+        if self.index_of_id(new.id).is_ok() {
+            return Err(Error::DuplicateSocket);
+        }
+        let new = Handle {
+            id: new.id,
+            generation: old.generation,
+        };
+
+        let socket = self
+            .sockets
+            .get_mut(index)
+            .and_then(|slot| slot.as_mut())
+            .ok_or(Error::InvalidSocket)?;
+        debug!("[Socket Set] Rebinding: {} -> {}", old.id, new.id);
+        socket.set_handle(new);
+        socket.set_module_id(Some(new.id));
+        self.orphans.flush(Handle::new(new.id), |data| {
+            socket.rx_enqueue_slice(data);
+        });
+        Ok(new)
+    }
+
+    /// Remove a socket from the set
+    pub fn remove(&mut self, handle: Handle) -> Result<()> {
+        self.take(handle).map(drop)
+    }
+
+    /// Remove a socket from the set, and return it, e.g. to recover data
+    /// still in its receive buffer.
+    pub fn take(&mut self, handle: Handle) -> Result<Socket<L, TXL>> {
+        let index = self.index_of(handle)?;
+        let item: &Option<Socket<L, TXL>> = self.sockets.get(index).ok_or(Error::InvalidSocket)?;
+
+        debug!(
+            "[Socket Set] Removing socket! {} {:?}",
+            handle.id,
+            item.as_ref().map(|i| i.get_type())
+        );
+
+        let socket = self.empty_slot(index).ok_or(Error::InvalidSocket)?;
+        self.push_removed(&socket, RemovalReason::Removed);
+        self.retire();
+        Ok(socket)
+    }
+
+    /// Remove every socket for which `f` returns false, and return how many
+    /// were removed.
+    ///
+    /// The remaining sockets keep their slots.
+    pub fn retain<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(Handle, &Socket<L, TXL>) -> bool,
+    {
+        self.retain_mut(|handle, socket| f(handle, socket))
+    }
+
+    /// Like [`retain`](#method.retain), but `f` may also modify the sockets.
+    pub fn retain_mut<F>(&mut self, f: F) -> usize
+    where
+        F: FnMut(Handle, &mut Socket<L, TXL>) -> bool,
+    {
+        self.remove_unless(f, RemovalReason::Removed)
+    }
+
+    /// Remove every socket for which `f` returns false, recording `reason`
+    /// for each, and return how many were removed.
+    fn remove_unless<F>(&mut self, mut f: F, reason: RemovalReason) -> usize
+    where
+        F: FnMut(Handle, &mut Socket<L, TXL>) -> bool,
+    {
+        let mut removed = 0;
+        for index in 0..self.sockets.len() {
+            let keep = match &mut self.sockets[index] {
+                Some(socket) => f(socket.handle(), socket),
+                None => true,
+            };
+            if !keep {
+                if let Some(socket) = self.empty_slot(index) {
+                    debug!(
+                        "[Socket Set] Removing socket! {} {:?}",
+                        socket.handle().id,
+                        socket.get_type()
+                    );
+                    self.push_removed(&socket, reason);
+                    removed += 1;
+                }
+            }
+        }
+        if removed > 0 {
+            self.retire();
+        }
+        removed
+    }
+
+    /// Prune the sockets in this set.
+    ///
+    /// All sockets are removed and dropped.
+    pub fn prune(&mut self) {
+        debug!("[Socket Set] Pruning: {:?}", self);
+        self.prune_where(|_| true);
+    }
+
+    /// Prune the sockets of type `ty` in this set, and return how many were
+    /// removed, e.g. to drop TCP sockets after the PDP context was lost.
+    pub fn prune_type(&mut self, ty: SocketType) -> usize {
+        self.prune_where(|socket| socket.get_type() == ty)
+    }
+
+    /// Prune the sockets in this set for which `f` returns true, and return
+    /// how many were removed.
+    ///
+    /// The remaining sockets keep their slots; see
+    /// [`retain`](#method.retain).
+    pub fn prune_where<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(&Socket<L, TXL>) -> bool,
+    {
+        self.remove_unless(|_, socket| !f(socket), RemovalReason::Pruned)
+    }
+
+    /// Tear down the set, calling `f` for every socket in slot order as it is
+    /// removed.
+    ///
+    /// The set is consumed and each socket is taken out of its slot before
+    /// `f` is called, so the handle can no longer be resolved in the set; it
+    /// still identifies the socket on the module, e.g. to issue the
+    /// corresponding close. `f` should neither allocate nor panic.
+    pub fn teardown<F>(mut self, mut f: F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        debug!("[Socket Set] Tearing down: {:?}", self);
+        for index in 0..N {
+            if let Some(socket) = self.empty_slot(index) {
+                f(TeardownEvent::Socket {
+                    handle: socket.handle(),
+                    ty: socket.get_type(),
+                });
+            }
+        }
+    }
+
+    /// Remove the first socket that should be recycled from the set, and
+    /// return whether there was one; see [`recycle_all`](#method.recycle_all)
+    /// to remove all of them at once.
+    pub fn recycle(&mut self, ts: Instant) -> bool {
+        self.take_recycled(ts).is_some()
+    }
+
+    /// Remove every socket that should be recycled from the set, and return
+    /// how many were removed.
+    pub fn recycle_all(&mut self, ts: Instant) -> usize {
+        let mut recycled = 0;
+        while self.take_recycled(ts).is_some() {
+            recycled += 1;
+        }
+        recycled
+    }
+
+    /// Remove the first socket that should be recycled from the set, and
+    /// return it; see [`TcpSocket::recycle`](crate::TcpSocket::recycle).
+    ///
+    /// TCP sockets get a [`TcpEvent::Recycled`](crate::TcpEvent::Recycled)
+    /// event pushed before being removed, so the caller can drain the
+    /// socket's events to learn how the connection ended.
+    pub fn take_recycled(&mut self, ts: Instant) -> Option<Socket<L, TXL>> {
+        let index = self.sockets.iter_mut().position(|slot| match slot {
+            Some(socket) => socket.recycle(ts),
+            None => false,
+        })?;
+        let mut socket = self.empty_slot(index)?;
+        self.push_removed(&socket, RemovalReason::Recycled);
+        self.retire();
+
+        match socket {
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(ref mut tcp) => {
+                debug!(
+                    "[Socket Set] Recycling TCP socket! {} {:?}",
+                    tcp.handle().id,
+                    tcp.close_reason()
+                );
+                tcp.push_event(crate::TcpEvent::Recycled);
+            }
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(ref mut _udp) => {
+                #[cfg(feature = "stats")]
+                _udp.count_recycle();
+                debug!(
+                    "[Socket Set] Recycling socket! {} {:?}",
+                    _udp.handle().id,
+                    SocketType::Udp
+                );
+            }
+        }
+        Some(socket)
+    }
+
+    /// Plan the actions a driver should take for the sockets in the set at
+    /// `ts`, at most one per socket, in slot order.
+    ///
+    /// A socket due for recycling gets only [`PollAction::Recycle`], so no
+    /// check for available data is made on it. Otherwise a due check takes
+    /// precedence over sending queued data, which waits for a cycle without
+    /// one. A planned check counts as made, as with
+    /// [`Socket::should_update_available_data`]. Queued data is only flushed
+    /// once [`Socket::should_flush_tx`] allows it, i.e. not while a write is
+    /// in flight or within the coalescing window of a TCP socket.
+    pub fn poll(&mut self, ts: Instant) -> Vec<PollAction, N> {
+        let mut actions = Vec::new();
+        for socket in self.sockets.iter_mut().flatten() {
+            let handle = socket.handle();
+            let action = if socket.recycle(ts) {
+                PollAction::Recycle(handle)
+            } else if socket.should_update_available_data(ts) {
+                PollAction::CheckAvailable(handle)
+            } else if socket.should_flush_tx(ts) {
+                PollAction::FlushTx(handle)
+            } else {
+                continue;
+            };
+            // At most one action per socket, so this always fits.
+            let _ = actions.push(action);
+        }
+        actions
+    }
+
+    /// Return how long until any socket in the set next needs a check for
+    /// available data, or `None` if none does; see
+    /// [`Socket::time_until_next_check`].
+    pub fn next_poll_in(&self, ts: Instant) -> Option<Duration> {
+        self.iter()
+            .filter_map(|(_, s)| s.time_until_next_check(ts))
+            .min()
+    }
+
+    /// Return the socket to read from next, and the number of bytes to
+    /// request, or `None` if no socket can be read from.
+    ///
+    /// The socket with the most data available on the module goes first; see
+    /// [`Socket::max_read_len`].
+    pub fn next_read(&self, at_chunk_limit: usize) -> Option<(Handle, usize)> {
+        self.iter()
+            .filter(|(_, s)| s.max_read_len(at_chunk_limit) > 0)
+            .fold(
+                None,
+                |best: Option<(Handle, &Socket<L, TXL>)>, (h, s)| match best {
+                    Some((_, b)) if b.available_data() >= s.available_data() => best,
+                    _ => Some((h, s)),
+                },
+            )
+            .map(|(h, s)| (h, s.max_read_len(at_chunk_limit)))
+    }
+
+    /// Return the handles of all sockets in this set, in slot order.
+    ///
+    /// As the handles are copied, the set can be changed while going through
+    /// them:
+    ///
+    // Doctest binaries have no defmt global logger to link against, so the
+    // example is not run with the `defmt` feature.
+    #[cfg_attr(
+        all(feature = "socket-tcp", feature = "socket-udp", not(feature = "defmt")),
+        doc = "```"
+    )]
+    #[cfg_attr(
+        not(all(feature = "socket-tcp", feature = "socket-udp", not(feature = "defmt"))),
+        doc = "```ignore"
+    )]
+    /// use ublox_sockets::{SocketHandle, SocketSet, TcpSocket, UdpSocket};
+    ///
+    /// let mut set = SocketSet::<2, 64>::new();
+    /// set.add(TcpSocket::new(0)).unwrap();
+    /// set.add(UdpSocket::new(1)).unwrap();
+    ///
+    /// for handle in set.handles() {
+    ///     if let Ok(mut socket) = set.get::<UdpSocket<64>>(handle) {
+    ///         socket.set_available_data(0);
+    ///     }
+    /// }
+    /// # assert_eq!(set.handles(), [SocketHandle::new(0), SocketHandle::new(1)]);
+    /// ```
+    pub fn handles(&self) -> Vec<Handle, N> {
+        self.iter().map(|(handle, _)| handle).collect()
+    }
+
+    /// Return the handles of all sockets of type `ty` in this set, in slot
+    /// order; see [`handles`](#method.handles).
+    pub fn handles_of_type(&self, ty: SocketType) -> Vec<Handle, N> {
+        self.iter()
+            .filter(|(_, socket)| socket.get_type() == ty)
+            .map(|(handle, _)| handle)
+            .collect()
+    }
+
+    /// Iterate every socket in this set.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &Socket<L, TXL>)> {
+        self.sockets
+            .iter()
+            .filter_map(|slot| slot.as_ref().map(|socket| (socket.handle(), socket)))
+    }
+
+    /// Iterate every socket in this set, as SocketRef.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle, SocketRef<'_, Socket<L, TXL>>)> {
+        self.sockets.iter_mut().filter_map(|slot| {
+            if let Some(socket) = slot {
+                Some((socket.handle(), SocketRef::new(socket)))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Find the socket connected to `remote`; see [`Socket::remote_addr`].
+    ///
+    /// If several sockets match, the first one in the set is returned.
+    pub fn find_by_remote(&self, remote: no_std_net::SocketAddr) -> Option<Handle> {
+        self.iter()
+            .find(|(_, socket)| socket.remote_addr() == Some(remote))
+            .map(|(handle, _)| handle)
+    }
+
+    /// Find the socket bound to the local port `port`.
+    ///
+    /// If several sockets match, e.g. a TCP and a UDP socket on the same
+    /// port, the first one in the set is returned.
+    pub fn find_by_local_port(&self, port: u16) -> Option<Handle> {
+        self.iter()
+            .find(|(_, socket)| socket.local_port() == Some(port))
+            .map(|(handle, _)| handle)
+    }
+
+    /// Iterate every socket of type `T` in this set.
+    pub fn iter_typed<'a, T: AnySocket<L, TXL> + 'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (Handle, &'a T)> {
+        self.iter()
+            .filter_map(|(handle, socket)| T::downcast_ref(socket).map(|socket| (handle, socket)))
+    }
+
+    /// Iterate every socket of type `T` in this set, as mutable.
+    pub fn iter_typed_mut<'a, T: AnySocket<L, TXL> + 'a>(
+        &'a mut self,
+    ) -> impl Iterator<Item = (Handle, &'a mut T)> {
+        self.sockets.iter_mut().filter_map(|slot| {
+            let socket = slot.as_mut()?;
+            let handle = socket.handle();
+            let socket = T::downcast(SocketRef::new(socket)).ok()?;
+            Some((handle, SocketRef::into_inner(socket)))
+        })
+    }
+
+    /// Iterate every TCP socket in this set.
+    #[cfg(feature = "socket-tcp")]
+    pub fn iter_tcp(&self) -> impl Iterator<Item = (Handle, &crate::TcpSocket<L, TXL>)> {
+        self.iter_typed()
+    }
+
+    /// Iterate every TCP socket in this set, as mutable.
+    #[cfg(feature = "socket-tcp")]
+    pub fn iter_tcp_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (Handle, &mut crate::TcpSocket<L, TXL>)> {
+        self.iter_typed_mut()
+    }
+
+    /// Iterate every UDP socket in this set.
+    #[cfg(feature = "socket-udp")]
+    pub fn iter_udp(&self) -> impl Iterator<Item = (Handle, &crate::UdpSocket<L, TXL>)> {
+        self.iter_typed()
+    }
+
+    /// Iterate every UDP socket in this set, as mutable.
+    #[cfg(feature = "socket-udp")]
+    pub fn iter_udp_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (Handle, &mut crate::UdpSocket<L, TXL>)> {
+        self.iter_typed_mut()
+    }
+}
+
+impl<const N: usize, const L: usize, const TXL: usize> core::fmt::Debug for Set<N, L, TXL> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        struct Entry<'a, const L: usize, const TXL: usize>(&'a Socket<L, TXL>);
+
+        impl<const L: usize, const TXL: usize> core::fmt::Debug for Entry<'_, L, TXL> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                match self.0 {
+                    #[cfg(feature = "socket-udp")]
+                    Socket::Udp(s) => f
+                        .debug_tuple("Udp")
+                        .field(&s.handle())
+                        .field(&s.state())
+                        .finish(),
+                    #[cfg(feature = "socket-tcp")]
+                    Socket::Tcp(s) => f
+                        .debug_tuple("Tcp")
+                        .field(&s.handle())
+                        .field(s.state())
+                        .finish(),
+                }
+            }
+        }
+
+        f.debug_list()
+            .entries(self.iter().map(|(_, s)| Entry(s)))
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize, const L: usize, const TXL: usize> defmt::Format for Set<N, L, TXL> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "[");
+        for socket in self.iter() {
+            match socket.1 {
+                #[cfg(feature = "socket-udp")]
+                Socket::Udp(s) => defmt::write!(fmt, "[{:?}, UDP{:?}],", socket.0, s),
+                #[cfg(feature = "socket-tcp")]
+                Socket::Tcp(s) => defmt::write!(
+                    fmt,
+                    "[{:?}, TCP({:?}), may_send: {}, can_send: {}, security_profile: {:?}],",
+                    socket.0,
+                    s.state(),
+                    s.may_send(),
+                    s.can_send(),
+                    s.security_profile()
+                ),
+            }
+        }
+        defmt::write!(fmt, "] free: {}", self.free_slots());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "socket-udp")]
+    use crate::UdpSocket;
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    use crate::{RemoteEndpoint, TcpSocketConfig, UdpSocketConfig};
+    #[cfg(feature = "socket-tcp")]
+    use crate::{TcpSocket, TcpState};
+    use no_std_net::{Ipv4Addr, SocketAddrV4};
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn add_socket() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn remove_socket() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 2);
+
+        assert!(set.remove(Handle::new(0)).is_ok());
+        assert_eq!(set.len(), 1);
+
+        assert!(set.get::<TcpSocket<64>>(Handle::new(0)).is_err());
+
+        set.get::<UdpSocket<64>>(Handle::new(1))
+            .expect("failed to get udp socket");
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn add_duplicate_socket() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(0)), Err(Error::DuplicateSocket));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn add_socket_to_full_set() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.add(UdpSocket::new(2)), Err(Error::SocketSetFull));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn reset_socket() {
+        let mut set = Set::<2, 64>::new();
+        let remote = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080);
+
+        let mut socket = TcpSocket::new(0);
+        socket.set_state(crate::TcpState::Connected(remote.into()));
+        set.add(socket).unwrap();
+        set.add(UdpSocket::new(1)).unwrap();
+
+        assert_eq!(set.reset(Handle::new(0)), Ok(()));
+        assert_eq!(
+            set.get::<TcpSocket<64>>(Handle::new(0)).unwrap().state(),
+            &crate::TcpState::Created
+        );
+        assert_eq!(set.reset(Handle::new(1)), Err(Error::Illegal));
+        assert_eq!(set.reset(Handle::new(2)), Err(Error::InvalidSocket));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn next_poll_in() {
+        let mut set = Set::<3, 8>::new();
+        assert_eq!(set.next_poll_in(Instant::from_secs(0)), None);
+
+        let remote = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080);
+        for id in 0..2 {
+            let mut socket = TcpSocket::new(id);
+            socket.set_state(crate::TcpState::Connected(remote.into()));
+            set.add(socket).unwrap();
+        }
+        set.add(UdpSocket::new(2)).unwrap();
+        assert_eq!(
+            set.next_poll_in(Instant::from_secs(0)),
+            Some(Duration::from_secs(0))
+        );
+
+        for (id, ts) in [(0, 10), (1, 4)] {
+            set.get::<TcpSocket<8>>(Handle::new(id))
+                .unwrap()
+                .should_update_available_data(Instant::from_secs(ts));
+        }
+        assert_eq!(
+            set.next_poll_in(Instant::from_secs(12)),
+            Some(Duration::from_secs(7))
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn next_read() {
+        let mut set = Set::<3, 8>::new();
+        assert_eq!(set.next_read(512), None);
+
+        set.add(TcpSocket::new(0)).unwrap();
+        set.add(TcpSocket::new(1)).unwrap();
+        set.add(UdpSocket::new(2)).unwrap();
+        assert_eq!(set.next_read(512), None);
+
+        set.get::<TcpSocket<8>>(Handle::new(0))
+            .unwrap()
+            .set_available_data(5);
+        set.get::<UdpSocket<8>>(Handle::new(2))
+            .unwrap()
+            .set_available_data(20);
+        assert_eq!(set.next_read(512), Some((Handle::new(2), 8)));
+        assert_eq!(set.next_read(4), Some((Handle::new(2), 4)));
+
+        // A socket without room in its receive buffer is skipped
+        set.rx_enqueue_slice(Handle::new(2), b"abcdefgh", Instant::from_secs(0))
+            .unwrap();
+        assert_eq!(set.next_read(512), Some((Handle::new(0), 5)));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn closed_by_remote() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::task::{Wake, Waker};
+
+        struct CountingWaker(AtomicUsize);
+
+        impl Wake for CountingWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+
+        let mut set = Set::<2, 64>::new();
+        let mut socket = TcpSocket::new(0);
+        socket.set_state(TcpState::Connected(
+            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080).into(),
+        ));
+        let tcp = set.add(socket).unwrap();
+        let udp = set.add(UdpSocket::new(1)).unwrap();
+        set.register_rx_waker(tcp, &waker).unwrap();
+        set.register_rx_waker(udp, &waker).unwrap();
+
+        assert_eq!(set.closed_by_remote(tcp, Instant::from_secs(1)), Ok(()));
+        assert_eq!(
+            set.get::<TcpSocket<64>>(tcp).unwrap().state(),
+            &TcpState::ShutdownForWrite(Instant::from_secs(1))
+        );
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        assert_eq!(set.closed_by_remote(udp, Instant::from_secs(2)), Ok(()));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        #[cfg(feature = "stats")]
+        {
+            let tcp = set.get::<TcpSocket<64>>(tcp).unwrap().stats();
+            assert_eq!(tcp.remote_closes, 1);
+            let udp = set.get::<UdpSocket<64>>(udp).unwrap().stats();
+            assert_eq!(udp.remote_closes, 1);
+        }
+        assert_eq!(set.recycle_all(Instant::from_secs(17)), 2);
+
+        assert_eq!(
+            set.closed_by_remote(Handle::new(5), Instant::from_secs(3)),
+            Err(Error::InvalidSocket)
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn set_available_data() {
+        let mut set = Set::<2, 64>::new();
+        let tcp = set.add(TcpSocket::new(0)).unwrap();
+        let udp = set.add(UdpSocket::new(1)).unwrap();
+
+        assert_eq!(set.set_available_data(Handle::new(1), 12), Ok(()));
+        assert_eq!(set.available_data_of(udp), Some(12));
+        assert_eq!(
+            set.set_available_data(Handle::new(2), 1),
+            Err(Error::InvalidSocket)
+        );
+
+        assert_eq!(
+            set.set_available_data_batch([
+                (Handle::new(0), 5),
+                (Handle::new(3), 7),
+                (Handle::new(1), 0),
+            ]),
+            Err(Error::InvalidSocket)
+        );
+        assert_eq!(set.available_data_of(tcp), Some(5));
+        assert_eq!(set.available_data_of(udp), Some(0));
+        assert_eq!(set.set_available_data_batch([(tcp, 1), (udp, 2)]), Ok(()));
+
+        // Clamped to the capacity of the receive buffer, even when full.
+        assert_eq!(set.set_available_data(tcp, 1000), Ok(()));
+        assert_eq!(set.available_data_of(tcp), Some(64));
+        set.get::<TcpSocket<64>>(tcp)
+            .unwrap()
+            .rx_enqueue_slice(&[0; 64]);
+        assert_eq!(set.set_available_data(tcp, 100), Ok(()));
+        assert_eq!(set.available_data_of(tcp), Some(64));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn prune_by_type() {
+        let mut set = Set::<3, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        let udp = set.add(UdpSocket::new(1)).unwrap();
+        set.add(TcpSocket::new(2)).unwrap();
+        let mut socket = set.get::<UdpSocket<64>>(udp).unwrap();
+        socket.bind(5000).unwrap();
+        socket.rx_enqueue_slice(b"keep");
+
+        assert_eq!(set.prune_type(SocketType::Tcp), 2);
+        assert_eq!(set.handles().as_slice(), &[udp]);
+        let socket = set.get::<UdpSocket<64>>(udp).unwrap();
+        assert_eq!(socket.local_port(), Some(5000));
+        assert_eq!(socket.recv_queue(), 4);
+        assert_eq!(set.sockets[1].as_ref().map(|s| s.handle()), Some(udp));
+
+        assert_eq!(set.prune_type(SocketType::Tcp), 0);
+        while let Some(event) = set.pop_event() {
+            assert!(matches!(
+                event,
+                SetEvent::SocketRemoved {
+                    ty: SocketType::Tcp,
+                    reason: RemovalReason::Pruned,
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn prune_by_predicate() {
+        let mut set = Set::<3, 64>::new();
+        for id in 0..3 {
+            set.add(UdpSocket::new(id)).unwrap();
+        }
+        set.get::<UdpSocket<64>>(Handle::new(1))
+            .unwrap()
+            .bind(5000)
+            .unwrap();
+
+        assert_eq!(set.prune_where(|socket| socket.local_port().is_none()), 2);
+        assert_eq!(set.handles().as_slice(), &[Handle::new(1)]);
+        assert_eq!(set.prune_where(|_| false), 0);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn handle_snapshots() {
+        let mut set = Set::<4, 64>::new();
+        assert!(set.handles().is_empty());
+
+        set.add(UdpSocket::new(3)).unwrap();
+        set.add(TcpSocket::new(0)).unwrap();
+        set.add(UdpSocket::new(1)).unwrap();
+        assert_eq!(
+            set.handles().as_slice(),
+            &[Handle::new(3), Handle::new(0), Handle::new(1)]
+        );
+        assert_eq!(
+            set.handles_of_type(SocketType::Udp).as_slice(),
+            &[Handle::new(3), Handle::new(1)]
+        );
+        assert_eq!(
+            set.handles_of_type(SocketType::Tcp).as_slice(),
+            &[Handle::new(0)]
+        );
+
+        // The snapshot stays valid while sockets are removed.
+        for handle in set.handles_of_type(SocketType::Udp) {
+            set.remove(handle).unwrap();
+        }
+        assert_eq!(set.handles().as_slice(), &[Handle::new(0)]);
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn const_construction() {
+        static SET: Set<4, 16> = Set::new();
+        assert!(SET.is_empty());
+        assert_eq!(SET.capacity(), 4);
+
+        const EMPTY: Set<2, 16> = Set::new();
+        let mut set = EMPTY;
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn module_ids() {
+        let mut set = Set::<2, 64>::new();
+        let tcp = set.add(TcpSocket::new(0)).unwrap();
+        let udp = set.add(UdpSocket::new(1)).unwrap();
+        assert_eq!(set.find_by_module_id(1), Some(udp));
+
+        // The module reassigned the ids, e.g. after a reboot.
+        set.get::<TcpSocket<64>>(tcp)
+            .unwrap()
+            .set_module_id(Some(1));
+        set.get::<UdpSocket<64>>(udp)
+            .unwrap()
+            .set_module_id(Some(3));
+        assert_eq!(set.find_by_module_id(1), Some(tcp));
+        assert_eq!(set.resolve(3), Some(udp));
+        assert_eq!(set.find_by_module_id(0), None);
+
+        set.get::<TcpSocket<64>>(tcp)
+            .unwrap()
+            .set_state(TcpState::Connected(
+                SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080).into(),
+            ));
+        assert_eq!(
+            set.rx_enqueue_slice(Handle::new(1), b"abc", Instant::from_secs(0)),
+            Ok(3)
+        );
+        assert_eq!(set.get::<TcpSocket<64>>(tcp).unwrap().recv_queue(), 3);
+        assert_eq!(set.get::<UdpSocket<64>>(udp).unwrap().recv_queue(), 0);
+
+        // Handles stay as they were.
+        assert_eq!(set.get_ref::<UdpSocket<64>>(udp).unwrap().handle(), udp);
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn module_id_orphans() {
+        let mut set = Set::<1, 64>::new();
+        set.set_orphan_policy(OrphanPolicy::Hold {
+            max_bytes: 16,
+            ttl: Duration::from_secs(5),
+        });
+        assert_eq!(
+            set.rx_enqueue_slice(Handle::new(4), b"abc", Instant::from_secs(0)),
+            Ok(3)
+        );
+
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.set_module_id(Some(4));
+        let handle = set.add(socket).unwrap();
+        assert_eq!(handle, Handle::new(0));
+        assert_eq!(set.get::<UdpSocket<64>>(handle).unwrap().recv_queue(), 3);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn poll_actions() {
+        let mut set = Set::<4, 64>::new();
+        let remote = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080);
+        for id in 0..2 {
+            let mut socket = TcpSocket::new(id);
+            socket.set_state(crate::TcpState::Connected(remote.into()));
+            set.add(socket).unwrap();
+        }
+        set.add(UdpSocket::new(2)).unwrap();
+        set.get::<UdpSocket<64>>(Handle::new(2))
+            .unwrap()
+            .closed_by_remote(Instant::from_secs(0));
+        set.add(TcpSocket::new(3)).unwrap();
+
+        assert_eq!(
+            set.poll(Instant::from_secs(0)).as_slice(),
+            &[
+                PollAction::CheckAvailable(Handle::new(0)),
+                PollAction::CheckAvailable(Handle::new(1)),
+                PollAction::CheckAvailable(Handle::new(2)),
+            ]
+        );
+
+        // No check is due yet, so queued data is flushed instead.
+        set.get::<TcpSocket<64>>(Handle::new(1))
+            .unwrap()
+            .send_slice(b"data")
+            .unwrap();
+        assert_eq!(
+            set.poll(Instant::from_millis(100)).as_slice(),
+            &[PollAction::FlushTx(Handle::new(1))]
+        );
+
+        // A socket due for recycling gets nothing else.
+        set.get::<UdpSocket<64>>(Handle::new(2))
+            .unwrap()
+            .set_available_data(4);
+        let actions = set.poll(Instant::from_secs(15));
+        assert!(actions.contains(&PollAction::Recycle(Handle::new(2))));
+        assert!(!actions.contains(&PollAction::CheckAvailable(Handle::new(2))));
+        assert_eq!(set.len(), 4);
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    fn connected_tcp_set() -> Set<1, 64> {
+        let mut set = Set::<1, 64>::new();
+        let remote = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080);
+        let mut socket = TcpSocket::new(0);
+        socket.set_state(crate::TcpState::Connected(remote.into()));
+        set.add(socket).unwrap();
+        // Make the first check for available data, so it is not due below
+        assert_eq!(
+            set.poll(Instant::from_secs(0)).as_slice(),
+            &[PollAction::CheckAvailable(Handle::new(0))]
+        );
+        set
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[test]
+    fn poll_no_flush_while_in_flight() {
+        let mut set = connected_tcp_set();
+        let mut socket = set.get::<TcpSocket<64>>(Handle::new(0)).unwrap();
+        socket.send_slice(b"data").unwrap();
+        assert_eq!(
+            set.poll(Instant::from_millis(100)).as_slice(),
+            &[PollAction::FlushTx(Handle::new(0))]
+        );
+
+        let mut socket = set.get::<TcpSocket<64>>(Handle::new(0)).unwrap();
+        assert_eq!(socket.tx_mark_sent(2), Ok(&b"da"[..]));
+        assert!(set.poll(Instant::from_millis(200)).is_empty());
+
+        // The unacknowledged rest is flushed once the write completes
+        set.get::<TcpSocket<64>>(Handle::new(0)).unwrap().tx_ack(2);
+        assert_eq!(
+            set.poll(Instant::from_millis(300)).as_slice(),
+            &[PollAction::FlushTx(Handle::new(0))]
+        );
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[test]
+    fn poll_no_flush_while_coalescing() {
+        let mut set = connected_tcp_set();
+        let mut socket = set.get::<TcpSocket<64>>(Handle::new(0)).unwrap();
+        socket.set_tx_coalesce(16, Duration::from_millis(50));
+        socket.send_slice(b"data").unwrap();
+
+        assert!(set.poll(Instant::from_millis(100)).is_empty());
+        assert!(set.poll(Instant::from_millis(149)).is_empty());
+        assert_eq!(
+            set.poll(Instant::from_millis(150)).as_slice(),
+            &[PollAction::FlushTx(Handle::new(0))]
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn get_pair() {
+        let mut set = Set::<3, 64>::new();
+        let remote = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080);
+        for id in 0..2 {
+            let mut socket = TcpSocket::new(id);
+            socket.set_state(crate::TcpState::Connected(remote.into()));
+            set.add(socket).unwrap();
+        }
+        let udp = set.add(UdpSocket::new(2)).unwrap();
+        set.get::<TcpSocket<64>>(Handle::new(1))
+            .unwrap()
+            .rx_enqueue_slice(b"proxy");
+
+        // Handles in reverse slot order come back in argument order.
+        let (mut from, mut to) = set
+            .get_pair::<TcpSocket<64>, TcpSocket<64>>(Handle::new(1), Handle::new(0))
+            .unwrap();
+        let mut buf = [0u8; 8];
+        let len = from.recv_slice(&mut buf).unwrap();
+        assert_eq!(to.send_slice(&buf[..len]), Ok(5));
+        assert_eq!(from.recv_queue(), 0);
+        assert_eq!(to.send_queue(), 5);
+
+        assert_eq!(
+            set.get_pair::<TcpSocket<64>, TcpSocket<64>>(Handle::new(0), Handle::new(0))
+                .err(),
+            Some(Error::Illegal)
+        );
+        assert_eq!(
+            set.get_pair::<TcpSocket<64>, TcpSocket<64>>(Handle::new(0), udp)
+                .err(),
+            Some(Error::Illegal)
+        );
+        assert_eq!(
+            set.get_pair::<TcpSocket<64>, UdpSocket<64>>(Handle::new(0), Handle::new(3))
+                .err(),
+            Some(Error::InvalidSocket)
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn get_shared() {
+        let remote = no_std_net::SocketAddr::new(
+            no_std_net::IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 1)),
+            4000,
+        );
+        let mut set = Set::<2, 8>::new();
+        let tcp = set.add(TcpSocket::new(0)).unwrap();
+        let udp = set.add(UdpSocket::new(1)).unwrap();
+        set.get::<UdpSocket<8>>(udp)
+            .unwrap()
+            .connect(remote)
+            .unwrap();
+        set.get::<UdpSocket<8>>(udp).unwrap().set_available_data(4);
+
+        let set = &set;
+        assert_eq!(set.get_ref::<TcpSocket<8>>(tcp).unwrap().handle(), tcp);
+        assert_eq!(
+            set.get_ref::<UdpSocket<8>>(udp).unwrap().endpoint(),
+            Some(remote)
+        );
+        assert_eq!(set.get_ref::<UdpSocket<8>>(tcp).err(), Some(Error::Illegal));
+        assert_eq!(
+            set.get_ref::<TcpSocket<8>>(Handle::new(2)).err(),
+            Some(Error::InvalidSocket)
+        );
 
-        assert!(set.remove(Handle(0)).is_ok());
+        assert_eq!(set.state_of(udp), Some(ConnectionStatus::Connected));
+        assert_eq!(set.endpoint_of(udp), Some(remote));
+        assert_eq!(set.endpoint_of(tcp), None);
+        assert_eq!(set.available_data_of(udp), Some(4));
+        assert_eq!(set.available_data_of(Handle::new(2)), None);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn debug_output() {
+        let mut set = Set::<3, 8>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        set.add(UdpSocket::new(1)).unwrap();
+
+        assert_eq!(
+            format!("{:?}", set),
+            "[Tcp(Handle { id: 0, generation: 0 }, Created), Udp(Handle { id: 1, generation: 0 }, Closed)]"
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn debug_output_states() {
+        let mut set = Set::<2, 8>::new();
+        assert_eq!(format!("{:?}", set), "[]");
+
+        let handle = set.add(UdpSocket::new(1)).unwrap();
+        set.get::<UdpSocket<8>>(handle)
+            .unwrap()
+            .set_state(crate::UdpState::Established);
+        set.add(TcpSocket::new(0)).unwrap();
+
+        // Entries follow slot order, not handle order.
+        assert_eq!(
+            format!("{:?}", set),
+            "[Udp(Handle { id: 1, generation: 0 }, Established), Tcp(Handle { id: 0, generation: 0 }, Created)]"
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn get_socket_asymmetric_buffers() {
+        let mut set = Set::<2, 8, 32>::new();
+        set.add(TcpSocket::<8, 32>::new(0)).unwrap();
+        set.add(UdpSocket::<8, 32>::new(1)).unwrap();
+
+        assert!(set.get::<TcpSocket<8, 32>>(Handle::new(0)).is_ok());
+        assert!(set.get::<UdpSocket<8, 32>>(Handle::new(1)).is_ok());
+        assert_eq!(
+            set.get::<TcpSocket<8, 32>>(Handle::new(1)).err(),
+            Some(Error::Illegal)
+        );
+        assert_eq!(
+            set.get::<UdpSocket<8, 32>>(Handle::new(0)).err(),
+            Some(Error::Illegal)
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn get_socket() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 2);
+
+        set.get::<TcpSocket<64>>(Handle::new(0))
+            .expect("failed to get tcp socket");
+
+        set.get::<UdpSocket<64>>(Handle::new(1))
+            .expect("failed to get udp socket");
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn get_socket_wrong_type() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 2);
+
+        assert!(set.get::<TcpSocket<64>>(Handle::new(1)).is_err());
+
+        set.get::<UdpSocket<64>>(Handle::new(1))
+            .expect("failed to get udp socket");
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn get_socket_type() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 2);
+
+        assert_eq!(set.socket_type(Handle::new(0)), Some(SocketType::Tcp));
+        assert_eq!(set.socket_type(Handle::new(1)), Some(SocketType::Udp));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn replace_socket() {
+        let mut set = Set::<2, 64>::new();
+
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
+        assert_eq!(set.len(), 2);
+
+        assert!(set.remove(Handle::new(0)).is_ok());
         assert_eq!(set.len(), 1);
 
-        assert!(set.get::<TcpSocket<64>>(Handle(0)).is_err());
+        assert!(set.get::<TcpSocket<64>>(Handle::new(0)).is_err());
 
-        set.get::<UdpSocket<64>>(Handle(1))
+        set.get::<UdpSocket<64>>(Handle::new(1))
             .expect("failed to get udp socket");
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
+        let handle = set.add(TcpSocket::new(0)).unwrap();
+        assert_eq!(handle.id(), 0);
         assert_eq!(set.len(), 2);
 
-        set.get::<TcpSocket<64>>(Handle(0))
+        set.get::<TcpSocket<64>>(handle)
             .expect("failed to get tcp socket");
     }
 
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn connection_status_queries() {
+        let mut set = Set::<2, 64>::new();
+        assert!(!set.any_connected());
+        assert!(set.all_disconnected());
+
+        set.add(UdpSocket::new(1)).unwrap();
+        assert!(!set.any_connected());
+        assert!(set.all_disconnected());
+
+        set.add(TcpSocket::new(0)).unwrap();
+        assert!(!set.any_connected());
+        assert!(!set.all_disconnected());
+
+        set.get::<UdpSocket<64>>(Handle::new(1))
+            .unwrap()
+            .connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 53))
+            .unwrap();
+        assert!(set.any_connected());
+        assert!(!set.all_disconnected());
+    }
+
+    #[test]
+    fn orphan_policy_reject() {
+        let mut set = Set::<2, 64>::new();
+        assert_eq!(set.orphan_policy(), OrphanPolicy::Reject);
+
+        assert_eq!(
+            set.rx_enqueue_slice(Handle::new(0), b"data", Instant::from_secs(0)),
+            Err(Error::InvalidSocket)
+        );
+        assert_eq!(set.dropped_orphan_bytes(), 0);
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn orphan_policy_drop() {
+        let mut set = Set::<2, 64>::new();
+        set.set_orphan_policy(OrphanPolicy::Drop);
+
+        assert_eq!(
+            set.rx_enqueue_slice(Handle::new(0), b"data", Instant::from_secs(0)),
+            Ok(0)
+        );
+        assert_eq!(set.dropped_orphan_bytes(), 4);
+
+        set.add(UdpSocket::new(0)).unwrap();
+        let mut socket = set.get::<UdpSocket<64>>(Handle::new(0)).unwrap();
+        socket
+            .connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 53))
+            .unwrap();
+        assert_eq!(socket.peek(64), Ok(&b""[..]));
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn orphan_policy_hold_flushes_in_order() {
+        let mut set = Set::<2, 64>::new();
+        set.set_orphan_policy(OrphanPolicy::Hold {
+            max_bytes: 32,
+            ttl: Duration::from_secs(5),
+        });
+
+        let ts = Instant::from_secs(0);
+        assert_eq!(set.rx_enqueue_slice(Handle::new(0), b"abc", ts), Ok(3));
+        assert_eq!(set.rx_enqueue_slice(Handle::new(1), b"123", ts), Ok(3));
+        assert_eq!(set.rx_enqueue_slice(Handle::new(0), b"def", ts), Ok(3));
+
+        set.add(UdpSocket::new(0)).unwrap();
+        let mut socket = set.get::<UdpSocket<64>>(Handle::new(0)).unwrap();
+        socket
+            .connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 53))
+            .unwrap();
+        assert_eq!(socket.peek(64), Ok(&b"abcdef"[..]));
+
+        set.add(UdpSocket::new(1)).unwrap();
+        let mut socket = set.get::<UdpSocket<64>>(Handle::new(1)).unwrap();
+        socket
+            .connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 54))
+            .unwrap();
+        assert_eq!(socket.peek(64), Ok(&b"123"[..]));
+        assert_eq!(set.dropped_orphan_bytes(), 0);
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn orphan_policy_hold_evicts_oldest() {
+        let mut set = Set::<2, 64>::new();
+        set.set_orphan_policy(OrphanPolicy::Hold {
+            max_bytes: 6,
+            ttl: Duration::from_secs(5),
+        });
+
+        let ts = Instant::from_secs(0);
+        assert_eq!(set.rx_enqueue_slice(Handle::new(0), b"abc", ts), Ok(3));
+        assert_eq!(set.rx_enqueue_slice(Handle::new(0), b"def", ts), Ok(3));
+        assert_eq!(set.rx_enqueue_slice(Handle::new(0), b"ghi", ts), Ok(3));
+        assert_eq!(set.rx_enqueue_slice(Handle::new(0), b"too long", ts), Ok(0));
+        assert_eq!(set.dropped_orphan_bytes(), 11);
+
+        set.add(UdpSocket::new(0)).unwrap();
+        let mut socket = set.get::<UdpSocket<64>>(Handle::new(0)).unwrap();
+        socket
+            .connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 53))
+            .unwrap();
+        assert_eq!(socket.peek(64), Ok(&b"defghi"[..]));
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn orphan_policy_hold_ttl_expiry() {
+        let mut set = Set::<2, 64>::new();
+        set.set_orphan_policy(OrphanPolicy::Hold {
+            max_bytes: 32,
+            ttl: Duration::from_secs(5),
+        });
+
+        assert_eq!(
+            set.rx_enqueue_slice(Handle::new(0), b"old", Instant::from_secs(0)),
+            Ok(3)
+        );
+        assert_eq!(
+            set.rx_enqueue_slice(Handle::new(1), b"new", Instant::from_secs(3)),
+            Ok(3)
+        );
+        set.expire_orphans(Instant::from_secs(5));
+        assert_eq!(set.dropped_orphan_bytes(), 3);
+
+        set.add(UdpSocket::new(0)).unwrap();
+        set.add(UdpSocket::new(1)).unwrap();
+        for (handle, port, expected) in [(0, 53, &b""[..]), (1, 54, &b"new"[..])] {
+            let mut socket = set.get::<UdpSocket<64>>(Handle::new(handle)).unwrap();
+            socket
+                .connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port))
+                .unwrap();
+            assert_eq!(socket.peek(64), Ok(expected));
+        }
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn index_of_out_of_place() {
+        let mut set = Set::<3, 64>::new();
+        set.add(TcpSocket::new(2)).unwrap();
+        set.add(UdpSocket::new(0)).unwrap();
+        set.add(TcpSocket::new(1)).unwrap();
+        set.add(UdpSocket::new(7)).unwrap_err();
+
+        assert_eq!(set.index_of(Handle::new(2)), Ok(0));
+        assert_eq!(set.index_of(Handle::new(0)), Ok(1));
+        assert_eq!(set.index_of(Handle::new(1)), Ok(2));
+        assert_eq!(set.index_of(Handle::new(7)), Err(Error::InvalidSocket));
+
+        set.remove(Handle::new(2)).unwrap();
+        set.add(UdpSocket::new(0)).unwrap_err();
+        let handle = set.add(UdpSocket::new(7)).unwrap();
+        assert_eq!(set.index_of(handle), Ok(0));
+        assert_eq!(set.index_of_id(7), Ok(0));
+        assert_eq!(set.socket_type(handle), Some(SocketType::Udp));
+        assert_eq!(set.index_of_id(2), Err(Error::InvalidSocket));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn allocate_handles() {
+        let mut set = Set::<3, 64>::new();
+        assert_eq!(set.next_free_handle(), Some(Handle::new(0)));
+        assert_eq!(set.add_tcp(TcpSocketConfig::default()), Ok(Handle::new(0)));
+        assert_eq!(set.add_udp(UdpSocketConfig::default()), Ok(Handle::new(1)));
+        assert_eq!(set.socket_type(Handle::new(1)), Some(SocketType::Udp));
+
+        // Handles picked by the caller are skipped.
+        set.add(TcpSocket::new(2)).unwrap();
+        assert_eq!(set.next_free_handle(), None);
+        assert_eq!(
+            set.add_tcp(TcpSocketConfig::default()),
+            Err(Error::SocketSetFull)
+        );
+
+        // The lowest free id is reused after a remove, in a new generation.
+        set.remove(Handle::new(1)).unwrap();
+        set.remove(Handle::new(0)).unwrap();
+        let next = set.next_free_handle().unwrap();
+        assert_eq!((next.id(), next.generation()), (0, 2));
+        let udp = set.add_udp(UdpSocketConfig::default()).unwrap();
+        let tcp = set.add_tcp(TcpSocketConfig::default()).unwrap();
+        assert_eq!(udp, next);
+        assert_eq!(tcp.id(), 1);
+        assert_eq!(set.socket_type(udp), Some(SocketType::Udp));
+        assert_eq!(set.socket_type(tcp), Some(SocketType::Tcp));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn typed_iteration() {
+        let mut set = Set::<4, 64>::new();
+        set.add(UdpSocket::new(0)).unwrap();
+        set.add(TcpSocket::new(1)).unwrap();
+        set.add(UdpSocket::new(2)).unwrap();
+        set.add(TcpSocket::new(3)).unwrap();
+
+        let tcp: Vec<Handle, 4> = set.iter_tcp().map(|(handle, _)| handle).collect();
+        assert_eq!(&tcp[..], &[Handle::new(1), Handle::new(3)]);
+        let udp: Vec<Handle, 4> = set.iter_udp().map(|(handle, _)| handle).collect();
+        assert_eq!(&udp[..], &[Handle::new(0), Handle::new(2)]);
+
+        for (handle, socket) in set.iter_udp_mut() {
+            socket.bind(5000 + u16::from(handle.id())).unwrap();
+        }
+        for (_, socket) in set.iter_tcp_mut() {
+            socket.set_available_data(7);
+        }
+
+        let ports: Vec<Option<u16>, 4> = set.iter().map(|(_, s)| s.local_port()).collect();
+        assert_eq!(&ports[..], &[Some(5000), None, Some(5002), None]);
+        assert!(set.iter_tcp().all(|(_, s)| s.get_available_data() == 7));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn find_sockets() {
+        let peer = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+        let mut set = Set::<4, 64>::new();
+        set.add(UdpSocket::new(0)).unwrap();
+        set.add(TcpSocket::new(1)).unwrap();
+        set.add(UdpSocket::new(2)).unwrap();
+        set.add(TcpSocket::new(3)).unwrap();
+
+        set.get::<UdpSocket<64>>(Handle::new(0))
+            .unwrap()
+            .bind(5000)
+            .unwrap();
+        set.get::<UdpSocket<64>>(Handle::new(2))
+            .unwrap()
+            .connect(peer)
+            .unwrap();
+        let mut tcp = set.get::<TcpSocket<64>>(Handle::new(1)).unwrap();
+        tcp.set_state(TcpState::Connected(peer.into()));
+        tcp.set_local_endpoint(Some(
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 5000).into(),
+        ));
+        set.get::<TcpSocket<64>>(Handle::new(3))
+            .unwrap()
+            .set_state(TcpState::Connected(
+                RemoteEndpoint::host("example.com", 80).unwrap(),
+            ));
+
+        // The TCP connection comes after the UDP socket bound to the port.
+        assert_eq!(set.find_by_local_port(5000), Some(Handle::new(0)));
+        assert_eq!(set.find_by_local_port(5001), None);
+        assert_eq!(set.find_by_remote(peer.into()), Some(Handle::new(1)));
+        assert_eq!(
+            set.find_by_remote(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 81).into()),
+            None
+        );
+
+        set.remove(Handle::new(0)).unwrap();
+        set.remove(Handle::new(1)).unwrap();
+        assert_eq!(set.find_by_local_port(5000), None);
+        assert_eq!(set.find_by_remote(peer.into()), Some(Handle::new(2)));
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn retain_sockets() {
+        let mut set = Set::<4, 64>::new();
+        set.add(UdpSocket::new(0)).unwrap();
+        set.add(TcpSocket::new(1)).unwrap();
+        set.add(UdpSocket::new(2)).unwrap();
+        set.add(TcpSocket::new(3)).unwrap();
+        set.get::<UdpSocket<64>>(Handle::new(2))
+            .unwrap()
+            .bind(5000)
+            .unwrap();
+        set.get::<TcpSocket<64>>(Handle::new(3))
+            .unwrap()
+            .set_state(TcpState::Connected(
+                SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80).into(),
+            ));
+
+        assert_eq!(
+            set.retain(|_, socket| socket.connection_status() == ConnectionStatus::Connected),
+            2
+        );
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.sockets[0].as_ref().map(|s| s.handle()), None);
+        assert_eq!(
+            set.sockets[2].as_ref().map(|s| s.handle()),
+            Some(Handle::new(2))
+        );
+        assert_eq!(
+            set.sockets[3].as_ref().map(|s| s.handle()),
+            Some(Handle::new(3))
+        );
+
+        assert_eq!(set.retain(|_, _| true), 0);
+
+        let removed = set.retain_mut(|handle, socket| {
+            socket.set_available_data(5);
+            handle != Handle::new(3)
+        });
+        assert_eq!(removed, 1);
+        assert_eq!(set.socket_type(Handle::new(3)), None);
+        assert_eq!(
+            set.iter().map(|(_, s)| s.available_data()).sum::<usize>(),
+            5
+        );
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[test]
+    #[allow(irrefutable_let_patterns)]
+    fn take_socket() {
+        let mut set = Set::<2, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        let mut tcp = set.get::<TcpSocket<64>>(Handle::new(0)).unwrap();
+        tcp.set_state(TcpState::Connected(
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80).into(),
+        ));
+        tcp.rx_enqueue_slice(b"unread");
+
+        let socket = set.take(Handle::new(0)).unwrap();
+        assert!(set.is_empty());
+        assert_eq!(set.take(Handle::new(0)).err(), Some(Error::InvalidSocket));
+
+        let Socket::Tcp(mut tcp) = socket else {
+            panic!("expected a TCP socket");
+        };
+        let mut data = [0; 8];
+        assert_eq!(tcp.recv_slice(&mut data), Ok(6));
+        assert_eq!(&data[..6], b"unread");
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn add_at_slot() {
+        let mut set = Set::<3, 64>::new();
+        assert_eq!(set.add_at(2, TcpSocket::new(2)), Ok(Handle::new(2)));
+        assert_eq!(set.add_at(2, UdpSocket::new(1)), Err(Error::Illegal));
+        assert_eq!(set.add_at(3, UdpSocket::new(1)), Err(Error::InvalidSocket));
+        assert_eq!(
+            set.add_at(0, UdpSocket::new(2)),
+            Err(Error::DuplicateSocket)
+        );
+        assert_eq!(set.add_at(0, UdpSocket::new(1)), Ok(Handle::new(1)));
+
+        assert_eq!(
+            set.sockets[0].as_ref().map(|s| s.handle()),
+            Some(Handle::new(1))
+        );
+        assert_eq!(
+            set.sockets[2].as_ref().map(|s| s.handle()),
+            Some(Handle::new(2))
+        );
+        assert_eq!(set.socket_type(Handle::new(1)), Some(SocketType::Udp));
+        assert_eq!(set.socket_type(Handle::new(2)), Some(SocketType::Tcp));
+
+        // The remaining free slot is used by `add`.
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(
+            set.sockets[1].as_ref().map(|s| s.handle()),
+            Some(Handle::new(0))
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn replace_in_place() {
+        let mut set = Set::<3, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        set.add(UdpSocket::new(1)).unwrap();
+
+        let old = set.replace(Handle::new(0), UdpSocket::new(0)).unwrap();
+        assert_eq!(old.get_type(), SocketType::Tcp);
+        assert_eq!(set.socket_type(Handle::new(0)), None);
+        let handle = set.resolve(0).unwrap();
+        assert_eq!(set.socket_type(handle), Some(SocketType::Udp));
+
+        assert_eq!(
+            set.replace(handle, TcpSocket::new(1)).err(),
+            Some(Error::DuplicateSocket)
+        );
+        assert_eq!(
+            set.replace(Handle::new(2), TcpSocket::new(2)).err(),
+            Some(Error::InvalidSocket)
+        );
+
+        let old = set.replace(handle, TcpSocket::new(4)).unwrap();
+        assert_eq!(old.handle(), handle);
+        assert_eq!(set.socket_type(handle), None);
+        let handle = set.resolve(4).unwrap();
+        assert_eq!(set.socket_type(handle), Some(SocketType::Tcp));
+        assert_eq!(set.sockets[0].as_ref().map(|s| s.handle()), Some(handle));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn type_limits() {
+        let mut set = Set::<4, 64>::new();
+        assert_eq!(set.type_limit(SocketType::Tcp), None);
+        set.set_type_limit(SocketType::Tcp, Some(1));
+        set.set_type_limit(SocketType::Udp, Some(2));
+
+        set.add(TcpSocket::new(0)).unwrap();
+        assert_eq!(set.add(TcpSocket::new(1)), Err(Error::SocketSetFull));
+        assert_eq!(set.add_at(3, TcpSocket::new(1)), Err(Error::SocketSetFull));
+        set.add(UdpSocket::new(1)).unwrap();
+        set.add(UdpSocket::new(2)).unwrap();
+        assert_eq!(set.add(UdpSocket::new(3)), Err(Error::SocketSetFull));
+        assert_eq!(
+            set.replace(Handle::new(1), TcpSocket::new(1)).err(),
+            Some(Error::SocketSetFull)
+        );
+        assert_eq!(set.count(SocketType::Tcp), 1);
+        assert_eq!(set.count(SocketType::Udp), 2);
+
+        // Replacing a socket with one of the same type is within the limit.
+        assert!(set.replace(Handle::new(1), UdpSocket::new(1)).is_ok());
+
+        set.remove(Handle::new(0)).unwrap();
+        assert!(set.add(TcpSocket::new(3)).is_ok());
+
+        set.set_type_limit(SocketType::Udp, None);
+        assert!(set.add(UdpSocket::new(4)).is_ok());
+        assert_eq!(set.count(SocketType::Udp), 3);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn stale_handles() {
+        let mut set = Set::<2, 64>::new();
+        let old = set.add(TcpSocket::new(2)).unwrap();
+        set.remove(old).unwrap();
+        let new = set.add(UdpSocket::new(2)).unwrap();
+
+        assert_eq!(new.id(), old.id());
+        assert_ne!(new, old);
+        assert_eq!(
+            set.get::<TcpSocket<64>>(old).err(),
+            Some(Error::InvalidSocket)
+        );
+        assert_eq!(set.socket_type(old), None);
+        assert_eq!(set.remove(old), Err(Error::InvalidSocket));
+        assert!(set.get::<UdpSocket<64>>(new).is_ok());
+        assert_eq!(set.resolve(2), Some(new));
+        assert_eq!(set.resolve(3), None);
+
+        // Data from the module only carries the socket id.
+        assert_eq!(
+            set.rx_enqueue_slice(Handle::new(2), b"abc", Instant::from_secs(0)),
+            Ok(3)
+        );
+        assert_eq!(set.get::<UdpSocket<64>>(new).unwrap().recv_queue(), 3);
+    }
+
+    #[test]
+    fn handle_serializes_as_id() {
+        let handle = Handle {
+            id: 3,
+            generation: 7,
+        };
+        let serialized: heapless::String<8> =
+            atat::serde_at::to_string(&handle, "", Default::default()).unwrap();
+        assert_eq!(serialized.as_str(), "3");
+        let deserialized: Handle = atat::serde_at::from_str("3").unwrap();
+        assert_eq!(deserialized, Handle::new(3));
+        assert_eq!(<Handle as AtatLen>::LEN, <u8 as AtatLen>::LEN);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn free_slots() {
+        let mut set = Set::<3, 64>::new();
+        assert!(!set.is_full());
+        assert_eq!(set.free_slots(), 3);
+
+        set.set_type_limit(SocketType::Tcp, Some(1));
+        set.add(UdpSocket::new(0)).unwrap();
+        assert_eq!(set.free_slots(), 2);
+        assert_eq!(set.free_slots_of_type(SocketType::Tcp), 1);
+        assert_eq!(set.free_slots_of_type(SocketType::Udp), 2);
+
+        set.add(TcpSocket::new(1)).unwrap();
+        assert_eq!(set.free_slots_of_type(SocketType::Tcp), 0);
+        assert_eq!(set.free_slots_of_type(SocketType::Udp), 1);
+
+        // A limit lowered below the current count leaves no room.
+        set.set_type_limit(SocketType::Udp, Some(0));
+        assert_eq!(set.free_slots_of_type(SocketType::Udp), 0);
+
+        set.add(TcpSocket::new(2)).unwrap_err();
+        set.set_type_limit(SocketType::Tcp, None);
+        set.add(TcpSocket::new(2)).unwrap();
+        assert!(set.is_full());
+        assert_eq!(set.free_slots(), 0);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn counts_follow_every_removal_path() {
+        let check = |set: &Set<4, 64>| {
+            let slots = |ty: Option<SocketType>| {
+                set.sockets
+                    .iter()
+                    .flatten()
+                    .filter(|s| ty.is_none_or(|ty| s.get_type() == ty))
+                    .count()
+            };
+            assert_eq!(set.len(), slots(None));
+            assert_eq!(set.count(SocketType::Tcp), slots(Some(SocketType::Tcp)));
+            assert_eq!(set.count(SocketType::Udp), slots(Some(SocketType::Udp)));
+            assert_eq!(set.free_slots(), 4 - slots(None));
+            assert_eq!(set.is_full(), slots(None) == 4);
+        };
+
+        let mut set = Set::<4, 64>::new();
+        check(&set);
+        let tcp = set.add(TcpSocket::new(0)).unwrap();
+        set.add_at(3, UdpSocket::new(1)).unwrap();
+        set.add(UdpSocket::new(2)).unwrap();
+        set.add(TcpSocket::new(3)).unwrap();
+        check(&set);
+        assert!(set.is_full());
+
+        set.replace(tcp, UdpSocket::new(0)).unwrap();
+        check(&set);
+        assert_eq!(set.count(SocketType::Udp), 3);
+
+        set.take(Handle::new(3)).unwrap();
+        check(&set);
+        assert_eq!(set.retain(|handle, _| handle.id != 2), 1);
+        check(&set);
+
+        set.get::<UdpSocket<64>>(Handle::new(1))
+            .unwrap()
+            .closed_by_remote(Instant::from_secs(0));
+        assert!(set.recycle(Instant::from_secs(15)));
+        check(&set);
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.count(SocketType::Tcp), 0);
+
+        let mut torn_down = 0;
+        set.teardown(|_| torn_down += 1);
+        assert_eq!(torn_down, 1);
+    }
+
+    #[test]
+    fn handle_conversions() {
+        let handle = Handle {
+            id: 3,
+            generation: 7,
+        };
+        assert_eq!(handle.raw(), 3);
+        assert_eq!(u8::from(handle), 3);
+        assert_eq!(Handle::from(3), Handle::new(3));
+        assert_eq!(format!("{}", handle), "3");
+
+        let serialized: heapless::String<8> =
+            atat::serde_at::to_string(&Handle::from(255), "", Default::default()).unwrap();
+        assert_eq!(serialized.as_str(), "255");
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn lifecycle_events() {
+        let mut set = Set::<3, 64>::new();
+        let tcp = set.add(TcpSocket::new(0)).unwrap();
+        let udp = set.add(UdpSocket::new(1)).unwrap();
+        assert_eq!(set.pop_event(), None);
+
+        set.remove(tcp).unwrap();
+        assert_eq!(
+            set.pop_event(),
+            Some(SetEvent::SocketRemoved {
+                handle: tcp,
+                ty: SocketType::Tcp,
+                reason: RemovalReason::Removed
+            })
+        );
+
+        let mut socket = set.get::<UdpSocket<64>>(udp).unwrap();
+        socket.bind(5000).unwrap();
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert!(set.recycle(Instant::from_secs(20)));
+        assert_eq!(
+            set.pop_event(),
+            Some(SetEvent::SocketRemoved {
+                handle: udp,
+                ty: SocketType::Udp,
+                reason: RemovalReason::Recycled
+            })
+        );
+
+        let tcp = set.add(TcpSocket::new(0)).unwrap();
+        set.prune();
+        assert_eq!(
+            set.pop_event(),
+            Some(SetEvent::SocketRemoved {
+                handle: tcp,
+                ty: SocketType::Tcp,
+                reason: RemovalReason::Pruned
+            })
+        );
+        assert_eq!(set.pop_event(), None);
+        assert_eq!(set.lost_events(), 0);
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[test]
+    fn lifecycle_events_overflow() {
+        let mut set = Set::<1, 64>::new();
+        for id in 0..MAX_SET_EVENTS as u8 + 2 {
+            let handle = set.add(TcpSocket::new(id)).unwrap();
+            set.remove(handle).unwrap();
+        }
+        assert_eq!(set.lost_events(), 2);
+
+        // The oldest events were discarded.
+        match set.pop_event() {
+            Some(SetEvent::SocketRemoved { handle, .. }) => assert_eq!(handle.id(), 2),
+            event => panic!("unexpected event {:?}", event),
+        }
+        let mut remaining = 1;
+        while set.pop_event().is_some() {
+            remaining += 1;
+        }
+        assert_eq!(remaining, MAX_SET_EVENTS);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn teardown_order() {
+        let mut set = Set::<3, 64>::new();
+        set.add(TcpSocket::new(2)).unwrap();
+        set.add(UdpSocket::new(0)).unwrap();
+        set.add(TcpSocket::new(1)).unwrap();
+        set.remove(Handle::new(0)).unwrap();
+
+        let mut events: Vec<TeardownEvent, 4> = Vec::new();
+        set.teardown(|event| events.push(event).unwrap());
+
+        assert_eq!(
+            &events[..],
+            &[
+                TeardownEvent::Socket {
+                    handle: Handle::new(2),
+                    ty: SocketType::Tcp
+                },
+                TeardownEvent::Socket {
+                    handle: Handle::new(1),
+                    ty: SocketType::Tcp
+                },
+            ]
+        );
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn rebind_handle() {
+        let mut set = Set::<3, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        set.add(UdpSocket::new(1)).unwrap();
+
+        assert_eq!(
+            set.rebind_handle(Handle::new(0), Handle::new(1)),
+            Err(Error::DuplicateSocket)
+        );
+        assert_eq!(
+            set.rebind_handle(Handle::new(2), Handle::new(3)),
+            Err(Error::InvalidSocket)
+        );
+        assert_eq!(
+            set.rebind_handle(Handle::new(0), Handle::new(0)),
+            Ok(Handle::new(0))
+        );
+
+        assert_eq!(
+            set.rebind_handle(Handle::new(0), Handle::new(5)),
+            Ok(Handle::new(5))
+        );
+        assert_eq!(
+            set.get::<TcpSocket<64>>(Handle::new(0)).err(),
+            Some(Error::InvalidSocket)
+        );
+        assert_eq!(
+            set.get::<TcpSocket<64>>(Handle::new(5)).unwrap().handle(),
+            Handle::new(5)
+        );
+
+        // The old handle is free again.
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
+        assert_eq!(set.len(), 3);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
     #[test]
     fn prune_socket_set() {
         let mut set = Set::<2, 64>::new();
 
-        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle(0)));
+        assert_eq!(set.add(TcpSocket::new(0)), Ok(Handle::new(0)));
         assert_eq!(set.len(), 1);
-        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle(1)));
+        assert_eq!(set.add(UdpSocket::new(1)), Ok(Handle::new(1)));
         assert_eq!(set.len(), 2);
 
-        set.get::<TcpSocket<64>>(Handle(0))
+        set.get::<TcpSocket<64>>(Handle::new(0))
             .expect("failed to get tcp socket");
 
         set.prune();
         assert_eq!(set.len(), 0);
     }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn recycle_all_sockets() {
+        let mut set = Set::<4, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        for id in 1..4 {
+            set.add(UdpSocket::new(id)).unwrap();
+            set.get::<UdpSocket<64>>(Handle::new(id))
+                .unwrap()
+                .closed_by_remote(Instant::from_secs(0));
+        }
+
+        assert_eq!(set.recycle_all(Instant::from_secs(14)), 0);
+        assert_eq!(set.recycle_all(Instant::from_secs(15)), 3);
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.count(SocketType::Udp), 0);
+        assert_eq!(set.recycle_all(Instant::from_secs(30)), 0);
+    }
+
+    #[cfg(all(feature = "socket-tcp", feature = "socket-udp"))]
+    #[test]
+    fn recycle_closed_udp_socket() {
+        let mut set = Set::<2, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        set.add(UdpSocket::new(1)).unwrap();
+        set.get::<UdpSocket<64>>(Handle::new(1))
+            .unwrap()
+            .closed_by_remote(Instant::from_secs(0));
+
+        assert!(!set.recycle(Instant::from_secs(14)));
+        let socket = set.take_recycled(Instant::from_secs(15)).unwrap();
+        assert_eq!(socket.handle(), Handle::new(1));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_add_and_remove() {
+        let logged = |prefix: &str| {
+            crate::test_log::lines()
+                .iter()
+                .any(|line| line.starts_with(prefix))
+        };
+
+        crate::test_log::install();
+        let mut set = Set::<2, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        assert!(logged("[Socket Set] Adding: 0 Tcp"));
+
+        set.remove(Handle::new(0)).unwrap();
+        assert!(logged("[Socket Set] Removing socket! 0 Some(Tcp)"));
+    }
 }
@@ -1,19 +1,105 @@
-use super::{Error, Result, RingBuffer, Socket, SocketHandle, SocketMeta};
+use super::{
+    CloseReason, ConnectionStatus, Error, Result, RingBuffer, RxOverflow, SocketHandle, SocketMeta,
+};
+use core::task::Waker;
 use embassy_time::{Duration, Instant};
-use no_std_net::SocketAddr;
+use heapless::{Deque, String, Vec};
+use no_std_net::{SocketAddr, SocketAddrV4, SocketAddrV6};
 
 /// A TCP socket ring buffer.
 pub type SocketBuffer<const N: usize> = RingBuffer<u8, N>;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Maximum length of a hostname in a [`RemoteEndpoint`].
+pub const MAX_HOSTNAME_LEN: usize = 64;
+
+/// The remote endpoint of a TCP connection.
+///
+/// Hostnames are resolved by the module when connecting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteEndpoint {
+    Ip(SocketAddr),
+    Host(String<MAX_HOSTNAME_LEN>, u16),
+}
+
+impl RemoteEndpoint {
+    /// Create an endpoint from a hostname and a port.
+    ///
+    /// Returns `Err(Error::BadLength)` if the hostname is longer than
+    /// [`MAX_HOSTNAME_LEN`].
+    pub fn host(hostname: &str, port: u16) -> Result<Self> {
+        let mut host = String::new();
+        host.push_str(hostname).map_err(|_| Error::BadLength)?;
+        Ok(RemoteEndpoint::Host(host, port))
+    }
+
+    /// Return the IP address of the endpoint, if it is not a hostname.
+    pub fn ip(&self) -> Option<SocketAddr> {
+        match self {
+            RemoteEndpoint::Ip(addr) => Some(*addr),
+            RemoteEndpoint::Host(..) => None,
+        }
+    }
+
+    /// Return the port of the endpoint.
+    pub fn port(&self) -> u16 {
+        match self {
+            RemoteEndpoint::Ip(addr) => addr.port(),
+            RemoteEndpoint::Host(_, port) => *port,
+        }
+    }
+}
+
+impl From<SocketAddr> for RemoteEndpoint {
+    fn from(addr: SocketAddr) -> Self {
+        RemoteEndpoint::Ip(addr)
+    }
+}
+
+impl From<SocketAddrV4> for RemoteEndpoint {
+    fn from(addr: SocketAddrV4) -> Self {
+        RemoteEndpoint::Ip(addr.into())
+    }
+}
+
+impl From<SocketAddrV6> for RemoteEndpoint {
+    fn from(addr: SocketAddrV6) -> Self {
+        RemoteEndpoint::Ip(addr.into())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for RemoteEndpoint {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            RemoteEndpoint::Ip(addr) => defmt::write!(fmt, "{}", defmt::Debug2Format(addr)),
+            RemoteEndpoint::Host(host, port) => defmt::write!(fmt, "{}:{}", host.as_str(), port),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
 pub enum State {
     /// Freshly created, unsullied
+    #[default]
     Created,
-    WaitingForConnect(SocketAddr),
+    WaitingForConnect(RemoteEndpoint),
     /// TCP connected or UDP has an address
-    Connected(SocketAddr),
+    Connected(RemoteEndpoint),
     /// Block all writes (Socket is closed by remote)
     ShutdownForWrite(Instant),
+    /// Reads are shut down locally; received data is dropped
+    ShutdownForRead(RemoteEndpoint),
+    /// Both halves are shut down
+    Closed(Instant),
+}
+
+/// The half of a connection to shut down; see [`TcpSocket::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Shutdown {
+    Read,
+    Write,
+    Both,
 }
 
 #[cfg(feature = "defmt")]
@@ -24,46 +110,195 @@ impl defmt::Format for State {
             State::WaitingForConnect(_) => defmt::write!(fmt, "State::WaitingForConnect"),
             State::Connected(_) => defmt::write!(fmt, "State::Connected"),
             State::ShutdownForWrite(_) => defmt::write!(fmt, "State::ShutdownForWrite"),
+            State::ShutdownForRead(_) => defmt::write!(fmt, "State::ShutdownForRead"),
+            State::Closed(_) => defmt::write!(fmt, "State::Closed"),
+        }
+    }
+}
+
+/// Number of state-change events a TCP socket keeps until they are popped.
+const MAX_EVENTS: usize = 4;
+
+/// A state change of a TCP socket, for the application to observe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpEvent {
+    /// The connection was established.
+    Connected,
+    /// The connection was closed by the remote end at the given time.
+    ClosedByRemote(Instant),
+    /// The socket was recycled, after its read timeout expired.
+    Recycled,
+    /// The connection attempt failed.
+    ConnectFailed,
+    /// The connection was closed while the given number of bytes were
+    /// written to the module, but not yet acknowledged.
+    TxFailed(usize),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for TcpEvent {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            TcpEvent::Connected => defmt::write!(fmt, "TcpEvent::Connected"),
+            TcpEvent::ClosedByRemote(_) => defmt::write!(fmt, "TcpEvent::ClosedByRemote"),
+            TcpEvent::Recycled => defmt::write!(fmt, "TcpEvent::Recycled"),
+            TcpEvent::ConnectFailed => defmt::write!(fmt, "TcpEvent::ConnectFailed"),
+            TcpEvent::TxFailed(len) => defmt::write!(fmt, "TcpEvent::TxFailed({})", len),
         }
     }
 }
 
-impl Default for State {
+/// TCP keep-alive configuration, as applied to the socket on the module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    /// Time without activity before the first keep-alive probe is sent.
+    pub idle: Duration,
+    /// Time between subsequent keep-alive probes.
+    pub interval: Duration,
+    /// Number of unanswered probes before the connection is considered dead.
+    pub count: u8,
+}
+
+/// Configuration of a TCP socket, applied on construction with
+/// [`TcpSocket::with_config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpSocketConfig {
+    /// Interval between checks for data available on the module.
+    pub check_interval: Duration,
+    /// Time a socket closed by the remote end is kept for reading.
+    pub read_timeout: Option<Duration>,
+    /// Time a connection attempt may take.
+    pub connect_timeout: Option<Duration>,
+    /// Time a connection may go without traffic.
+    pub idle_timeout: Option<Duration>,
+    pub keep_alive: Option<KeepAlive>,
+    pub security_profile: Option<u8>,
+}
+
+impl Default for TcpSocketConfig {
     fn default() -> Self {
-        State::Created
+        TcpSocketConfig {
+            check_interval: Duration::from_secs(15),
+            read_timeout: Some(Duration::from_secs(15)),
+            connect_timeout: Some(Duration::from_secs(60)),
+            idle_timeout: None,
+            keep_alive: None,
+            security_profile: None,
+        }
     }
 }
 
+/// Traffic statistics of a TCP socket.
+#[cfg(feature = "stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TcpStats {
+    /// Bytes enqueued into the receive buffer.
+    pub rx_enqueued: usize,
+    /// Bytes dequeued from the receive buffer by the application.
+    pub rx_delivered: usize,
+    /// Number of times the connection was closed by the remote end.
+    pub remote_closes: u32,
+    /// Number of times the socket was connected again after its first connection.
+    pub reconnects: u32,
+}
+
 /// A Transmission Control Protocol socket.
 ///
 /// A TCP socket may passively listen for connections or actively connect to another endpoint.
 /// Note that, for listening sockets, there is no "backlog"; to be able to simultaneously
 /// accept several connections, as many sockets must be allocated, or any new connection
 /// attempts will be reset.
-#[derive(Debug)]
-pub struct TcpSocket<const L: usize> {
+///
+/// `L` is the size of the receive buffer, and `TXL` the size of the transmit
+/// buffer, which defaults to `L`.
+pub struct TcpSocket<const L: usize, const TXL: usize = L> {
     pub(crate) meta: SocketMeta,
     state: State,
+    local_endpoint: Option<SocketAddr>,
     check_interval: Duration,
+    poll_interval: Duration,
+    poll_backoff_max: Option<Duration>,
     read_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    connect_started: Option<Instant>,
+    connect_attempts: u8,
+    max_connect_attempts: Option<u8>,
+    next_retry_at: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    security_profile: Option<u8>,
+    close_reason: Option<CloseReason>,
     available_data: usize,
+    decrement_available_data: bool,
     rx_buffer: SocketBuffer<L>,
+    tx_buffer: SocketBuffer<TXL>,
+    tx_in_flight: usize,
+    tx_coalesce_min: usize,
+    tx_coalesce_delay: Duration,
+    tx_unsent_since: Option<Instant>,
     last_check_time: Option<Instant>,
+    deferred_check: bool,
+    dropped_bytes: usize,
+    keep_alive: Option<KeepAlive>,
+    last_activity: Option<Instant>,
+    activity_pending: bool,
+    last_keepalive: Option<Instant>,
+    rx_waker: Option<Waker>,
+    events: Deque<TcpEvent, MAX_EVENTS>,
+    #[cfg(feature = "stats")]
+    stats: TcpStats,
+    #[cfg(feature = "stats")]
+    has_connected: bool,
 }
 
-impl<const L: usize> TcpSocket<L> {
+impl<const L: usize, const TXL: usize> TcpSocket<L, TXL> {
     /// Create a socket using the given buffers.
-    pub fn new(socket_id: u8) -> TcpSocket<L> {
+    pub fn new(socket_id: u8) -> TcpSocket<L, TXL> {
+        Self::with_config(socket_id, TcpSocketConfig::default())
+    }
+
+    /// Create a socket with the given configuration.
+    pub fn with_config(socket_id: u8, config: TcpSocketConfig) -> TcpSocket<L, TXL> {
         TcpSocket {
             meta: SocketMeta {
-                handle: SocketHandle(socket_id),
+                handle: SocketHandle::new(socket_id),
+                module_id: Some(socket_id),
             },
             state: State::default(),
+            local_endpoint: None,
             rx_buffer: SocketBuffer::new(),
+            tx_buffer: SocketBuffer::new(),
+            tx_in_flight: 0,
+            tx_coalesce_min: 0,
+            tx_coalesce_delay: Duration::from_ticks(0),
+            tx_unsent_since: None,
             available_data: 0,
-            check_interval: Duration::from_secs(15),
-            read_timeout: Some(Duration::from_secs(15)),
+            decrement_available_data: false,
+            check_interval: config.check_interval,
+            poll_interval: config.check_interval,
+            poll_backoff_max: None,
+            read_timeout: config.read_timeout,
+            connect_timeout: config.connect_timeout,
+            connect_started: None,
+            connect_attempts: 0,
+            max_connect_attempts: None,
+            next_retry_at: None,
+            idle_timeout: config.idle_timeout,
+            security_profile: config.security_profile,
+            close_reason: None,
             last_check_time: None,
+            deferred_check: false,
+            dropped_bytes: 0,
+            keep_alive: config.keep_alive,
+            last_activity: None,
+            activity_pending: false,
+            last_keepalive: None,
+            rx_waker: None,
+            events: Deque::new(),
+            #[cfg(feature = "stats")]
+            stats: TcpStats::default(),
+            #[cfg(feature = "stats")]
+            has_connected: false,
         }
     }
 
@@ -72,79 +307,487 @@ impl<const L: usize> TcpSocket<L> {
         self.meta.handle
     }
 
+    #[deprecated(note = "the set assigns handles; use `set_module_id` to follow the module's id")]
     pub fn update_handle(&mut self, handle: SocketHandle) {
         debug!(
             "[TCP Socket] [{:?}] Updating handle {:?}",
             self.handle(),
             handle
         );
-        self.meta.update(handle)
+        self.meta.update(handle);
+        self.meta.module_id = Some(handle.id());
+    }
+
+    /// Return the socket id the module assigned to this socket, if any.
+    ///
+    /// This starts out as the id the socket was created with.
+    pub fn module_id(&self) -> Option<u8> {
+        self.meta.module_id
+    }
+
+    /// Set the socket id the module assigned to this socket, e.g. after the
+    /// module reassigned ids. The handle of the socket is left unchanged.
+    pub fn set_module_id(&mut self, module_id: Option<u8>) {
+        debug!(
+            "[TCP Socket] [{:?}] Updating module id {:?}",
+            self.handle(),
+            module_id
+        );
+        self.meta.module_id = module_id;
     }
 
     /// Return the bound endpoint.
-    pub fn endpoint(&self) -> Option<SocketAddr> {
+    ///
+    /// The endpoint is not kept once the transmit half is shut down, whether
+    /// by the remote end or locally with [`Shutdown::Write`], so this returns
+    /// `None` from then on.
+    pub fn endpoint(&self) -> Option<&RemoteEndpoint> {
         match self.state {
-            State::Connected(s) | State::WaitingForConnect(s) => Some(s),
+            State::Connected(ref s)
+            | State::WaitingForConnect(ref s)
+            | State::ShutdownForRead(ref s) => Some(s),
             _ => None,
         }
     }
 
+    /// Start connecting to the given remote endpoint.
+    ///
+    /// This function returns `Err(Error::Illegal)` if the socket is not in
+    /// `State::Created`.
+    pub fn connect<T: Into<RemoteEndpoint>>(&mut self, remote: T) -> Result<()> {
+        if self.state != State::Created {
+            return Err(Error::Illegal);
+        }
+
+        self.set_state(State::WaitingForConnect(remote.into()));
+        Ok(())
+    }
+
+    /// Return the local endpoint, if known.
+    pub fn local_endpoint(&self) -> Option<SocketAddr> {
+        self.local_endpoint
+    }
+
+    /// Return the local port, if known.
+    pub fn local_port(&self) -> Option<u16> {
+        self.local_endpoint.map(|endpoint| endpoint.port())
+    }
+
+    /// Set the local endpoint, e.g. the server address of a connection
+    /// accepted through a [`TcpListener`](crate::tcp_listener::TcpListener).
+    pub fn set_local_endpoint(&mut self, endpoint: Option<SocketAddr>) {
+        self.local_endpoint = endpoint;
+    }
+
     /// Return the connection state, in terms of the TCP state machine.
     pub fn state(&self) -> &State {
         &self.state
     }
 
+    /// Return the connection status, as seen by the application.
+    pub fn connection_status(&self) -> ConnectionStatus {
+        match self.state {
+            State::Created | State::WaitingForConnect(_) => ConnectionStatus::Connecting,
+            State::Connected(_) | State::ShutdownForRead(_) => ConnectionStatus::Connected,
+            State::ShutdownForWrite(_) => ConnectionStatus::Disconnected {
+                reason: self.close_reason.or(Some(CloseReason::RemoteFin)),
+            },
+            State::Closed(_) => ConnectionStatus::Disconnected {
+                reason: self.close_reason,
+            },
+        }
+    }
+
+    /// Return the socket to `State::Created`, ready to be reused for a new
+    /// connection.
+    ///
+    /// The receive buffer and all connection specific bookkeeping are
+    /// cleared, while the handle, the configured timeouts and intervals, and
+    /// any unpopped events and statistics are kept. The security profile is
+    /// cleared, as it belongs to the connection.
     pub fn reset(&mut self) {
         self.set_state(State::default());
+        self.connect_started = None;
+        self.connect_attempts = 0;
+        self.next_retry_at = None;
+        self.security_profile = None;
+        self.close_reason = None;
+        self.local_endpoint = None;
+        self.last_activity = None;
+        self.activity_pending = false;
+        self.last_keepalive = None;
         self.rx_buffer.clear();
+        self.tx_buffer.clear();
+        self.tx_in_flight = 0;
+        self.tx_unsent_since = None;
         self.set_available_data(0);
         self.last_check_time = None;
+        self.deferred_check = false;
+        self.poll_interval = self.check_interval;
+    }
+
+    /// Set the security profile (`+USECPRF`) the socket is bound to, making it
+    /// a TLS socket once connected.
+    pub fn set_security_profile(&mut self, profile: Option<u8>) {
+        self.security_profile = profile;
+    }
+
+    /// Return the security profile the socket is bound to, if any.
+    pub fn security_profile(&self) -> Option<u8> {
+        self.security_profile
+    }
+
+    /// Set the keep-alive configuration of the socket.
+    pub fn set_keep_alive(&mut self, keep_alive: Option<KeepAlive>) {
+        self.keep_alive = keep_alive;
+    }
+
+    /// Return the keep-alive configuration of the socket.
+    pub fn keep_alive(&self) -> Option<KeepAlive> {
+        self.keep_alive
     }
 
-    pub fn should_update_available_data(&mut self) -> bool {
+    /// Record that data was exchanged with the remote endpoint.
+    ///
+    /// The activity is timestamped by the next call taking the current time,
+    /// e.g. [should_send_keepalive](#method.should_send_keepalive).
+    fn mark_activity(&mut self) {
+        self.activity_pending = true;
+    }
+
+    /// Record that the connection was active at `ts`, e.g. when it was just
+    /// accepted by a [`TcpListener`](crate::TcpListener).
+    #[cfg(feature = "socket-tcp")]
+    pub(crate) fn mark_activity_at(&mut self, ts: Instant) {
+        self.activity_pending = false;
+        self.last_activity = Some(ts);
+    }
+
+    /// Return the time of the last recorded activity, timestamping any
+    /// activity recorded since the previous call with `ts`.
+    fn last_activity(&mut self, ts: Instant) -> Option<Instant> {
+        if core::mem::take(&mut self.activity_pending) {
+            self.last_activity = Some(ts);
+        }
+        self.last_activity
+    }
+
+    /// Check whether a keep-alive should be sent on the connection.
+    ///
+    /// This returns true once the connection has been idle for the configured
+    /// [`KeepAlive::idle`] period, and again every [`KeepAlive::interval`]
+    /// for as long as it stays idle.
+    pub fn should_send_keepalive(&mut self, ts: Instant) -> bool {
+        let keep_alive = match self.keep_alive {
+            Some(keep_alive) if self.is_connected() => keep_alive,
+            _ => return false,
+        };
+
+        let last_activity = match self.last_activity(ts) {
+            Some(last_activity) => last_activity,
+            None => {
+                self.last_activity = Some(ts);
+                return false;
+            }
+        };
+
+        let idle = ts
+            .checked_duration_since(last_activity)
+            .map(|dur| dur >= keep_alive.idle)
+            .unwrap_or(false);
+        if !idle {
+            return false;
+        }
+
+        let due = self
+            .last_keepalive
+            .filter(|&sent| sent >= last_activity)
+            .and_then(|sent| ts.checked_duration_since(sent))
+            .map(|dur| dur >= keep_alive.interval)
+            .unwrap_or(true);
+        if due {
+            self.last_keepalive = Some(ts);
+        }
+        due
+    }
+
+    /// Enable exponential backoff of the available-data polling interval.
+    ///
+    /// Every check that finds no data available doubles the interval, up to
+    /// `max`. Any incoming data resets it to `base`.
+    pub fn set_poll_backoff(&mut self, base: Duration, max: Duration) {
+        self.check_interval = base;
+        self.poll_interval = base;
+        self.poll_backoff_max = Some(max.max(base));
+    }
+
+    /// Return the interval currently used between available-data checks.
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    pub fn should_update_available_data(&mut self, ts: Instant) -> bool {
         // Cannot request available data on a socket that is closed by the
-        // module
-        if !self.is_connected() {
+        // module. Data for a socket with reads shut down is still read, to be
+        // dropped.
+        if !matches!(self.state, State::Connected(_) | State::ShutdownForRead(_)) {
             return false;
         }
 
-        let ts = Instant::now();
+        // Data read now could not be stored, so hold off until the
+        // application has made room, and check right away once it has.
+        if self.rx_buffer.is_full() {
+            self.deferred_check = true;
+            return false;
+        }
 
-        let should_update = self
-            .last_check_time
-            .and_then(|last_check_time| ts.checked_duration_since(last_check_time))
-            .map(|dur| dur >= self.check_interval)
-            .unwrap_or(true);
+        let should_update = core::mem::take(&mut self.deferred_check)
+            || self
+                .last_check_time
+                .and_then(|last_check_time| ts.checked_duration_since(last_check_time))
+                .map(|dur| dur >= self.poll_interval)
+                .unwrap_or(true);
 
-        if should_update {
-            self.last_check_time.replace(ts);
+        if should_update && self.last_check_time.replace(ts).is_some() && self.available_data == 0 {
+            self.back_off_poll_interval();
         }
 
         should_update
     }
 
-    pub fn recycle(&self) -> bool {
-        if let Some(read_timeout) = self.read_timeout {
-            match self.state {
-                State::Created | State::WaitingForConnect(_) | State::Connected(_) => false,
-                State::ShutdownForWrite(closed_time) => Instant::now()
-                    .checked_duration_since(closed_time)
-                    .map(|dur| dur >= read_timeout)
-                    .unwrap_or(false),
+    /// Return the time of the last check for available data, if any.
+    pub fn last_check_time(&self) -> Option<Instant> {
+        self.last_check_time
+    }
+
+    /// Return how long until [should_update_available_data](#method.should_update_available_data)
+    /// next returns true, or `None` if it will not until the state of the
+    /// socket changes; e.g. when not connected, or while the receive buffer
+    /// is full.
+    pub fn time_until_next_check(&self, ts: Instant) -> Option<Duration> {
+        if !matches!(self.state, State::Connected(_) | State::ShutdownForRead(_))
+            || self.rx_buffer.is_full()
+        {
+            return None;
+        }
+        if self.deferred_check {
+            return Some(Duration::from_ticks(0));
+        }
+
+        match self.last_check_time {
+            Some(last_check_time) => last_check_time.checked_add(self.poll_interval).map(|due| {
+                due.checked_duration_since(ts)
+                    .unwrap_or(Duration::from_ticks(0))
+            }),
+            None => Some(Duration::from_ticks(0)),
+        }
+    }
+
+    fn back_off_poll_interval(&mut self) {
+        if let Some(max) = self.poll_backoff_max {
+            self.poll_interval = self
+                .poll_interval
+                .checked_mul(2)
+                .map_or(max, |interval| interval.min(max));
+        }
+    }
+
+    /// Set the time a socket closed by the remote end is kept for reading
+    /// before it is recycled, or `None` to keep it. Defaults to 15 seconds.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Set the time a connection attempt may take before the socket is
+    /// recycled, or `None` to wait forever. Defaults to 60 seconds.
+    pub fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+
+    /// Return the connect timeout of the socket.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Set the time a connection may go without traffic before the socket is
+    /// recycled, or `None` to keep idle connections. Defaults to `None`.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Return the idle timeout of the socket.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Record a failed connection attempt at `ts`, allowing a retry once
+    /// `backoff` has passed.
+    pub fn record_connect_failure(&mut self, ts: Instant, backoff: Duration) {
+        self.connect_attempts = self.connect_attempts.saturating_add(1);
+        self.next_retry_at = ts.checked_add(backoff);
+    }
+
+    /// Return the number of failed connection attempts since the socket was
+    /// last connected.
+    pub fn connect_attempts(&self) -> u8 {
+        self.connect_attempts
+    }
+
+    /// Set the number of failed connection attempts after which the socket is
+    /// recycled instead of retried, or `None` to retry forever. Defaults to
+    /// `None`.
+    pub fn set_max_connect_attempts(&mut self, max: Option<u8>) {
+        self.max_connect_attempts = max;
+    }
+
+    fn connect_attempts_exhausted(&self) -> bool {
+        self.max_connect_attempts
+            .map(|max| self.connect_attempts >= max)
+            .unwrap_or(false)
+    }
+
+    /// Return whether a failed connection should be attempted again now.
+    pub fn should_retry_connect(&self, ts: Instant) -> bool {
+        self.state == State::Created
+            && !self.connect_attempts_exhausted()
+            && self.next_retry_at.map(|at| ts >= at).unwrap_or(false)
+    }
+
+    /// Return whether the socket should be removed from its set.
+    ///
+    /// This is the case once the read timeout has elapsed after the remote
+    /// end closed the connection, or once the connect timeout has elapsed
+    /// while waiting for a connection, or once the idle timeout has elapsed
+    /// without traffic on a connected socket. As state changes carry no
+    /// timestamp, these timeouts run from the first call after the socket
+    /// entered the state.
+    pub fn recycle(&mut self, ts: Instant) -> bool {
+        let elapsed = |since: Instant, timeout: Option<Duration>| match timeout {
+            Some(timeout) => ts
+                .checked_duration_since(since)
+                .map(|dur| dur >= timeout)
+                .unwrap_or(false),
+            None => false,
+        };
+
+        let (recycle, reason) = match self.state {
+            State::Created => (
+                self.connect_attempts_exhausted(),
+                Some(CloseReason::ConnectFailed),
+            ),
+            State::Connected(_) | State::ShutdownForRead(_) => match self.last_activity(ts) {
+                Some(last_activity) => (
+                    elapsed(last_activity, self.idle_timeout),
+                    Some(CloseReason::IdleTimeout),
+                ),
+                None => (false, None),
+            },
+            State::WaitingForConnect(_) => {
+                let started = *self.connect_started.get_or_insert(ts);
+                (
+                    elapsed(started, self.connect_timeout),
+                    Some(CloseReason::ConnectTimeout),
+                )
             }
-        } else {
-            false
+            State::ShutdownForWrite(closed_time) | State::Closed(closed_time) => (
+                elapsed(closed_time, self.read_timeout),
+                Some(CloseReason::ReadTimeout),
+            ),
+        };
+
+        if recycle {
+            self.set_close_reason(reason);
         }
+        recycle
     }
 
-    pub fn closed_by_remote(&mut self) {
-        self.set_state(State::ShutdownForWrite(Instant::now()));
+    /// Return the reason the connection was closed, if it was.
+    ///
+    /// The first reason recorded after the socket left `Connected` is kept
+    /// until the socket is reset, or connects again.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+
+    fn set_close_reason(&mut self, reason: Option<CloseReason>) {
+        if self.close_reason.is_none() {
+            self.close_reason = reason;
+        }
+    }
+
+    /// Record that the remote end closed the connection at `ts`.
+    pub fn closed_by_remote(&mut self, ts: Instant) {
+        self.set_state(State::ShutdownForWrite(ts));
+        self.push_event(TcpEvent::ClosedByRemote(ts));
+        self.set_close_reason(Some(CloseReason::RemoteFin));
+        let in_flight = core::mem::take(&mut self.tx_in_flight);
+        if in_flight > 0 {
+            self.push_event(TcpEvent::TxFailed(in_flight));
+        }
         self.set_available_data(0);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.remote_closes += 1;
+        }
+        self.wake_rx();
+    }
+
+    /// Pop the oldest state-change event of the socket.
+    ///
+    /// Only the last few events are kept; older ones are discarded when
+    /// they are not popped in time.
+    pub fn pop_event(&mut self) -> Option<TcpEvent> {
+        self.events.pop_front()
+    }
+
+    pub(crate) fn push_event(&mut self, event: TcpEvent) {
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        self.events.push_back(event).ok();
+    }
+
+    /// Register a waker to be woken when data is received, or the connection
+    /// is closed by the remote end.
+    ///
+    /// Only one waker is stored; registering a new one replaces the previous
+    /// one. The waker is consumed when woken, so it has to be registered again
+    /// on every poll.
+    pub fn register_rx_waker(&mut self, waker: &Waker) {
+        match self.rx_waker {
+            Some(ref w) if w.will_wake(waker) => {}
+            _ => self.rx_waker = Some(waker.clone()),
+        }
+    }
+
+    fn wake_rx(&mut self) {
+        if let Some(waker) = self.rx_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Return the traffic statistics of the socket.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> TcpStats {
+        self.stats
+    }
+
+    /// Reset the traffic statistics of the socket.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = TcpStats::default();
     }
 
     /// Set available data.
     pub fn set_available_data(&mut self, available_data: usize) {
+        let was_empty = self.available_data == 0;
         self.available_data = available_data;
+        if available_data > 0 {
+            self.poll_interval = self.check_interval;
+            if was_empty {
+                self.wake_rx();
+            }
+        }
     }
 
     /// Get the number of bytes available to ingress.
@@ -152,6 +795,24 @@ impl<const L: usize> TcpSocket<L> {
         self.available_data
     }
 
+    /// Make [rx_enqueue_slice](#method.rx_enqueue_slice) subtract the data it
+    /// is given from the available data, so that
+    /// [get_available_data](#method.get_available_data) reflects what is
+    /// still pending on the module between polls. Dropped bytes count too, as
+    /// they were read from the module all the same. Defaults to `false`.
+    pub fn set_decrement_available_data(&mut self, enabled: bool) {
+        self.decrement_available_data = enabled;
+    }
+
+    /// Return the number of bytes to request in the next read from the
+    /// module, bounded by the data available on the module, the free space in
+    /// the receive buffer, and the per-command limit of the AT interface.
+    pub fn max_read_len(&self, at_chunk_limit: usize) -> usize {
+        self.available_data
+            .min(self.rx_window())
+            .min(at_chunk_limit)
+    }
+
     /// Return whether a connection is active.
     ///
     /// This function returns true if the socket is actively exchanging packets
@@ -174,12 +835,69 @@ impl<const L: usize> TcpSocket<L> {
     pub fn may_recv(&self) -> bool {
         match self.state {
             State::Connected(_) | State::ShutdownForWrite(_) => true,
+            State::ShutdownForRead(_) | State::Closed(_) => false,
             // If we have something in the receive buffer, we can receive that.
             _ if !self.rx_buffer.is_empty() => true,
             _ => false,
         }
     }
 
+    /// Return whether the transmit half of the full-duplex connection is open.
+    ///
+    /// This function returns true if it's possible to send data to the remote
+    /// endpoint, which means the socket must be `Connected`, or have only its
+    /// receive half shut down.
+    pub fn may_send(&self) -> bool {
+        matches!(self.state, State::Connected(_) | State::ShutdownForRead(_))
+    }
+
+    /// Check whether the transmit half of the connection is open (see
+    /// [may_send](#method.may_send)), and the transmit buffer is not full.
+    pub fn can_send(&self) -> bool {
+        self.may_send() && self.tx_buffer.window() > 0
+    }
+
+    /// Shut down one or both halves of the connection at `ts`.
+    ///
+    /// Once reads are shut down, buffered data is discarded and received data
+    /// is dropped, and counted; see [take_dropped_bytes](#method.take_dropped_bytes).
+    /// Once writes are shut down, the remote endpoint is forgotten; see
+    /// [endpoint](#method.endpoint).
+    /// Once both halves are shut down, the socket times out like a socket
+    /// closed by the remote end; see [recycle](#method.recycle).
+    ///
+    /// This function returns `Err(Error::Illegal)` if the socket is not
+    /// connected.
+    pub fn shutdown(&mut self, how: Shutdown, ts: Instant) -> Result<()> {
+        let read = matches!(how, Shutdown::Read | Shutdown::Both);
+        let write = matches!(how, Shutdown::Write | Shutdown::Both);
+
+        let state = match self.state {
+            State::Connected(ref endpoint) => match (read, write) {
+                (true, true) => State::Closed(ts),
+                (true, false) => State::ShutdownForRead(endpoint.clone()),
+                _ => State::ShutdownForWrite(ts),
+            },
+            // Already shut down as requested
+            State::ShutdownForRead(_) if !write => return Ok(()),
+            State::ShutdownForWrite(_) if !read => return Ok(()),
+            State::Closed(_) => return Ok(()),
+            State::ShutdownForRead(_) | State::ShutdownForWrite(_) => State::Closed(ts),
+            State::Created | State::WaitingForConnect(_) => return Err(Error::Illegal),
+        };
+
+        if matches!(self.state, State::Connected(_)) {
+            self.set_close_reason(Some(CloseReason::LocalClose));
+        }
+        if read {
+            let buffered = self.rx_buffer.len();
+            self.dropped_bytes = self.dropped_bytes.saturating_add(buffered);
+            self.rx_buffer.clear();
+        }
+        self.set_state(state);
+        Ok(())
+    }
+
     /// Check whether the receive half of the full-duplex connection buffer is open
     /// (see [may_recv](#method.may_recv), and the receive buffer is not full.
     pub fn can_recv(&self) -> bool {
@@ -190,6 +908,11 @@ impl<const L: usize> TcpSocket<L> {
         !self.rx_buffer.is_full()
     }
 
+    /// Check whether `len` more bytes can be received whole.
+    pub fn can_recv_bytes(&self, len: usize) -> bool {
+        self.may_recv() && self.rx_buffer.window() >= len
+    }
+
     fn recv_impl<'b, F, R>(&'b mut self, f: F) -> Result<R>
     where
         F: FnOnce(&'b mut SocketBuffer<L>) -> (usize, R),
@@ -202,6 +925,14 @@ impl<const L: usize> TcpSocket<L> {
         }
 
         let (_size, result) = f(&mut self.rx_buffer);
+        // Not `mark_activity`, as `rx_buffer` is still borrowed by `result`
+        if _size > 0 {
+            self.activity_pending = true;
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.rx_delivered += _size;
+        }
         Ok(result)
     }
 
@@ -251,6 +982,59 @@ impl<const L: usize> TcpSocket<L> {
         })
     }
 
+    /// Dequeue received data into the remaining capacity of the given vector,
+    /// and return the amount of octets appended.
+    ///
+    /// See also [recv](#method.recv).
+    pub fn recv_into_vec<const N: usize>(&mut self, out: &mut Vec<u8, N>) -> Result<usize> {
+        self.recv_impl(|rx_buffer| {
+            let size = rx_buffer.dequeue_into_vec(out);
+            (size, size)
+        })
+    }
+
+    /// Dequeue exactly `data.len()` received octets into `data`.
+    ///
+    /// If fewer octets are queued, nothing is dequeued and
+    /// `Err(Error::Exhausted)` is returned.
+    ///
+    /// See also [recv_slice](#method.recv_slice).
+    pub fn recv_exact(&mut self, data: &mut [u8]) -> Result<()> {
+        self.recv_exact_with(data.len(), |a, b| {
+            let (head, tail) = data.split_at_mut(a.len());
+            head.copy_from_slice(a);
+            if let Some(b) = b {
+                tail.copy_from_slice(b);
+            }
+        })
+    }
+
+    /// Call `f` with exactly `len` received octets, and dequeue them.
+    ///
+    /// If the octets wrap around the end of the buffer, the second argument
+    /// of `f` will be `Some()` with the remainder. If fewer than `len` octets
+    /// are queued, `f` is not called, nothing is dequeued and
+    /// `Err(Error::Exhausted)` is returned.
+    ///
+    /// This function returns `Err(Error::Illegal) if the receive half of the
+    /// connection is not open; see [may_recv](#method.may_recv).
+    pub fn recv_exact_with<'b, F, R>(&'b mut self, len: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(&'b [u8], Option<&'b [u8]>) -> R,
+    {
+        if self.may_recv() && self.recv_queue() < len {
+            return Err(Error::Exhausted);
+        }
+
+        self.recv_impl(|rx_buffer| {
+            rx_buffer.dequeue_many_with_wrapping(|a, b| {
+                let a_len = a.len().min(len);
+                let b = b.map(|b| &b[..len - a_len]).filter(|_| a_len < len);
+                (len, f(&a[..a_len], b))
+            })
+        })
+    }
+
     /// Peek at a sequence of received octets without removing them from
     /// the receive buffer, and return a pointer to it.
     ///
@@ -279,8 +1063,153 @@ impl<const L: usize> TcpSocket<L> {
         Ok(buffer.len())
     }
 
+    /// Enqueue received data into the receive buffer, and return the number of
+    /// bytes that fit.
+    ///
+    /// Bytes that did not fit are dropped, and counted; see
+    /// [take_dropped_bytes](#method.take_dropped_bytes).
     pub fn rx_enqueue_slice(&mut self, data: &[u8]) -> usize {
-        self.rx_buffer.enqueue_slice(data)
+        if self.decrement_available_data {
+            self.available_data = self.available_data.saturating_sub(data.len());
+        }
+
+        if matches!(self.state, State::ShutdownForRead(_) | State::Closed(_)) {
+            self.dropped_bytes = self.dropped_bytes.saturating_add(data.len());
+            return 0;
+        }
+
+        self.poll_interval = self.check_interval;
+        if !data.is_empty() {
+            self.mark_activity();
+        }
+        let stored = self.rx_buffer.enqueue_slice(data);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.rx_enqueued += stored;
+        }
+        self.dropped_bytes = self.dropped_bytes.saturating_add(data.len() - stored);
+        if stored > 0 {
+            self.wake_rx();
+        }
+        stored
+    }
+
+    /// Enqueue received data into the receive buffer, and return an error if
+    /// not all of it fit.
+    ///
+    /// See also [rx_enqueue_slice](#method.rx_enqueue_slice).
+    pub fn rx_enqueue_slice_checked(
+        &mut self,
+        data: &[u8],
+    ) -> core::result::Result<usize, RxOverflow> {
+        let stored = self.rx_enqueue_slice(data);
+        if stored < data.len() {
+            return Err(RxOverflow {
+                stored,
+                dropped: data.len() - stored,
+            });
+        }
+        Ok(stored)
+    }
+
+    /// Return the number of received bytes dropped because the receive buffer
+    /// was full, and reset the count.
+    pub fn take_dropped_bytes(&mut self) -> usize {
+        core::mem::take(&mut self.dropped_bytes)
+    }
+
+    /// Enqueue data to be sent into the transmit buffer, and return the number
+    /// of bytes that fit.
+    ///
+    /// This function returns `Err(Error::Illegal)` if the transmit half of
+    /// the connection is not open; see [may_send](#method.may_send).
+    pub fn send_slice(&mut self, data: &[u8]) -> Result<usize> {
+        if !self.may_send() {
+            return Err(Error::Illegal);
+        }
+
+        Ok(self.tx_buffer.enqueue_slice(data))
+    }
+
+    /// Return the amount of octets queued in the transmit buffer, including
+    /// those in flight.
+    pub fn send_queue(&self) -> usize {
+        self.tx_buffer.len()
+    }
+
+    /// Return the amount of octets written to the module, but not yet
+    /// acknowledged.
+    pub fn tx_in_flight(&self) -> usize {
+        self.tx_in_flight
+    }
+
+    /// Mark up to `len` queued bytes as being written to the module, and
+    /// return them.
+    ///
+    /// The bytes stay in the transmit buffer until acknowledged with
+    /// [tx_ack](#method.tx_ack). Only one write may be outstanding per
+    /// socket; this function returns `Err(Error::Illegal)` while bytes are in
+    /// flight, and `Err(Error::Exhausted)` if nothing is queued.
+    pub fn tx_mark_sent(&mut self, len: usize) -> Result<&[u8]> {
+        if self.tx_in_flight > 0 {
+            return Err(Error::Illegal);
+        }
+        if self.tx_buffer.is_empty() {
+            return Err(Error::Exhausted);
+        }
+
+        self.tx_unsent_since = None;
+        let data = self.tx_buffer.get_allocated(0, len);
+        self.tx_in_flight = data.len();
+        Ok(data)
+    }
+
+    /// Coalesce small writes: only flush the transmit buffer once at least
+    /// `min_bytes` are queued, or `max_delay` has passed since the first
+    /// unsent byte was seen. A `min_bytes` of 0 disables coalescing.
+    ///
+    /// See [should_flush_tx](#method.should_flush_tx).
+    pub fn set_tx_coalesce(&mut self, min_bytes: usize, max_delay: Duration) {
+        self.tx_coalesce_min = min_bytes;
+        self.tx_coalesce_delay = max_delay;
+    }
+
+    /// Return whether queued data should be written to the module now.
+    ///
+    /// As writes carry no timestamp, the coalescing delay runs from the first
+    /// call that sees unsent data. Nothing is flushed while a write is in
+    /// flight.
+    pub fn should_flush_tx(&mut self, ts: Instant) -> bool {
+        if self.tx_in_flight > 0 || !self.may_send() {
+            return false;
+        }
+
+        let unsent = self.tx_buffer.len();
+        if unsent == 0 {
+            self.tx_unsent_since = None;
+            return false;
+        }
+
+        let since = *self.tx_unsent_since.get_or_insert(ts);
+        // A full buffer cannot grow any further
+        unsent >= core::cmp::min(self.tx_coalesce_min, TXL)
+            || ts
+                .checked_duration_since(since)
+                .map(|dur| dur >= self.tx_coalesce_delay)
+                .unwrap_or(false)
+    }
+
+    /// Acknowledge that the module accepted `len` of the bytes in flight.
+    ///
+    /// Accepted bytes are removed from the transmit buffer; the remainder of
+    /// the write stays queued, to be sent again.
+    pub fn tx_ack(&mut self, len: usize) {
+        let len = core::cmp::min(len, self.tx_in_flight);
+        self.tx_buffer.dequeue_allocated(len);
+        self.tx_in_flight = 0;
+        if len > 0 {
+            self.mark_activity();
+        }
     }
 
     /// Return the amount of octets queued in the receive buffer.
@@ -297,12 +1226,973 @@ impl<const L: usize> TcpSocket<L> {
             self.state,
             state
         );
+        if matches!(
+            (&self.state, &state),
+            (State::WaitingForConnect(_), State::Created)
+        ) {
+            self.push_event(TcpEvent::ConnectFailed);
+        }
+        if !matches!(state, State::WaitingForConnect(_)) {
+            self.connect_started = None;
+        }
+        if matches!(state, State::WaitingForConnect(_) | State::Connected(_)) {
+            self.close_reason = None;
+        }
+        if matches!(state, State::Connected(_)) {
+            self.connect_attempts = 0;
+            self.next_retry_at = None;
+            self.push_event(TcpEvent::Connected);
+            self.mark_activity();
+            #[cfg(feature = "stats")]
+            {
+                if core::mem::replace(&mut self.has_connected, true) {
+                    self.stats.reconnects += 1;
+                }
+            }
+        }
         self.state = state
     }
 }
 
-impl<const L: usize> Into<Socket<L>> for TcpSocket<L> {
-    fn into(self) -> Socket<L> {
-        Socket::Tcp(self)
+#[cfg(feature = "defmt")]
+impl<const L: usize, const TXL: usize> defmt::Format for TcpSocket<L, TXL> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "[{:?}, {:?}, security_profile: {:?}],",
+            self.handle(),
+            self.state(),
+            self.security_profile()
+        )
+    }
+}
+
+impl<const L: usize, const TXL: usize> core::fmt::Debug for TcpSocket<L, TXL> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TcpSocket")
+            .field("handle", &self.handle())
+            .field("state", &self.state)
+            .field("available_data", &self.available_data)
+            .field("recv_queue", &self.recv_queue())
+            .field("send_queue", &self.send_queue())
+            .field("tx_in_flight", &self.tx_in_flight)
+            .field("read_timeout", &self.read_timeout)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "socket-tcp")]
+impl<const L: usize, const TXL: usize> From<TcpSocket<L, TXL>> for crate::Socket<L, TXL> {
+    fn from(socket: TcpSocket<L, TXL>) -> Self {
+        crate::Socket::Tcp(socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "socket-tcp")]
+    use crate::Socket;
+    use no_std_net::{Ipv4Addr, SocketAddrV4};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    fn remote() -> RemoteEndpoint {
+        SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080).into()
+    }
+
+    fn connected_socket() -> TcpSocket<64> {
+        let mut socket = TcpSocket::new(0);
+        socket.set_state(State::Connected(remote()));
+        socket
+    }
+
+    #[test]
+    fn decrement_available_data() {
+        let mut socket = connected_socket();
+        socket.set_available_data(10);
+        socket.rx_enqueue_slice(b"abc");
+        assert_eq!(socket.get_available_data(), 10);
+
+        socket.set_decrement_available_data(true);
+        socket.rx_enqueue_slice(b"abc");
+        assert_eq!(socket.get_available_data(), 7);
+        socket.rx_enqueue_slice(b"abcdefgh");
+        assert_eq!(socket.get_available_data(), 0);
+    }
+
+    #[test]
+    fn connection_status_for_every_state() {
+        let cases = [
+            (State::Created, ConnectionStatus::Connecting),
+            (
+                State::WaitingForConnect(remote()),
+                ConnectionStatus::Connecting,
+            ),
+            (State::Connected(remote()), ConnectionStatus::Connected),
+            (
+                State::ShutdownForWrite(Instant::from_secs(0)),
+                ConnectionStatus::Disconnected {
+                    reason: Some(CloseReason::RemoteFin),
+                },
+            ),
+            (
+                State::ShutdownForRead(remote()),
+                ConnectionStatus::Connected,
+            ),
+            (
+                State::Closed(Instant::from_secs(0)),
+                ConnectionStatus::Disconnected { reason: None },
+            ),
+        ];
+
+        for (state, status) in cases {
+            let mut socket = TcpSocket::<64>::new(0);
+            socket.set_state(state);
+            assert_eq!(socket.connection_status(), status);
+            #[cfg(feature = "socket-tcp")]
+            {
+                let socket: Socket<64> = socket.into();
+                assert_eq!(socket.connection_status(), status);
+            }
+        }
+    }
+
+    #[test]
+    fn write_shutdown_forgets_endpoint() {
+        let mut socket = connected_socket();
+        socket
+            .shutdown(Shutdown::Read, Instant::from_secs(0))
+            .unwrap();
+        assert_eq!(socket.endpoint(), Some(&remote()));
+
+        let mut socket = connected_socket();
+        socket
+            .shutdown(Shutdown::Write, Instant::from_secs(0))
+            .unwrap();
+        assert_eq!(socket.endpoint(), None);
+        assert_eq!(
+            socket.connection_status(),
+            ConnectionStatus::Disconnected {
+                reason: Some(CloseReason::LocalClose)
+            }
+        );
+    }
+
+    #[test]
+    fn fixed_poll_interval() {
+        let mut socket = connected_socket();
+
+        assert!(socket.should_update_available_data(Instant::from_secs(0)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(14)));
+        assert!(socket.should_update_available_data(Instant::from_secs(15)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(29)));
+        assert!(socket.should_update_available_data(Instant::from_secs(30)));
+        assert_eq!(socket.poll_interval(), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn poll_backoff_quiet_then_burst() {
+        let mut socket = connected_socket();
+        socket.set_poll_backoff(Duration::from_secs(1), Duration::from_secs(4));
+
+        let quiet = [
+            (0, true),
+            (1, true),
+            (2, false),
+            (3, true),
+            (5, false),
+            (7, true),
+            (10, false),
+            (11, true),
+        ];
+        for (secs, expected) in quiet {
+            assert_eq!(
+                socket.should_update_available_data(Instant::from_secs(secs)),
+                expected,
+                "at {}s",
+                secs
+            );
+        }
+        assert_eq!(socket.poll_interval(), Duration::from_secs(4));
+
+        socket.set_available_data(100);
+        assert_eq!(socket.poll_interval(), Duration::from_secs(1));
+
+        let burst = [(12, true), (13, true), (14, true)];
+        for (secs, expected) in burst {
+            assert_eq!(
+                socket.should_update_available_data(Instant::from_secs(secs)),
+                expected,
+                "at {}s",
+                secs
+            );
+        }
+        assert_eq!(socket.poll_interval(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn no_polling_while_rx_full() {
+        let mut socket = TcpSocket::<4>::new(0);
+        socket.set_state(State::Connected(remote()));
+
+        assert!(socket.should_update_available_data(Instant::from_secs(0)));
+        assert_eq!(socket.rx_enqueue_slice(b"abcd"), 4);
+
+        assert!(!socket.should_update_available_data(Instant::from_secs(15)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(30)));
+
+        let mut data = [0; 1];
+        assert_eq!(socket.recv_slice(&mut data), Ok(1));
+        assert!(socket.should_update_available_data(Instant::from_secs(31)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(32)));
+        assert!(socket.should_update_available_data(Instant::from_secs(46)));
+    }
+
+    #[test]
+    fn recv_exact_across_wrap() {
+        let mut socket = TcpSocket::<8>::new(0);
+        socket.set_state(State::Connected(remote()));
+
+        assert_eq!(socket.rx_enqueue_slice(b"abcdef"), 6);
+        let mut header = [0; 4];
+        assert_eq!(socket.recv_exact(&mut header), Ok(()));
+        assert_eq!(&header, b"abcd");
+
+        assert_eq!(socket.rx_enqueue_slice(b"ghijk"), 5);
+
+        let mut payload = [0; 8];
+        assert_eq!(socket.recv_exact(&mut payload), Err(Error::Exhausted));
+        assert_eq!(socket.recv_queue(), 7);
+
+        let mut payload = [0; 5];
+        assert_eq!(socket.recv_exact(&mut payload), Ok(()));
+        assert_eq!(&payload, b"efghi");
+        assert_eq!(socket.recv_queue(), 2);
+
+        assert_eq!(
+            socket.recv_exact_with(2, |a, b| {
+                assert_eq!(a, b"jk");
+                assert_eq!(b, None);
+            }),
+            Ok(())
+        );
+        assert_eq!(socket.recv_queue(), 0);
+    }
+
+    #[test]
+    fn recv_exact_with_wrapped() {
+        let mut socket = TcpSocket::<8>::new(0);
+        socket.set_state(State::Connected(remote()));
+
+        socket.rx_enqueue_slice(b"abcdef");
+        socket.recv_exact(&mut [0; 4]).unwrap();
+        socket.rx_enqueue_slice(b"ghijk");
+
+        assert_eq!(
+            socket.recv_exact_with(6, |a, b| {
+                assert_eq!(a, b"efgh");
+                assert_eq!(b, Some(&b"ij"[..]));
+                a.len() + b.map_or(0, <[u8]>::len)
+            }),
+            Ok(6)
+        );
+        assert_eq!(socket.recv_queue(), 1);
+    }
+
+    #[test]
+    fn recv_exact_requires_may_recv() {
+        let mut socket = TcpSocket::<8>::new(0);
+        assert_eq!(socket.recv_exact(&mut [0; 4]), Err(Error::Illegal));
+        assert_eq!(socket.recv_exact(&mut []), Err(Error::Illegal));
+    }
+
+    #[test]
+    fn rx_overflow_is_counted() {
+        let mut socket = TcpSocket::<4>::new(0);
+
+        assert_eq!(socket.rx_enqueue_slice(b"abc"), 3);
+        assert_eq!(socket.take_dropped_bytes(), 0);
+        assert_eq!(socket.rx_enqueue_slice(b"def"), 1);
+        assert_eq!(socket.rx_enqueue_slice(b"gh"), 0);
+        assert_eq!(socket.take_dropped_bytes(), 4);
+        assert_eq!(socket.take_dropped_bytes(), 0);
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[test]
+    fn rx_enqueue_slice_checked() {
+        let mut socket: Socket<4> = TcpSocket::<4>::new(0).into();
+
+        assert_eq!(socket.rx_enqueue_slice_checked(b"ab"), Ok(2));
+        assert_eq!(
+            socket.rx_enqueue_slice_checked(b"cdef"),
+            Err(RxOverflow {
+                stored: 2,
+                dropped: 2
+            })
+        );
+        assert_eq!(socket.take_dropped_bytes(), 2);
+        assert_eq!(
+            Error::from(RxOverflow {
+                stored: 0,
+                dropped: 1
+            }),
+            Error::Exhausted
+        );
+    }
+
+    #[test]
+    fn shutdown() {
+        let mut socket = TcpSocket::<8>::new(0);
+        assert_eq!(
+            socket.shutdown(Shutdown::Read, Instant::from_secs(0)),
+            Err(Error::Illegal)
+        );
+        assert_eq!(socket.state(), &State::Created);
+
+        socket.set_state(State::Connected(remote()));
+        socket.rx_enqueue_slice(b"abc");
+        socket
+            .shutdown(Shutdown::Read, Instant::from_secs(0))
+            .unwrap();
+        assert_eq!(socket.state(), &State::ShutdownForRead(remote()));
+        assert!(!socket.may_recv());
+        assert!(!socket.can_recv());
+        assert!(socket.may_send());
+        assert!(socket.should_update_available_data(Instant::from_secs(0)));
+
+        assert_eq!(socket.rx_enqueue_slice(b"defg"), 0);
+        assert_eq!(socket.recv_queue(), 0);
+        assert_eq!(socket.take_dropped_bytes(), 7);
+
+        socket
+            .shutdown(Shutdown::Write, Instant::from_secs(10))
+            .unwrap();
+        assert_eq!(socket.state(), &State::Closed(Instant::from_secs(10)));
+        assert!(!socket.may_send());
+        assert!(!socket.recycle(Instant::from_secs(24)));
+        assert!(socket.recycle(Instant::from_secs(25)));
+    }
+
+    #[test]
+    fn shutdown_both() {
+        let cases = [
+            (
+                Shutdown::Write,
+                State::ShutdownForWrite(Instant::from_secs(1)),
+            ),
+            (Shutdown::Both, State::Closed(Instant::from_secs(1))),
+        ];
+
+        for (how, state) in cases {
+            let mut socket = connected_socket();
+            socket.shutdown(how, Instant::from_secs(1)).unwrap();
+            assert_eq!(socket.state(), &state);
+        }
+
+        // Shutting down reads after the remote closed closes both halves
+        let mut socket = connected_socket();
+        socket.set_state(State::ShutdownForWrite(Instant::from_secs(1)));
+        socket
+            .shutdown(Shutdown::Read, Instant::from_secs(2))
+            .unwrap();
+        assert_eq!(socket.state(), &State::Closed(Instant::from_secs(2)));
+    }
+
+    #[test]
+    fn debug_output() {
+        let mut socket = TcpSocket::<8>::new(2);
+        socket.set_state(State::Connected(remote()));
+        socket.rx_enqueue_slice(b"abc");
+
+        let debug = format!("{:?}", socket);
+        assert!(debug
+            .starts_with("TcpSocket { handle: Handle { id: 2, generation: 0 }, state: Connected("));
+        assert!(debug.contains("recv_queue: 3"));
+        assert!(debug.ends_with(".. }"));
+    }
+
+    #[test]
+    fn connect_retries() {
+        let mut socket = TcpSocket::<64>::new(0);
+        let backoff = Duration::from_secs(2);
+        socket.set_max_connect_attempts(Some(2));
+        assert!(!socket.should_retry_connect(Instant::from_secs(0)));
+
+        socket.connect(remote()).unwrap();
+        socket.set_state(State::Created);
+        socket.record_connect_failure(Instant::from_secs(10), backoff);
+        assert_eq!(socket.connect_attempts(), 1);
+        assert!(!socket.should_retry_connect(Instant::from_secs(11)));
+        assert!(socket.should_retry_connect(Instant::from_secs(12)));
+        assert!(!socket.recycle(Instant::from_secs(12)));
+
+        socket.connect(remote()).unwrap();
+        assert!(!socket.should_retry_connect(Instant::from_secs(12)));
+        socket.set_state(State::Created);
+        socket.record_connect_failure(Instant::from_secs(20), backoff);
+        assert!(!socket.should_retry_connect(Instant::from_secs(30)));
+        assert!(socket.recycle(Instant::from_secs(30)));
+        assert_eq!(socket.close_reason(), Some(CloseReason::ConnectFailed));
+
+        // A successful connection resets the bookkeeping
+        socket.connect(remote()).unwrap();
+        socket.set_state(State::Connected(remote()));
+        assert_eq!(socket.connect_attempts(), 0);
+        socket.set_state(State::Created);
+        assert!(!socket.should_retry_connect(Instant::from_secs(30)));
+        assert!(!socket.recycle(Instant::from_secs(30)));
+    }
+
+    #[test]
+    fn close_reasons() {
+        let mut socket = connected_socket();
+        assert_eq!(socket.close_reason(), None);
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert_eq!(socket.close_reason(), Some(CloseReason::RemoteFin));
+
+        // The first reason is kept
+        assert!(socket.recycle(Instant::from_secs(15)));
+        assert_eq!(socket.close_reason(), Some(CloseReason::RemoteFin));
+
+        let mut socket = connected_socket();
+        socket
+            .shutdown(Shutdown::Write, Instant::from_secs(0))
+            .unwrap();
+        assert_eq!(
+            socket.connection_status(),
+            ConnectionStatus::Disconnected {
+                reason: Some(CloseReason::LocalClose)
+            }
+        );
+
+        let mut socket = TcpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        assert!(!socket.recycle(Instant::from_secs(0)));
+        assert_eq!(socket.close_reason(), None);
+        assert!(socket.recycle(Instant::from_secs(60)));
+        assert_eq!(socket.close_reason(), Some(CloseReason::ConnectTimeout));
+
+        // Connecting again clears the reason
+        socket.set_state(State::Connected(remote()));
+        assert_eq!(socket.close_reason(), None);
+        socket.set_idle_timeout(Some(Duration::from_secs(1)));
+        assert!(!socket.recycle(Instant::from_secs(99)));
+        assert!(socket.recycle(Instant::from_secs(100)));
+        assert_eq!(socket.close_reason(), Some(CloseReason::IdleTimeout));
+
+        socket.reset();
+        socket.set_state(State::ShutdownForWrite(Instant::from_secs(0)));
+        assert!(socket.recycle(Instant::from_secs(15)));
+        assert_eq!(socket.close_reason(), Some(CloseReason::ReadTimeout));
+    }
+
+    #[test]
+    fn time_until_next_check() {
+        let mut socket = TcpSocket::<4>::new(0);
+        assert_eq!(socket.time_until_next_check(Instant::from_secs(0)), None);
+
+        socket.set_state(State::Connected(remote()));
+        assert_eq!(
+            socket.time_until_next_check(Instant::from_secs(0)),
+            Some(Duration::from_secs(0))
+        );
+
+        assert!(socket.should_update_available_data(Instant::from_secs(10)));
+        assert_eq!(socket.last_check_time(), Some(Instant::from_secs(10)));
+        assert_eq!(
+            socket.time_until_next_check(Instant::from_secs(20)),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            socket.time_until_next_check(Instant::from_secs(30)),
+            Some(Duration::from_secs(0))
+        );
+
+        // No check while the receive buffer is full, and one right away after
+        socket.rx_enqueue_slice(b"abcd");
+        assert!(!socket.should_update_available_data(Instant::from_secs(30)));
+        assert_eq!(socket.time_until_next_check(Instant::from_secs(30)), None);
+        socket.recv_slice(&mut [0; 1]).unwrap();
+        assert_eq!(
+            socket.time_until_next_check(Instant::from_secs(30)),
+            Some(Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn with_config() {
+        let keep_alive = KeepAlive {
+            idle: Duration::from_secs(30),
+            interval: Duration::from_secs(5),
+            count: 3,
+        };
+        let config = TcpSocketConfig {
+            check_interval: Duration::from_secs(1),
+            connect_timeout: None,
+            keep_alive: Some(keep_alive),
+            security_profile: Some(1),
+            ..Default::default()
+        };
+
+        let socket = TcpSocket::<64>::with_config(4, config);
+        assert_eq!(socket.handle(), SocketHandle::new(4));
+        assert_eq!(socket.poll_interval(), Duration::from_secs(1));
+        assert_eq!(socket.connect_timeout(), None);
+        assert_eq!(socket.idle_timeout(), None);
+        assert_eq!(socket.keep_alive(), Some(keep_alive));
+        assert_eq!(socket.security_profile(), Some(1));
+
+        let socket = TcpSocket::<64>::new(0);
+        assert_eq!(socket.connect_timeout(), Some(Duration::from_secs(60)));
+        assert_eq!(socket.read_timeout, Some(Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn asymmetric_buffers() {
+        let mut socket = TcpSocket::<4, 16>::new(0);
+        socket.set_state(State::Connected(remote()));
+
+        assert_eq!(socket.rx_enqueue_slice(b"abcdefgh"), 4);
+        assert_eq!(socket.send_slice(b"abcdefgh"), Ok(8));
+        assert_eq!(socket.send_slice(b"abcdefghijkl"), Ok(8));
+    }
+
+    #[test]
+    fn tx_coalesce() {
+        let mut socket = TcpSocket::<8>::new(0);
+        socket.set_state(State::Connected(remote()));
+
+        // Without coalescing, any queued byte is flushed
+        assert!(!socket.should_flush_tx(Instant::from_secs(0)));
+        socket.send_slice(b"a").unwrap();
+        assert!(socket.should_flush_tx(Instant::from_secs(0)));
+        socket.tx_mark_sent(8).unwrap();
+        assert!(!socket.should_flush_tx(Instant::from_secs(0)));
+        socket.tx_ack(1);
+
+        socket.set_tx_coalesce(4, Duration::from_millis(100));
+
+        // Small writes are batched
+        socket.send_slice(b"ab").unwrap();
+        assert!(!socket.should_flush_tx(Instant::from_millis(1000)));
+        socket.send_slice(b"c").unwrap();
+        assert!(!socket.should_flush_tx(Instant::from_millis(1050)));
+        socket.send_slice(b"d").unwrap();
+        assert!(socket.should_flush_tx(Instant::from_millis(1060)));
+        assert_eq!(socket.tx_mark_sent(8), Ok(&b"abcd"[..]));
+        socket.tx_ack(4);
+
+        // A lone byte is flushed after the delay
+        socket.send_slice(b"e").unwrap();
+        assert!(!socket.should_flush_tx(Instant::from_millis(2000)));
+        assert!(!socket.should_flush_tx(Instant::from_millis(2099)));
+        assert!(socket.should_flush_tx(Instant::from_millis(2100)));
+
+        // A full buffer is flushed regardless of the threshold
+        socket.set_tx_coalesce(64, Duration::from_secs(60));
+        assert_eq!(socket.send_slice(b"fghijkl"), Ok(7));
+        assert!(socket.should_flush_tx(Instant::from_millis(3000)));
+    }
+
+    #[test]
+    fn tx_in_flight() {
+        let mut socket = TcpSocket::<8>::new(0);
+        assert_eq!(socket.send_slice(b"abc"), Err(Error::Illegal));
+
+        socket.set_state(State::Connected(remote()));
+        assert_eq!(socket.tx_mark_sent(4), Err(Error::Exhausted));
+        assert_eq!(socket.send_slice(b"abcdef"), Ok(6));
+
+        assert_eq!(socket.tx_mark_sent(4), Ok(&b"abcd"[..]));
+        assert_eq!(socket.tx_in_flight(), 4);
+        assert_eq!(socket.tx_mark_sent(4), Err(Error::Illegal));
+
+        // The module accepted only part of the write
+        socket.tx_ack(3);
+        assert_eq!(socket.tx_in_flight(), 0);
+        assert_eq!(socket.send_queue(), 3);
+        assert_eq!(socket.tx_mark_sent(8), Ok(&b"def"[..]));
+
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert_eq!(socket.tx_in_flight(), 0);
+        assert_eq!(socket.pop_event(), Some(TcpEvent::Connected));
+        assert_eq!(
+            socket.pop_event(),
+            Some(TcpEvent::ClosedByRemote(Instant::from_secs(0)))
+        );
+        assert_eq!(socket.pop_event(), Some(TcpEvent::TxFailed(3)));
+    }
+
+    #[test]
+    fn recv_into_vec() {
+        let mut socket = TcpSocket::<8>::new(0);
+        let mut out: Vec<u8, 6> = Vec::new();
+        assert_eq!(socket.recv_into_vec(&mut out), Err(Error::Illegal));
+
+        socket.set_state(State::Connected(remote()));
+        socket.rx_enqueue_slice(b"abcdef");
+        socket.recv_slice(&mut [0; 4]).unwrap();
+        socket.rx_enqueue_slice(b"ghijkl");
+
+        // Wraps around the end of the buffer, and stops at the capacity
+        out.push(b'-').unwrap();
+        assert_eq!(socket.recv_into_vec(&mut out), Ok(5));
+        assert_eq!(&out[..], b"-efghi");
+        assert_eq!(socket.recv_queue(), 3);
+
+        out.clear();
+        assert_eq!(socket.recv_into_vec(&mut out), Ok(3));
+        assert_eq!(&out[..], b"jkl");
+        assert_eq!(socket.recv_into_vec(&mut out), Ok(0));
+    }
+
+    #[test]
+    fn max_read_len() {
+        let mut socket = TcpSocket::<8>::new(0);
+        assert_eq!(socket.max_read_len(512), 0);
+
+        socket.set_available_data(100);
+        assert_eq!(socket.max_read_len(512), 8);
+        assert_eq!(socket.max_read_len(4), 4);
+
+        socket.rx_enqueue_slice(b"abcdefgh");
+        assert_eq!(socket.max_read_len(512), 0);
+
+        socket.rx_buffer.clear();
+        socket.set_available_data(3);
+        assert_eq!(socket.max_read_len(512), 3);
+    }
+
+    #[test]
+    fn connect_to_host() {
+        let mut socket = TcpSocket::<64>::new(0);
+        let remote = RemoteEndpoint::host("example.com", 443).unwrap();
+        assert_eq!(remote.ip(), None);
+        assert_eq!(remote.port(), 443);
+
+        socket.connect(remote.clone()).unwrap();
+        assert_eq!(socket.endpoint(), Some(&remote));
+        assert_eq!(socket.connect(remote.clone()), Err(Error::Illegal));
+
+        socket.reset();
+        let addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+        socket.connect(addr).unwrap();
+        assert_eq!(socket.endpoint().and_then(|e| e.ip()), Some(addr.into()));
+
+        let too_long = [b'a'; MAX_HOSTNAME_LEN + 1];
+        assert_eq!(
+            RemoteEndpoint::host(core::str::from_utf8(&too_long).unwrap(), 80),
+            Err(Error::BadLength)
+        );
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[test]
+    fn local_endpoint() {
+        let mut socket = TcpSocket::<64>::new(0);
+        assert_eq!(socket.local_endpoint(), None);
+        assert_eq!(socket.local_port(), None);
+
+        let local = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 5000));
+        socket.set_local_endpoint(Some(local));
+        socket.set_state(State::Connected(remote()));
+        assert_eq!(socket.local_endpoint(), Some(local));
+        assert_eq!(socket.endpoint(), Some(&remote()));
+
+        let socket: Socket<64> = socket.into();
+        assert_eq!(socket.local_port(), Some(5000));
+    }
+
+    #[test]
+    fn may_send_only_when_connected() {
+        let cases = [
+            (State::Created, false),
+            (State::WaitingForConnect(remote()), false),
+            (State::Connected(remote()), true),
+            (State::ShutdownForWrite(Instant::from_secs(0)), false),
+            (State::ShutdownForRead(remote()), true),
+            (State::Closed(Instant::from_secs(0)), false),
+        ];
+
+        for (state, may_send) in cases {
+            let mut socket = TcpSocket::<64>::new(0);
+            socket.set_state(state);
+            assert_eq!(socket.may_send(), may_send);
+            #[cfg(feature = "socket-tcp")]
+            {
+                let socket: Socket<64> = socket.into();
+                assert_eq!(socket.may_send(), may_send);
+            }
+        }
+    }
+
+    #[test]
+    fn can_send_until_tx_buffer_full() {
+        let mut socket = TcpSocket::<64, 4>::new(0);
+        assert!(!socket.can_send());
+
+        socket.set_state(State::Connected(remote()));
+        assert!(socket.can_send());
+        assert_eq!(socket.send_slice(b"abcd"), Ok(4));
+        assert!(socket.may_send());
+        assert!(!socket.can_send());
+    }
+
+    #[test]
+    fn keepalive_after_idle() {
+        let mut socket = TcpSocket::<64>::new(0);
+        let keep_alive = KeepAlive {
+            idle: Duration::from_secs(60),
+            interval: Duration::from_secs(10),
+            count: 3,
+        };
+        socket.set_keep_alive(Some(keep_alive));
+        assert_eq!(socket.keep_alive(), Some(keep_alive));
+        assert!(!socket.should_send_keepalive(Instant::from_secs(0)));
+
+        socket.set_state(State::Connected(remote()));
+        assert!(!socket.should_send_keepalive(Instant::from_secs(0)));
+        assert!(!socket.should_send_keepalive(Instant::from_secs(59)));
+        assert!(socket.should_send_keepalive(Instant::from_secs(60)));
+        assert!(!socket.should_send_keepalive(Instant::from_secs(65)));
+        assert!(socket.should_send_keepalive(Instant::from_secs(70)));
+
+        socket.rx_enqueue_slice(b"data");
+        assert!(!socket.should_send_keepalive(Instant::from_secs(75)));
+        assert!(!socket.should_send_keepalive(Instant::from_secs(134)));
+        assert!(socket.should_send_keepalive(Instant::from_secs(135)));
+    }
+
+    #[test]
+    fn no_keepalive_when_disabled() {
+        let mut socket = connected_socket();
+        assert!(!socket.should_send_keepalive(Instant::from_secs(0)));
+        assert!(!socket.should_send_keepalive(Instant::from_secs(3600)));
+    }
+
+    #[test]
+    fn state_events() {
+        let mut socket = TcpSocket::<64>::new(0);
+        assert_eq!(socket.pop_event(), None);
+
+        socket.set_state(State::WaitingForConnect(remote()));
+        socket.set_state(State::Created);
+        socket.set_state(State::WaitingForConnect(remote()));
+        socket.set_state(State::Connected(remote()));
+        assert_eq!(socket.pop_event(), Some(TcpEvent::ConnectFailed));
+        assert_eq!(socket.pop_event(), Some(TcpEvent::Connected));
+        assert_eq!(socket.pop_event(), None);
+
+        // The oldest events are discarded once the queue is full
+        for _ in 0..MAX_EVENTS {
+            socket.set_state(State::Connected(remote()));
+        }
+        socket.push_event(TcpEvent::Recycled);
+        for _ in 1..MAX_EVENTS {
+            assert_eq!(socket.pop_event(), Some(TcpEvent::Connected));
+        }
+        assert_eq!(socket.pop_event(), Some(TcpEvent::Recycled));
+        assert_eq!(socket.pop_event(), None);
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let mut socket = TcpSocket::<64>::new(0);
+        socket.set_state(State::WaitingForConnect(remote()));
+
+        let started = Instant::from_secs(10);
+        let timeout = Duration::from_secs(60);
+        assert!(!socket.recycle(started));
+        assert!(!socket.recycle(started + timeout - Duration::from_millis(1)));
+        assert!(socket.recycle(started + timeout));
+
+        // A new attempt restarts the timeout
+        socket.set_state(State::Created);
+        socket.set_state(State::WaitingForConnect(remote()));
+        assert!(!socket.recycle(started + timeout));
+        assert!(!socket.recycle(started + timeout * 2 - Duration::from_millis(1)));
+        assert!(socket.recycle(started + timeout * 2));
+
+        socket.set_connect_timeout(None);
+        assert!(!socket.recycle(started + timeout * 10));
+
+        socket.set_connect_timeout(Some(timeout));
+        socket.set_state(State::Connected(remote()));
+        assert!(!socket.recycle(started + timeout * 10));
+    }
+
+    #[test]
+    fn reuse_after_reset() {
+        let mut socket = TcpSocket::<64>::new(3);
+        socket.set_connect_timeout(Some(Duration::from_secs(5)));
+
+        for _ in 0..2 {
+            socket.set_state(State::WaitingForConnect(remote()));
+            socket.set_state(State::Connected(remote()));
+            socket.rx_enqueue_slice(b"abc");
+            socket.set_available_data(7);
+            assert!(socket.should_update_available_data(Instant::from_secs(0)));
+            socket.set_state(State::ShutdownForWrite(Instant::from_secs(1)));
+
+            socket.reset();
+            assert_eq!(socket.state(), &State::Created);
+            assert_eq!(socket.handle(), SocketHandle::new(3));
+            assert_eq!(socket.recv_queue(), 0);
+            assert_eq!(socket.get_available_data(), 0);
+            assert_eq!(socket.last_check_time, None);
+            assert_eq!(socket.connect_timeout(), Some(Duration::from_secs(5)));
+        }
+
+        socket.set_security_profile(Some(2));
+        assert_eq!(socket.security_profile(), Some(2));
+        socket.reset();
+        assert_eq!(socket.security_profile(), None);
+
+        assert_eq!(socket.pop_event(), Some(TcpEvent::Connected));
+        assert_eq!(socket.pop_event(), Some(TcpEvent::Connected));
+        assert_eq!(socket.pop_event(), None);
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let mut socket = connected_socket();
+        assert!(!socket.recycle(Instant::from_secs(0)));
+        assert!(!socket.recycle(Instant::from_secs(3600)));
+
+        socket.set_idle_timeout(Some(Duration::from_secs(60)));
+        assert!(socket.recycle(Instant::from_secs(3600)));
+
+        // Received data resets the countdown
+        socket.rx_enqueue_slice(b"abc");
+        assert!(!socket.recycle(Instant::from_secs(3600)));
+        assert!(!socket.recycle(Instant::from_secs(3659)));
+
+        // So does the application reading it
+        socket.recv_slice(&mut [0; 3]).unwrap();
+        assert!(!socket.recycle(Instant::from_secs(3659)));
+        assert!(!socket.recycle(Instant::from_secs(3718)));
+        assert!(socket.recycle(Instant::from_secs(3719)));
+    }
+
+    #[test]
+    fn long_running_past_u32_ticks() {
+        // Where a 32-bit tick count at 1 MHz would wrap, and far beyond
+        for start in [(1u64 << 32) - 20_000_000, 100 * 365 * 24 * 3600 * 1_000_000] {
+            let mut socket = connected_socket();
+            let mut checks = 0;
+            for step in 0..(2 * 24 * 3600) {
+                if socket
+                    .should_update_available_data(Instant::from_micros(start + step * 1_000_000))
+                {
+                    checks += 1;
+                }
+            }
+            assert_eq!(checks, 2 * 24 * 3600 / 15);
+
+            let closed = start + 10_000_000;
+            socket.set_state(State::ShutdownForWrite(Instant::from_micros(closed)));
+            assert!(!socket.recycle(Instant::from_micros(closed + 14_000_000)));
+            assert!(socket.recycle(Instant::from_micros(closed + 15_000_000)));
+        }
+    }
+
+    #[test]
+    fn recycle_after_remote_close() {
+        let mut socket = connected_socket();
+        socket.set_state(State::ShutdownForWrite(Instant::from_secs(10)));
+
+        assert!(!socket.recycle(Instant::from_secs(24)));
+        assert!(socket.recycle(Instant::from_secs(25)));
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn rx_waker() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let woken = || counter.0.load(Ordering::SeqCst);
+
+        let mut socket = connected_socket();
+        socket.rx_enqueue_slice(b"abc");
+        assert_eq!(woken(), 0);
+
+        socket.register_rx_waker(&waker);
+        socket.rx_enqueue_slice(b"abc");
+        assert_eq!(woken(), 1);
+
+        // Consumed by the wake above
+        socket.rx_enqueue_slice(b"abc");
+        assert_eq!(woken(), 1);
+
+        socket.register_rx_waker(&waker);
+        socket.set_available_data(0);
+        assert_eq!(woken(), 1);
+        socket.set_available_data(10);
+        assert_eq!(woken(), 2);
+
+        socket.register_rx_waker(&waker);
+        socket.set_available_data(20);
+        assert_eq!(woken(), 2);
+
+        // Only the first wake after registering reaches the waker
+        socket.register_rx_waker(&waker);
+        socket.set_available_data(0);
+        socket.set_available_data(5);
+        socket.set_available_data(0);
+        socket.set_available_data(5);
+        assert_eq!(woken(), 3);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn traffic_stats() {
+        let mut socket = connected_socket();
+        assert_eq!(socket.stats(), TcpStats::default());
+
+        socket.rx_enqueue_slice(b"abcdef");
+        socket.recv_slice(&mut [0; 4]).unwrap();
+        socket.recv(|buf| (buf.len(), ())).unwrap();
+        socket.closed_by_remote(Instant::from_secs(0));
+        socket.set_state(State::Connected(remote()));
+
+        assert_eq!(
+            socket.stats(),
+            TcpStats {
+                rx_enqueued: 6,
+                rx_delivered: 6,
+                remote_closes: 1,
+                reconnects: 1,
+            }
+        );
+
+        socket.reset_stats();
+        assert_eq!(socket.stats(), TcpStats::default());
+    }
+
+    #[test]
+    fn poll_backoff_reset_by_rx() {
+        let mut socket = connected_socket();
+        socket.set_poll_backoff(Duration::from_secs(1), Duration::from_secs(8));
+
+        assert!(socket.should_update_available_data(Instant::from_secs(0)));
+        assert!(socket.should_update_available_data(Instant::from_secs(1)));
+        assert!(socket.should_update_available_data(Instant::from_secs(3)));
+        assert_eq!(socket.poll_interval(), Duration::from_secs(4));
+
+        socket.rx_enqueue_slice(b"data");
+        assert_eq!(socket.poll_interval(), Duration::from_secs(1));
+        assert!(socket.should_update_available_data(Instant::from_secs(4)));
     }
 }
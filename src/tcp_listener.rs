@@ -1,47 +1,1344 @@
-use heapless::{spsc::Queue, FnvIndexMap};
+use embassy_time::{Duration, Instant};
+use heapless::{FnvIndexMap, Vec};
 use no_std_net::SocketAddr;
 
-use crate::SocketHandle;
+use crate::{
+    local_addr::{self, LocalAddr, SocketAddrWrapper},
+    tcp::State,
+    Error, ListenerError, PortAllocator, SocketHandle, SocketSet, TcpSocket, TeardownEvent,
+};
 
-pub struct TcpListener<const N: usize, const L: usize> {
-    handles: FnvIndexMap<SocketHandle, u16, N>,
-    connections: FnvIndexMap<u16, Queue<(SocketHandle, SocketAddr), L>, N>,
+/// Listener for TCP server sockets.
+///
+/// Up to `N` ports are bound at a time. Their pending connections share a
+/// pool of `L` entries, and each binding picks how many of them it may use
+/// with the `backlog` of [`bind`](#method.bind), so a control port with a
+/// backlog of 1 does not reserve as much memory as a busy data port.
+///
+/// A listener created [with a port allocator](#method.with_port_allocator)
+/// of up to `P` ports reserves every port it binds in it, so client ports
+/// allocated with [`allocate_port`](#method.allocate_port) never collide with
+/// a server port.
+pub struct TcpListener<const N: usize, const L: usize, const P: usize = 0> {
+    handles: FnvIndexMap<SocketHandle, SocketAddr, N>,
+    /// Pending connections of all bindings, in the order they were queued
+    pending: Vec<Pending, L>,
+    /// Backlog, counters and peer filter per local address
+    ports: FnvIndexMap<SocketAddrWrapper, PortState, N>,
+    /// Allocator the bound ports are reserved in
+    allocator: Option<PortAllocator<P>>,
 }
 
-impl<const N: usize, const L: usize> TcpListener<N, L> {
+struct Pending {
+    local: SocketAddrWrapper,
+    handle: SocketHandle,
+    remote: SocketAddr,
+    queued: Instant,
+}
+
+#[derive(Default)]
+struct PortState {
+    /// Maximum number of pending connections
+    backlog: usize,
+    /// Number of incoming connections dropped, as the backlog was full
+    dropped: usize,
+    /// Number of incoming connections rejected, by the filter or by `reject`
+    rejected: usize,
+    filter: Option<fn(SocketAddr) -> bool>,
+    #[cfg(feature = "stats")]
+    stats: crate::ListenerStats,
+}
+
+impl<const N: usize, const L: usize, const P: usize> Default for TcpListener<N, L, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const L: usize, const P: usize> TcpListener<N, L, P> {
     pub fn new() -> Self {
         Self {
             handles: FnvIndexMap::new(),
-            connections: FnvIndexMap::new(),
+            pending: Vec::new(),
+            ports: FnvIndexMap::new(),
+            allocator: None,
+        }
+    }
+
+    /// Create a listener that reserves every port it binds in `ports`, and
+    /// hands out client ports from it; see [`allocate_port`](#method.allocate_port).
+    pub fn with_port_allocator(ports: PortAllocator<P>) -> Self {
+        Self {
+            allocator: Some(ports),
+            ..Self::new()
+        }
+    }
+
+    /// Return the port allocator of the listener, if it has one.
+    pub fn port_allocator(&self) -> Option<&PortAllocator<P>> {
+        self.allocator.as_ref()
+    }
+
+    /// Allocate a local port for a client socket, which is never a port bound
+    /// by a server socket of the listener.
+    ///
+    /// Returns `None` if the listener has no port allocator, or it has no
+    /// free port left.
+    pub fn allocate_port(&mut self) -> Option<u16> {
+        self.allocator.as_mut()?.allocate()
+    }
+
+    /// Release a port allocated with [`allocate_port`](#method.allocate_port).
+    ///
+    /// Returns `Err(ListenerError::PortInUse)` if a server socket of the
+    /// listener is bound to `port`, and `Err(ListenerError::NotBound)` if the
+    /// port is not allocated, or the listener has no port allocator.
+    pub fn release_port(&mut self, port: u16) -> Result<(), ListenerError> {
+        if self.holds_port(port) {
+            return Err(ListenerError::PortInUse);
+        }
+        self.allocator
+            .as_mut()
+            .ok_or(ListenerError::NotBound)?
+            .release(port)
+    }
+
+    fn holds_port(&self, port: u16) -> bool {
+        self.handles.values().any(|local| local.port() == port)
+    }
+
+    /// Reserve `port` for a new binding, unless another binding holds it.
+    fn reserve_port(&mut self, port: u16) -> Result<(), ListenerError> {
+        if self.holds_port(port) {
+            return Ok(());
+        }
+        match self.allocator.as_mut() {
+            Some(ports) => ports.reserve(port),
+            None => Ok(()),
+        }
+    }
+
+    /// Release `port` once no binding holds it anymore.
+    fn release_unbound_port(&mut self, port: u16) {
+        if self.holds_port(port) {
+            return;
+        }
+        if let Some(ports) = self.allocator.as_mut() {
+            ports.release(port).ok();
         }
     }
 
-    pub fn bind(&mut self, handle: SocketHandle, port: u16) -> Result<(), ()> {
+    /// Bind server socket `handle` to the local address `local`, queueing up
+    /// to `backlog` of its incoming connections.
+    ///
+    /// The backlogs of all bindings share the `L` entries of the listener,
+    /// so a connection can also be dropped when the other bindings use them
+    /// all.
+    ///
+    /// Binding to the unspecified address gives a wildcard binding of the
+    /// port, which can coexist with bindings of the port to specific
+    /// addresses; see [`LocalAddr`] for which one a lookup picks.
+    ///
+    /// Returns `Err(ListenerError::InvalidBacklog)` if `backlog` is 0 or
+    /// larger than `L`, and `Err(ListenerError::PortInUse)` if the port is
+    /// allocated to a client socket; see [`allocate_port`](#method.allocate_port).
+    /// On error, the listener is left unchanged.
+    pub fn bind(
+        &mut self,
+        handle: SocketHandle,
+        local: SocketAddr,
+        backlog: usize,
+    ) -> Result<(), ListenerError> {
+        if backlog == 0 || backlog > L {
+            return Err(ListenerError::InvalidBacklog);
+        }
         if self.handles.contains_key(&handle) {
-            return Err(());
+            return Err(ListenerError::AlreadyBound);
+        }
+        if local_addr::conflicts(&self.ports, local) {
+            return Err(ListenerError::PortInUse);
+        }
+
+        self.reserve_port(local.port())?;
+        let key = SocketAddrWrapper(local);
+        if self.handles.insert(handle, local).is_err() {
+            self.release_unbound_port(local.port());
+            return Err(ListenerError::Full);
+        }
+        let state = PortState {
+            backlog,
+            ..PortState::default()
+        };
+        if self.ports.insert(key, state).is_err() {
+            self.handles.remove(&handle);
+            self.release_unbound_port(local.port());
+            return Err(ListenerError::Full);
+        }
+
+        Ok(())
+    }
+
+    /// Bind server socket `handle` to `port` on any local address; see
+    /// [`bind`](#method.bind).
+    pub fn bind_port(
+        &mut self,
+        handle: SocketHandle,
+        port: u16,
+        backlog: usize,
+    ) -> Result<(), ListenerError> {
+        self.bind(handle, SocketAddrWrapper::any(port).0, backlog)
+    }
+
+    /// Unbind the server socket `handle` from its port, calling `f` for every
+    /// pending connection that is dropped, so the caller can close it on the
+    /// module.
+    ///
+    /// The port can be bound again afterwards.
+    pub fn unbind<F>(&mut self, handle: SocketHandle, mut f: F) -> Result<(), ListenerError>
+    where
+        F: FnMut(TeardownEvent),
+    {
+        let local = self
+            .handles
+            .remove(&handle)
+            .ok_or(ListenerError::NotBound)?;
+        self.release_unbound_port(local.port());
+        let key = SocketAddrWrapper(local);
+        self.ports.remove(&key);
+        while let Some(pending) = self.take(key) {
+            f(TeardownEvent::Pending {
+                listener: handle,
+                handle: pending.handle,
+            });
+        }
+        Ok(())
+    }
+
+    /// Unbind all server sockets, e.g. when the link goes down, calling `f`
+    /// for every pending connection, followed by the binding it was queued
+    /// on; see [`unbind`](#method.unbind).
+    pub fn unbind_all<F>(&mut self, mut f: F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        for (&listener, local) in self.handles.iter() {
+            let key = SocketAddrWrapper(*local);
+            for pending in self.pending.iter().filter(|pending| pending.local == key) {
+                f(TeardownEvent::Pending {
+                    listener,
+                    handle: pending.handle,
+                });
+            }
+            f(TeardownEvent::Listener {
+                handle: listener,
+                port: local.port(),
+            });
+        }
+        if let Some(ports) = self.allocator.as_mut() {
+            for local in self.handles.values() {
+                ports.release(local.port()).ok();
+            }
+        }
+        self.handles.clear();
+        self.pending.clear();
+        self.ports.clear();
+    }
+
+    /// Tear down the listener, calling `f` for every pending connection,
+    /// followed by the binding it was queued on.
+    ///
+    /// `f` should neither allocate nor panic.
+    pub fn teardown<F>(mut self, f: F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        self.unbind_all(f);
+    }
+
+    /// Drop every pending connection, calling `f` for each, but keep the
+    /// bindings; see [`teardown`](crate::teardown).
+    #[cfg(feature = "socket-udp")]
+    pub(crate) fn drop_pending<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        for (&listener, local) in self.handles.iter() {
+            let key = SocketAddrWrapper(*local);
+            for pending in self.pending.iter().filter(|pending| pending.local == key) {
+                f(TeardownEvent::Pending {
+                    listener,
+                    handle: pending.handle,
+                });
+            }
         }
+        self.pending.clear();
+    }
 
-        self.handles.insert(handle, port).map_err(drop)?;
-        self.connections.insert(port, Queue::new()).map_err(drop)?;
+    /// Remove every binding, calling `f` for each.
+    #[cfg(feature = "socket-udp")]
+    pub(crate) fn drop_bindings<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        for (&handle, local) in self.handles.iter() {
+            f(TeardownEvent::Listener {
+                handle,
+                port: local.port(),
+            });
+        }
+        self.handles.clear();
+        self.ports.clear();
+    }
 
+    /// Queue an incoming connection from `addr` on the binding that `local`
+    /// matches, which the module gave socket `handle` at `ts`.
+    ///
+    /// `handle` carries the id the module assigned to the new socket, as
+    /// reported by the incoming connection URC; it is not the handle of a
+    /// socket in a [`SocketSet`], which may differ in generation. See
+    /// [`accept_into`](#method.accept_into).
+    ///
+    /// If the filter of the binding refuses `addr`, the connection is dropped
+    /// and counted, see [`rejected`](#method.rejected), and
+    /// `Err(ListenerError::Rejected)` is returned. If the backlog of the
+    /// binding is full, or all `L` entries of the listener are in use, the
+    /// connection is dropped and counted, see [`dropped`](#method.dropped),
+    /// and `Err(ListenerError::BacklogFull)` is returned. In both cases the
+    /// driver should close the socket on the module.
+    pub fn push_incoming(
+        &mut self,
+        local: impl Into<LocalAddr>,
+        handle: SocketHandle,
+        addr: SocketAddr,
+        ts: Instant,
+    ) -> Result<(), ListenerError> {
+        let key = self.key(local).ok_or(ListenerError::NotBound)?;
+        let queued = self.count(key);
+        let state = self.ports.get_mut(&key).ok_or(ListenerError::NotBound)?;
+        if let Some(filter) = state.filter {
+            if !filter(addr) {
+                state.rejected += 1;
+                #[cfg(feature = "stats")]
+                {
+                    state.stats.rejected += 1;
+                }
+                return Err(ListenerError::Rejected);
+            }
+        }
+        let pending = Pending {
+            local: key,
+            handle,
+            remote: addr,
+            queued: ts,
+        };
+        if queued >= state.backlog || self.pending.push(pending).is_err() {
+            state.dropped += 1;
+            #[cfg(feature = "stats")]
+            {
+                state.stats.dropped += 1;
+            }
+            return Err(ListenerError::BacklogFull);
+        }
+        #[cfg(feature = "stats")]
+        {
+            state.stats.peak_backlog = state.stats.peak_backlog.max(queued + 1);
+        }
         Ok(())
     }
 
-    pub fn incoming(&mut self, port: u16) -> Option<&mut Queue<(SocketHandle, SocketAddr), L>> {
-        self.connections.get_mut(&port)
+    /// Only queue incoming connections on the binding that `local` matches
+    /// from peers that `filter` returns true for; see
+    /// [`push_incoming`](#method.push_incoming).
+    ///
+    /// Connections already queued are kept.
+    pub fn set_filter(
+        &mut self,
+        local: impl Into<LocalAddr>,
+        filter: fn(SocketAddr) -> bool,
+    ) -> Result<(), ListenerError> {
+        let state = self.state_mut(local).ok_or(ListenerError::NotBound)?;
+        state.filter = Some(filter);
+        Ok(())
+    }
+
+    /// Queue incoming connections on the binding that `local` matches from
+    /// any peer again.
+    pub fn clear_filter(&mut self, local: impl Into<LocalAddr>) -> Result<(), ListenerError> {
+        let state = self.state_mut(local).ok_or(ListenerError::NotBound)?;
+        state.filter = None;
+        Ok(())
+    }
+
+    /// Return the number of incoming connections dropped on the binding that
+    /// `local` matches since it was bound, as its backlog was full, or `None`
+    /// if there is no such binding.
+    pub fn dropped(&self, local: impl Into<LocalAddr>) -> Option<usize> {
+        let key = self.key(local)?;
+        self.ports.get(&key).map(|state| state.dropped)
+    }
+
+    /// Return the number of incoming connections rejected on the binding that
+    /// `local` matches since it was bound, by its filter or with
+    /// [`reject`](#method.reject), or `None` if there is no such binding.
+    pub fn rejected(&self, local: impl Into<LocalAddr>) -> Option<usize> {
+        let key = self.key(local)?;
+        self.ports.get(&key).map(|state| state.rejected)
+    }
+
+    /// Return the statistics of the binding that `local` matches, since it
+    /// was bound, or `None` if there is no such binding.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self, local: impl Into<LocalAddr>) -> Option<&crate::ListenerStats> {
+        let key = self.key(local)?;
+        self.ports.get(&key).map(|state| &state.stats)
+    }
+
+    /// Drop the pending connections on the binding that `local` matches that
+    /// were queued more than `max_age` before `ts`, calling `f` with each of
+    /// their handles so the caller can close them, and return how many were
+    /// dropped.
+    pub fn expire_older_than<F>(
+        &mut self,
+        local: impl Into<LocalAddr>,
+        ts: Instant,
+        max_age: Duration,
+        mut f: F,
+    ) -> usize
+    where
+        F: FnMut(SocketHandle),
+    {
+        let key = match self.key(local) {
+            Some(key) => key,
+            None => return 0,
+        };
+        let mut expired = 0;
+        // Connections are queued in order, so the oldest is in front.
+        while let Some(pending) = self.peek(key) {
+            let stale = ts
+                .checked_duration_since(pending.queued)
+                .map(|age| age > max_age)
+                .unwrap_or(false);
+            if !stale {
+                break;
+            }
+            if let Some(pending) = self.take(key) {
+                f(pending.handle);
+                expired += 1;
+            }
+        }
+        #[cfg(feature = "stats")]
+        if let Some(state) = self.ports.get_mut(&key) {
+            state.stats.expired += expired as u32;
+        }
+        expired
+    }
+
+    /// Returns true if `local`, a port or a full local address, matches a
+    /// TCP server binding
+    pub fn is_port_bound(&self, local: impl Into<LocalAddr>) -> bool {
+        self.key(local).is_some()
+    }
+
+    /// Returns true if socket is a TCP server socket
+    pub fn is_bound(&self, handle: SocketHandle) -> bool {
+        self.handles.contains_key(&handle)
+    }
+
+    /// Get the port server socket `handle` is bound to.
+    pub fn get_port(&self, handle: SocketHandle) -> Result<u16, ListenerError> {
+        self.get_local_addr(handle).map(|local| local.port())
+    }
+
+    /// Get the local address server socket `handle` is bound to.
+    pub fn get_local_addr(&self, handle: SocketHandle) -> Result<SocketAddr, ListenerError> {
+        self.handles
+            .get(&handle)
+            .copied()
+            .ok_or(ListenerError::NotBound)
+    }
+
+    /// Iterate the server sockets and the ports they are bound to, e.g. to
+    /// listen on them again after a module reboot.
+    pub fn bound_ports(&self) -> impl Iterator<Item = (SocketHandle, u16)> + '_ {
+        self.bound_addrs()
+            .map(|(handle, local)| (handle, local.port()))
+    }
+
+    /// Iterate the server sockets and the local addresses they are bound to.
+    pub fn bound_addrs(&self) -> impl Iterator<Item = (SocketHandle, SocketAddr)> + '_ {
+        self.handles.iter().map(|(&handle, &local)| (handle, local))
+    }
+
+    /// Return the number of ports the listener can bind.
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Return the number of bound ports.
+    pub fn bound_count(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Return the number of connections queued on the binding that `local`
+    /// matches, or 0 if there is no such binding.
+    pub fn pending(&self, local: impl Into<LocalAddr>) -> usize {
+        self.key(local).map_or(0, |key| self.count(key))
+    }
+
+    /// Return the backlog the binding that `local` matches was bound with,
+    /// or `None` if there is no such binding.
+    pub fn backlog(&self, local: impl Into<LocalAddr>) -> Option<usize> {
+        let key = self.key(local)?;
+        self.ports.get(&key).map(|state| state.backlog)
+    }
+
+    /// Return the number of connections that can be queued on all ports
+    /// together.
+    pub fn backlog_capacity(&self) -> usize {
+        L
+    }
+
+    /// Return the number of connections queued on all ports.
+    pub fn total_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// See if a connection is pending for server socket `handle`.
+    pub fn available(&mut self, handle: SocketHandle) -> Result<bool, ListenerError> {
+        Ok(self.peek(self.handle_key(handle)?).is_some())
+    }
+
+    /// Pop the next pending connection for server socket `handle`.
+    ///
+    /// Returns `Err(ListenerError::WouldBlock)` if there is none yet.
+    pub fn accept(
+        &mut self,
+        handle: SocketHandle,
+    ) -> Result<(SocketHandle, SocketAddr), ListenerError> {
+        let connection = self.dequeue(handle)?;
+        #[cfg(feature = "stats")]
+        if let Some(stats) = self.stats_mut(handle) {
+            stats.accepted += 1;
+        }
+        Ok(connection)
+    }
+
+    /// Refuse the next pending connection for server socket `handle`, and
+    /// count it, see [`rejected`](#method.rejected).
+    ///
+    /// The connection is returned like with [`accept`](#method.accept), so
+    /// the caller can close its socket on the module.
+    pub fn reject(
+        &mut self,
+        handle: SocketHandle,
+    ) -> Result<(SocketHandle, SocketAddr), ListenerError> {
+        let local = self.get_local_addr(handle)?;
+        let connection = self.dequeue(handle)?;
+        if let Some(state) = self.ports.get_mut(&SocketAddrWrapper(local)) {
+            state.rejected += 1;
+            #[cfg(feature = "stats")]
+            {
+                state.stats.rejected += 1;
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Pop the next pending connection for server socket `handle`, and mark
+    /// its socket in `set` as connected to the remote peer at `ts`.
+    ///
+    /// The handle queued with [`push_incoming`](#method.push_incoming) is a
+    /// module id, so the socket is looked up in `set` with
+    /// [`SocketSet::find_by_module_id`], and its handle in `set` is returned.
+    /// The connection counts as active at `ts`, so an idle timeout of the
+    /// socket runs from the accept. The connection is only removed from the
+    /// queue once the socket has been updated, so if it is missing from `set`
+    /// (`Err(Error::InvalidSocket)`), or is not a TCP socket
+    /// (`Err(Error::Illegal)`), the listener is left untouched.
+    pub fn accept_into<const SN: usize, const SL: usize, const STXL: usize>(
+        &mut self,
+        handle: SocketHandle,
+        set: &mut SocketSet<SN, SL, STXL>,
+        ts: Instant,
+    ) -> Result<(SocketHandle, SocketAddr), Error> {
+        let key = self.handle_key(handle)?;
+        let (pending, addr) = self
+            .peek(key)
+            .map(|pending| (pending.handle, pending.remote))
+            .ok_or(ListenerError::WouldBlock)?;
+
+        let socket_handle = set
+            .find_by_module_id(pending.id())
+            .ok_or(Error::InvalidSocket)?;
+        let mut socket = set.get::<TcpSocket<SL, STXL>>(socket_handle)?;
+        socket.set_state(State::Connected(addr.into()));
+        socket.mark_activity_at(ts);
+
+        self.take(key);
+        #[cfg(feature = "stats")]
+        if let Some(stats) = self.stats_mut(handle) {
+            stats.accepted += 1;
+        }
+        Ok((socket_handle, addr))
+    }
+
+    /// Like [`accept`](#method.accept), but first drop the pending
+    /// connections queued more than `max_age` before `ts`, calling `f` with
+    /// each of their handles; see
+    /// [`expire_older_than`](#method.expire_older_than).
+    pub fn accept_fresh<F>(
+        &mut self,
+        handle: SocketHandle,
+        ts: Instant,
+        max_age: Duration,
+        f: F,
+    ) -> Result<(SocketHandle, SocketAddr), ListenerError>
+    where
+        F: FnMut(SocketHandle),
+    {
+        let local = self.get_local_addr(handle)?;
+        self.expire_older_than(local, ts, max_age, f);
+        self.accept(handle)
+    }
+
+    fn dequeue(
+        &mut self,
+        handle: SocketHandle,
+    ) -> Result<(SocketHandle, SocketAddr), ListenerError> {
+        let key = self.handle_key(handle)?;
+        self.take(key)
+            .map(|pending| (pending.handle, pending.remote))
+            .ok_or(ListenerError::WouldBlock)
     }
 
-    pub fn available(&mut self, handle: SocketHandle) -> Result<bool, ()> {
-        let port = self.handles.get(&handle).ok_or(())?;
-        Ok(!self.connections.get_mut(port).ok_or(())?.is_empty())
+    #[cfg(feature = "stats")]
+    fn stats_mut(&mut self, handle: SocketHandle) -> Option<&mut crate::ListenerStats> {
+        let local = self.handles.get(&handle)?;
+        self.ports
+            .get_mut(&SocketAddrWrapper(*local))
+            .map(|state| &mut state.stats)
     }
 
-    pub fn accept(&mut self, handle: SocketHandle) -> Result<(SocketHandle, SocketAddr), ()> {
-        let port = self.handles.get(&handle).ok_or(())?;
-        self.connections
-            .get_mut(port)
-            .ok_or(())?
-            .dequeue()
-            .ok_or(())
+    /// The oldest connection pending on the binding `key`.
+    fn peek(&self, key: SocketAddrWrapper) -> Option<&Pending> {
+        self.pending.iter().find(|pending| pending.local == key)
+    }
+
+    /// Remove the oldest connection pending on the binding `key`, keeping the
+    /// others in order.
+    fn take(&mut self, key: SocketAddrWrapper) -> Option<Pending> {
+        let index = self
+            .pending
+            .iter()
+            .position(|pending| pending.local == key)?;
+        Some(self.pending.remove(index))
+    }
+
+    fn count(&self, key: SocketAddrWrapper) -> usize {
+        self.pending
+            .iter()
+            .filter(|pending| pending.local == key)
+            .count()
+    }
+
+    fn handle_key(&self, handle: SocketHandle) -> Result<SocketAddrWrapper, ListenerError> {
+        self.handles
+            .get(&handle)
+            .map(|local| SocketAddrWrapper(*local))
+            .ok_or(ListenerError::NotBound)
+    }
+
+    fn key(&self, local: impl Into<LocalAddr>) -> Option<SocketAddrWrapper> {
+        local_addr::resolve(&self.ports, local.into())
+    }
+
+    fn state_mut(&mut self, local: impl Into<LocalAddr>) -> Option<&mut PortState> {
+        let key = self.key(local)?;
+        self.ports.get_mut(&key)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize, const L: usize, const P: usize> defmt::Format for TcpListener<N, L, P> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "[");
+        for (handle, local) in self.bound_addrs() {
+            defmt::write!(
+                fmt,
+                "[{:?}, port: {}, pending: {}/{}],",
+                handle,
+                local.port(),
+                self.pending(local),
+                self.backlog(local).unwrap_or(0)
+            );
+        }
+        defmt::write!(
+            fmt,
+            "] bound: {}/{}, pending: {}/{}",
+            self.bound_count(),
+            self.capacity(),
+            self.total_pending(),
+            self.backlog_capacity()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::Vec;
+    use no_std_net::{Ipv4Addr, SocketAddrV4};
+
+    #[test]
+    fn unbind_and_rebind() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(2), remote, Instant::from_secs(0))
+            .unwrap();
+
+        let mut events: Vec<TeardownEvent, 2> = Vec::new();
+        listener
+            .unbind(SocketHandle::new(0), |event| events.push(event).unwrap())
+            .unwrap();
+        assert_eq!(
+            &events[..],
+            &[TeardownEvent::Pending {
+                listener: SocketHandle::new(0),
+                handle: SocketHandle::new(2)
+            }]
+        );
+        assert!(!listener.is_port_bound(80));
+        assert_eq!(
+            listener.available(SocketHandle::new(0)),
+            Err(ListenerError::NotBound)
+        );
+        assert_eq!(
+            listener.unbind(SocketHandle::new(0), |_| {}),
+            Err(ListenerError::NotBound)
+        );
+
+        listener.bind_port(SocketHandle::new(1), 80, 2).unwrap();
+        assert_eq!(listener.available(SocketHandle::new(1)), Ok(false));
+    }
+
+    #[test]
+    fn errors() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(0), 81, 2),
+            Err(ListenerError::AlreadyBound)
+        );
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(1), 80, 2),
+            Err(ListenerError::PortInUse)
+        );
+        listener.bind_port(SocketHandle::new(1), 81, 2).unwrap();
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(2), 82, 2),
+            Err(ListenerError::Full)
+        );
+
+        assert_eq!(
+            listener.accept(SocketHandle::new(0)),
+            Err(ListenerError::WouldBlock)
+        );
+        assert_eq!(
+            listener.accept(SocketHandle::new(2)),
+            Err(ListenerError::NotBound)
+        );
+        listener
+            .push_incoming(80, SocketHandle::new(3), remote, Instant::from_secs(0))
+            .unwrap();
+        assert_eq!(
+            listener.accept(SocketHandle::new(0)),
+            Ok((SocketHandle::new(3), remote))
+        );
+        assert_eq!(
+            crate::Error::from(ListenerError::WouldBlock),
+            crate::Error::ListenerError
+        );
+    }
+
+    #[test]
+    fn bound_ports() {
+        let mut listener = TcpListener::<4, 3>::new();
+        assert_eq!(listener.bound_ports().count(), 0);
+        assert!(!listener.is_port_bound(80));
+
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        assert!(listener.is_bound(SocketHandle::new(0)));
+        assert!(listener.is_port_bound(80));
+        assert_eq!(listener.get_port(SocketHandle::new(0)), Ok(80));
+        assert_eq!(
+            listener.bound_ports().collect::<Vec<_, 4>>(),
+            [(SocketHandle::new(0), 80)]
+        );
+
+        listener.bind_port(SocketHandle::new(1), 443, 2).unwrap();
+        listener.bind_port(SocketHandle::new(2), 8080, 2).unwrap();
+        assert_eq!(
+            listener.bound_ports().collect::<Vec<_, 4>>(),
+            [
+                (SocketHandle::new(0), 80),
+                (SocketHandle::new(1), 443),
+                (SocketHandle::new(2), 8080)
+            ]
+        );
+
+        listener.unbind(SocketHandle::new(1), |_| {}).unwrap();
+        assert!(!listener.is_bound(SocketHandle::new(1)));
+        assert!(!listener.is_port_bound(443));
+        assert_eq!(
+            listener.get_port(SocketHandle::new(1)),
+            Err(ListenerError::NotBound)
+        );
+        let mut ports = listener.bound_ports().collect::<Vec<_, 4>>();
+        ports.sort_unstable();
+        assert_eq!(
+            ports,
+            [(SocketHandle::new(0), 80), (SocketHandle::new(2), 8080)]
+        );
+    }
+
+    #[test]
+    fn reserved_ports() {
+        let mut listener =
+            TcpListener::<2, 3, 4>::with_port_allocator(PortAllocator::new(8000..8003));
+        listener.bind_port(SocketHandle::new(0), 8000, 2).unwrap();
+        assert_eq!(listener.allocate_port(), Some(8001));
+        assert_eq!(listener.release_port(8000), Err(ListenerError::PortInUse));
+
+        // A client port cannot be bound, and a failed bind does not keep the
+        // reservation.
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(1), 8001, 2),
+            Err(ListenerError::PortInUse)
+        );
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(0), 8002, 2),
+            Err(ListenerError::AlreadyBound)
+        );
+        assert!(!listener.port_allocator().unwrap().is_in_use(8002));
+
+        // The port stays reserved while another binding holds it.
+        let local = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 8000));
+        listener.bind(SocketHandle::new(1), local, 1).unwrap();
+        listener.unbind(SocketHandle::new(0), |_| {}).unwrap();
+        assert_eq!(listener.allocate_port(), Some(8002));
+        assert_eq!(listener.allocate_port(), None);
+
+        listener.unbind_all(|_| {});
+        assert_eq!(listener.allocate_port(), Some(8000));
+    }
+
+    #[test]
+    fn backlog_full() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+
+        // A queue of capacity 3 holds 2 connections.
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        assert_eq!(listener.dropped(80), Some(0));
+        assert_eq!(listener.dropped(81), None);
+        assert_eq!(
+            listener.push_incoming(81, SocketHandle::new(1), remote, Instant::from_secs(0)),
+            Err(ListenerError::NotBound)
+        );
+
+        for id in 1..=2 {
+            listener
+                .push_incoming(80, SocketHandle::new(id), remote, Instant::from_secs(0))
+                .unwrap();
+        }
+        for id in 3..=4 {
+            assert_eq!(
+                listener.push_incoming(80, SocketHandle::new(id), remote, Instant::from_secs(0)),
+                Err(ListenerError::BacklogFull)
+            );
+        }
+        assert_eq!(listener.dropped(80), Some(2));
+
+        assert_eq!(
+            listener.accept(SocketHandle::new(0)),
+            Ok((SocketHandle::new(1), remote))
+        );
+        listener
+            .push_incoming(80, SocketHandle::new(5), remote, Instant::from_secs(0))
+            .unwrap();
+        assert_eq!(listener.dropped(80), Some(2));
+
+        listener.unbind(SocketHandle::new(0), |_| {}).unwrap();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        assert_eq!(listener.dropped(80), Some(0));
+    }
+
+    #[test]
+    fn bind_same_port_keeps_queue() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(2), remote, Instant::from_secs(0))
+            .unwrap();
+
+        // Used to replace the queue of the port, orphaning handle 0.
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(1), 80, 2),
+            Err(ListenerError::PortInUse)
+        );
+        assert!(!listener.is_bound(SocketHandle::new(1)));
+        assert_eq!(
+            listener.accept(SocketHandle::new(0)),
+            Ok((SocketHandle::new(2), remote))
+        );
+    }
+
+    #[test]
+    fn expire_stale_connections() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+        let max_age = Duration::from_secs(10);
+
+        let mut listener = TcpListener::<2, 4>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 3).unwrap();
+        for (id, secs) in [(1, 0), (2, 5), (3, 20)] {
+            listener
+                .push_incoming(80, SocketHandle::new(id), remote, Instant::from_secs(secs))
+                .unwrap();
+        }
+
+        let mut expired: Vec<SocketHandle, 3> = Vec::new();
+        assert_eq!(
+            listener.expire_older_than(80, Instant::from_secs(12), max_age, |handle| {
+                expired.push(handle).unwrap()
+            }),
+            1
+        );
+        assert_eq!(&expired[..], &[SocketHandle::new(1)]);
+        assert_eq!(
+            listener.expire_older_than(81, Instant::from_secs(12), max_age, |_| {}),
+            0
+        );
+
+        expired.clear();
+        assert_eq!(
+            listener.accept_fresh(
+                SocketHandle::new(0),
+                Instant::from_secs(25),
+                max_age,
+                |handle| { expired.push(handle).unwrap() }
+            ),
+            Ok((SocketHandle::new(3), remote))
+        );
+        assert_eq!(&expired[..], &[SocketHandle::new(2)]);
+        assert_eq!(
+            listener.accept_fresh(
+                SocketHandle::new(0),
+                Instant::from_secs(25),
+                max_age,
+                |_| {}
+            ),
+            Err(ListenerError::WouldBlock)
+        );
+        assert_eq!(
+            listener.accept_fresh(
+                SocketHandle::new(1),
+                Instant::from_secs(25),
+                max_age,
+                |_| {}
+            ),
+            Err(ListenerError::NotBound)
+        );
+    }
+
+    #[test]
+    fn accept_into_set() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+        let ts = Instant::from_secs(0);
+
+        let mut set = SocketSet::<4, 64>::new();
+        set.add(TcpSocket::new(0)).unwrap();
+        set.add(TcpSocket::new(2)).unwrap();
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(3), remote, ts)
+            .unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(2), remote, ts)
+            .unwrap();
+
+        // Socket 3 is not in the set yet, so it stays queued.
+        assert_eq!(
+            listener.accept_into(SocketHandle::new(0), &mut set, ts),
+            Err(Error::InvalidSocket)
+        );
+        assert_eq!(listener.available(SocketHandle::new(0)), Ok(true));
+
+        set.add(TcpSocket::new(3)).unwrap();
+        assert_eq!(
+            listener.accept_into(SocketHandle::new(0), &mut set, ts),
+            Ok((SocketHandle::new(3), remote))
+        );
+        let mut socket = set.get::<TcpSocket<64>>(SocketHandle::new(3)).unwrap();
+        assert!(socket.is_connected());
+        // The idle timeout runs from the accept
+        socket.set_idle_timeout(Some(Duration::from_secs(30)));
+        assert!(!socket.recycle(ts + Duration::from_secs(29)));
+        assert!(socket.recycle(ts + Duration::from_secs(30)));
+
+        assert_eq!(
+            listener.accept_into(SocketHandle::new(0), &mut set, ts),
+            Ok((SocketHandle::new(2), remote))
+        );
+        assert_eq!(
+            listener.accept_into(SocketHandle::new(0), &mut set, ts),
+            Err(Error::ListenerError)
+        );
+        assert_eq!(
+            listener.accept_into(SocketHandle::new(1), &mut set, ts),
+            Err(Error::ListenerError)
+        );
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    fn accept_into_wrong_type() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+
+        let mut set = SocketSet::<2, 64>::new();
+        set.add(crate::UdpSocket::new(1)).unwrap();
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        let ts = Instant::from_secs(0);
+        listener
+            .push_incoming(80, SocketHandle::new(1), remote, ts)
+            .unwrap();
+
+        assert_eq!(
+            listener.accept_into(SocketHandle::new(0), &mut set, ts),
+            Err(Error::Illegal)
+        );
+        assert_eq!(listener.available(SocketHandle::new(0)), Ok(true));
+    }
+
+    #[test]
+    fn reject_and_filter() {
+        let local = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+        let other = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 7), 4000));
+        let ts = Instant::from_secs(0);
+
+        let mut listener = TcpListener::<2, 4>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        assert_eq!(listener.rejected(80), Some(0));
+        assert_eq!(listener.rejected(81), None);
+
+        listener
+            .push_incoming(80, SocketHandle::new(1), other, ts)
+            .unwrap();
+        assert_eq!(
+            listener.reject(SocketHandle::new(0)),
+            Ok((SocketHandle::new(1), other))
+        );
+        assert_eq!(listener.rejected(80), Some(1));
+        assert_eq!(
+            listener.reject(SocketHandle::new(0)),
+            Err(ListenerError::WouldBlock)
+        );
+        assert_eq!(
+            listener.reject(SocketHandle::new(1)),
+            Err(ListenerError::NotBound)
+        );
+
+        fn is_local(addr: SocketAddr) -> bool {
+            matches!(addr, SocketAddr::V4(a) if a.ip().octets()[0] == 10)
+        }
+        listener.set_filter(80, is_local).unwrap();
+        assert_eq!(
+            listener.set_filter(81, is_local),
+            Err(ListenerError::NotBound)
+        );
+        assert_eq!(
+            listener.push_incoming(80, SocketHandle::new(2), other, ts),
+            Err(ListenerError::Rejected)
+        );
+        listener
+            .push_incoming(80, SocketHandle::new(3), local, ts)
+            .unwrap();
+        assert_eq!(listener.rejected(80), Some(2));
+        assert_eq!(listener.dropped(80), Some(0));
+        assert_eq!(
+            listener.accept(SocketHandle::new(0)),
+            Ok((SocketHandle::new(3), local))
+        );
+
+        listener.clear_filter(80).unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(2), other, ts)
+            .unwrap();
+    }
+
+    #[test]
+    fn occupancy() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+        let ts = Instant::from_secs(0);
+
+        let mut listener = TcpListener::<2, 4>::new();
+        assert_eq!(listener.capacity(), 2);
+        assert_eq!(listener.backlog_capacity(), 4);
+        assert_eq!(listener.bound_count(), 0);
+        assert_eq!(listener.total_pending(), 0);
+
+        listener.bind_port(SocketHandle::new(0), 80, 1).unwrap();
+        listener.bind_port(SocketHandle::new(1), 443, 4).unwrap();
+        assert_eq!(listener.bound_count(), listener.capacity());
+        assert_eq!(listener.backlog(80), Some(1));
+        assert_eq!(listener.backlog(8080), None);
+
+        // The backlog of the port runs out first
+        listener
+            .push_incoming(80, SocketHandle::new(2), remote, ts)
+            .unwrap();
+        assert_eq!(
+            listener.push_incoming(80, SocketHandle::new(3), remote, ts),
+            Err(ListenerError::BacklogFull)
+        );
+
+        // The shared entries run out first
+        for id in 4..7 {
+            listener
+                .push_incoming(443, SocketHandle::new(id), remote, ts)
+                .unwrap();
+        }
+        assert_eq!(
+            listener.push_incoming(443, SocketHandle::new(7), remote, ts),
+            Err(ListenerError::BacklogFull)
+        );
+        assert_eq!(listener.pending(80), 1);
+        assert_eq!(listener.pending(443), 3);
+        assert_eq!(listener.pending(8080), 0);
+        assert_eq!(listener.total_pending(), listener.backlog_capacity());
+        assert_eq!(listener.dropped(80), Some(1));
+        assert_eq!(listener.dropped(443), Some(1));
+
+        listener.accept(SocketHandle::new(0)).unwrap();
+        assert_eq!(listener.total_pending(), 3);
+        listener
+            .push_incoming(443, SocketHandle::new(7), remote, ts)
+            .unwrap();
+        assert_eq!(
+            listener.accept(SocketHandle::new(1)),
+            Ok((SocketHandle::new(4), remote))
+        );
+    }
+
+    #[test]
+    fn shared_backlog_size() {
+        use core::mem::size_of;
+
+        // The pending connections of all ports share one pool, rather than
+        // each port holding a queue as long as the largest backlog: the size
+        // grows by one entry per slot of the pool, whatever the number of
+        // ports, and the cost of a port does not depend on the pool.
+        let pool_growth = size_of::<TcpListener<4, 16>>() - size_of::<TcpListener<4, 8>>();
+        assert_eq!(pool_growth, 8 * size_of::<Pending>());
+        assert_eq!(
+            size_of::<TcpListener<8, 16>>() - size_of::<TcpListener<8, 8>>(),
+            pool_growth
+        );
+        assert_eq!(
+            size_of::<TcpListener<8, 16>>() - size_of::<TcpListener<4, 16>>(),
+            size_of::<TcpListener<8, 8>>() - size_of::<TcpListener<4, 8>>()
+        );
+    }
+
+    #[test]
+    fn invalid_backlog() {
+        let mut listener = TcpListener::<2, 4>::new();
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(0), 80, 0),
+            Err(ListenerError::InvalidBacklog)
+        );
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(0), 80, 5),
+            Err(ListenerError::InvalidBacklog)
+        );
+        assert_eq!(listener.bound_count(), 0);
+        assert_eq!(listener.bind_port(SocketHandle::new(0), 80, 4), Ok(()));
+        assert_eq!(listener.backlog(80), Some(4));
+    }
+
+    #[test]
+    fn local_addresses() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+        let apn1 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(100, 64, 0, 1), 80));
+        let apn2 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(100, 64, 0, 2), 80));
+        let ts = Instant::from_secs(0);
+
+        let mut listener = TcpListener::<4, 3>::new();
+        listener.bind(SocketHandle::new(0), apn1, 2).unwrap();
+        assert_eq!(
+            listener.bind(SocketHandle::new(1), apn1, 2),
+            Err(ListenerError::PortInUse)
+        );
+        assert_eq!(listener.get_local_addr(SocketHandle::new(0)), Ok(apn1));
+        assert_eq!(listener.get_port(SocketHandle::new(0)), Ok(80));
+
+        // The only binding of the port matches the bare port
+        assert!(listener.is_port_bound(80));
+        assert!(!listener.is_port_bound(apn2));
+
+        listener.bind(SocketHandle::new(1), apn2, 2).unwrap();
+        assert!(!listener.is_port_bound(80));
+        listener.bind_port(SocketHandle::new(2), 80, 2).unwrap();
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(3), 80, 2),
+            Err(ListenerError::PortInUse)
+        );
+
+        listener
+            .push_incoming(apn2, SocketHandle::new(4), remote, ts)
+            .unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(5), remote, ts)
+            .unwrap();
+        let apn3 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(100, 64, 0, 3), 80));
+        listener
+            .push_incoming(apn3, SocketHandle::new(6), remote, ts)
+            .unwrap();
+        assert_eq!(listener.pending(apn1), 0);
+        assert_eq!(listener.pending(apn2), 1);
+        assert_eq!(listener.pending(80), 2);
+
+        assert_eq!(
+            listener.accept(SocketHandle::new(1)),
+            Ok((SocketHandle::new(4), remote))
+        );
+        assert_eq!(
+            listener.accept(SocketHandle::new(2)),
+            Ok((SocketHandle::new(5), remote))
+        );
+
+        listener.unbind(SocketHandle::new(2), |_| {}).unwrap();
+        assert_eq!(
+            listener.push_incoming(apn3, SocketHandle::new(7), remote, ts),
+            Err(ListenerError::NotBound)
+        );
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+        let other = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 7), 4000));
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        assert_eq!(listener.stats(80), Some(&crate::ListenerStats::default()));
+        assert_eq!(listener.stats(81), None);
+
+        for (id, secs) in [(1, 0), (2, 10), (3, 10)] {
+            listener
+                .push_incoming(80, SocketHandle::new(id), remote, Instant::from_secs(secs))
+                .ok();
+        }
+        listener.accept(SocketHandle::new(0)).unwrap();
+        listener.reject(SocketHandle::new(0)).unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(4), remote, Instant::from_secs(0))
+            .unwrap();
+        listener.expire_older_than(80, Instant::from_secs(20), Duration::from_secs(5), |_| {});
+        listener.set_filter(80, |addr| addr.port() != 4000).unwrap();
+        listener
+            .push_incoming(80, SocketHandle::new(5), other, Instant::from_secs(20))
+            .ok();
+
+        assert_eq!(
+            listener.stats(80),
+            Some(&crate::ListenerStats {
+                accepted: 1,
+                rejected: 2,
+                dropped: 1,
+                expired: 1,
+                peak_backlog: 2,
+            })
+        );
+
+        listener.unbind(SocketHandle::new(0), |_| {}).unwrap();
+        assert_eq!(listener.stats(80), None);
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        assert_eq!(listener.stats(80), Some(&crate::ListenerStats::default()));
+    }
+
+    #[test]
+    fn unbind_all() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        listener.bind_port(SocketHandle::new(1), 443, 2).unwrap();
+        listener
+            .push_incoming(443, SocketHandle::new(2), remote, Instant::from_secs(0))
+            .unwrap();
+
+        let mut dropped = 0;
+        listener.unbind_all(|event| {
+            if let TeardownEvent::Pending { .. } = event {
+                dropped += 1;
+            }
+        });
+        assert_eq!(dropped, 1);
+        assert!(!listener.is_port_bound(80));
+        assert!(!listener.is_port_bound(443));
+
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        listener.bind_port(SocketHandle::new(1), 443, 2).unwrap();
+    }
+
+    #[test]
+    fn teardown_order() {
+        let remote = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000));
+
+        let mut listener = TcpListener::<2, 3>::new();
+        listener.bind_port(SocketHandle::new(0), 80, 2).unwrap();
+        listener.bind_port(SocketHandle::new(1), 443, 2).unwrap();
+        for id in 2..4 {
+            listener
+                .push_incoming(80, SocketHandle::new(id), remote, Instant::from_secs(0))
+                .unwrap();
+        }
+
+        let mut events: Vec<TeardownEvent, 4> = Vec::new();
+        listener.teardown(|event| events.push(event).unwrap());
+
+        assert_eq!(
+            &events[..],
+            &[
+                TeardownEvent::Pending {
+                    listener: SocketHandle::new(0),
+                    handle: SocketHandle::new(2)
+                },
+                TeardownEvent::Pending {
+                    listener: SocketHandle::new(0),
+                    handle: SocketHandle::new(3)
+                },
+                TeardownEvent::Listener {
+                    handle: SocketHandle::new(0),
+                    port: 80
+                },
+                TeardownEvent::Listener {
+                    handle: SocketHandle::new(1),
+                    port: 443
+                },
+            ]
+        );
     }
 }
@@ -1,57 +1,239 @@
 use core::cmp::min;
+use core::task::{Context, Poll, Waker};
 
-use super::{Error, Result, RingBuffer, Socket, SocketHandle, SocketMeta};
+use super::{
+    CloseReason, ConnectionStatus, Error, Result, RingBuffer, RxOverflow, SocketHandle, SocketMeta,
+};
 use embassy_time::{Duration, Instant};
+use heapless::{Deque, String, Vec};
 pub use no_std_net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
 /// A UDP socket ring buffer.
 pub type SocketBuffer<const N: usize> = RingBuffer<u8, N>;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// Maximum number of datagrams held in the receive buffer of a UDP socket.
+pub const MAX_DATAGRAMS: usize = 8;
+
+/// Maximum number of multicast groups a UDP socket can join.
+pub const MAX_MULTICAST_GROUPS: usize = 4;
+
+/// Metadata of a datagram dequeued with [`UdpSocket::recv_datagram`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpMetadata {
+    /// The sender of the datagram. For data enqueued with
+    /// [`UdpSocket::rx_enqueue_slice`], this is the remote endpoint the
+    /// socket is connected to, or the unspecified address if it is not.
+    pub remote: SocketAddr,
+    /// The length of the datagram.
+    pub len: usize,
+    /// Whether the datagram did not fit in the given slice, and the rest of
+    /// it was discarded.
+    pub truncated: bool,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for UdpMetadata {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "UdpMetadata {{ remote: {}, len: {}, truncated: {} }}",
+            defmt::Debug2Format(&self.remote),
+            self.len,
+            self.truncated
+        )
+    }
+}
+
+/// Maximum length of a hostname a datagram can be sent to; see
+/// [`UdpSocket::send_to_host`].
+pub const MAX_HOSTNAME_LEN: usize = 64;
+
+/// Where a datagram dequeued for sending is addressed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpDestination<'a> {
+    Ip(SocketAddr),
+    /// A hostname, resolved by the module when sending.
+    Host(&'a str, u16),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for UdpDestination<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            UdpDestination::Ip(addr) => defmt::write!(fmt, "{}", defmt::Debug2Format(addr)),
+            UdpDestination::Host(host, port) => defmt::write!(fmt, "{}:{}", host, port),
+        }
+    }
+}
+
+/// The stored form of a [`UdpDestination`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Destination {
+    Ip(SocketAddr),
+    Host(String<MAX_HOSTNAME_LEN>, u16),
+}
+
+/// Traffic statistics of a UDP socket.
+#[cfg(feature = "stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UdpStats {
+    /// Datagrams enqueued into the receive buffer.
+    pub datagrams_enqueued: u32,
+    /// Datagrams dequeued from the receive buffer by the application.
+    pub datagrams_delivered: u32,
+    /// Received datagrams dropped because they did not fit in the receive
+    /// buffer.
+    pub datagrams_dropped: u32,
+    /// Bytes enqueued into the receive buffer.
+    pub rx_bytes: usize,
+    /// Bytes handed to the driver for sending.
+    pub tx_bytes: usize,
+    /// Number of times the socket was closed by the remote end.
+    pub remote_closes: u32,
+    /// Number of times the socket was recycled from a socket set.
+    pub recycles: u32,
+    /// Datagrams rejected for exceeding the MTU, in either direction.
+    pub mtu_rejections: u32,
+}
+
+/// What to do with a received datagram that does not fit in the slice it is
+/// read into.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TruncationPolicy {
+    /// Copy what fits, and discard the rest of the datagram. The full length
+    /// of the datagram is returned, so the caller can tell it was cut.
+    TruncateAndDiscardTail,
+    /// Return `Err(Error::BadLength)`, and keep the datagram queued.
+    #[default]
+    ErrorIfTooSmall,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkKind {
+    Datagram(SocketAddr),
+    /// Bytes enqueued without boundaries, read like a stream.
+    Stream,
+    /// Unused space at the end of the buffer, skipped so that a datagram
+    /// is stored contiguously.
+    Padding,
+}
+
+/// A run of bytes in the receive buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Chunk {
+    len: usize,
+    kind: ChunkKind,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum State {
+    #[default]
     Closed,
     Established,
 }
 
-impl Default for State {
+/// Configuration of a UDP socket, applied on construction with
+/// [`UdpSocket::with_config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpSocketConfig {
+    /// Interval between checks for data available on the module.
+    pub check_interval: Duration,
+    /// Time a closed socket is kept for reading.
+    pub read_timeout: Option<Duration>,
+    /// Largest datagram accepted for sending or receiving.
+    pub mtu: usize,
+    /// What to do with a received datagram that does not fit.
+    pub truncation_policy: TruncationPolicy,
+}
+
+impl Default for UdpSocketConfig {
     fn default() -> Self {
-        State::Closed
+        UdpSocketConfig {
+            check_interval: Duration::from_secs(15),
+            read_timeout: Some(Duration::from_secs(15)),
+            mtu: 512,
+            truncation_policy: TruncationPolicy::default(),
+        }
     }
 }
 
 /// A User Datagram Protocol socket.
 ///
-/// A UDP socket is bound to a specific endpoint, and owns transmit and receive
-/// packet buffers.
-#[derive(Debug)]
-pub struct UdpSocket<const L: usize> {
+/// A UDP socket is bound to a local port, or connected to a remote endpoint,
+/// and owns transmit and receive packet buffers.
+///
+/// `L` is the size of the receive buffer, and `TXL` the size of the transmit
+/// buffer.
+pub struct UdpSocket<const L: usize, const TXL: usize = L> {
     pub(crate) meta: SocketMeta,
     pub(crate) endpoint: Option<SocketAddr>,
+    local_port: Option<u16>,
     check_interval: Duration,
     read_timeout: Option<Duration>,
     state: State,
     available_data: usize,
+    decrement_available_data: bool,
     rx_buffer: SocketBuffer<L>,
+    chunks: Deque<Chunk, MAX_DATAGRAMS>,
+    tx_buffer: SocketBuffer<TXL>,
+    tx_datagrams: Deque<(usize, Destination), MAX_DATAGRAMS>,
+    /// Hostname of the datagram last dequeued for sending, borrowed by the
+    /// returned [`UdpDestination`].
+    tx_host: String<MAX_HOSTNAME_LEN>,
+    mtu: usize,
+    truncation_policy: TruncationPolicy,
+    broadcast: bool,
+    multicast_groups: Vec<Ipv4Addr, MAX_MULTICAST_GROUPS>,
     last_check_time: Option<Instant>,
     closed_time: Option<Instant>,
+    close_reason: Option<CloseReason>,
+    deferred_check: bool,
+    dropped_bytes: usize,
+    rx_waker: Option<Waker>,
+    #[cfg(feature = "stats")]
+    stats: UdpStats,
 }
 
-impl<const L: usize> UdpSocket<L> {
+impl<const L: usize, const TXL: usize> UdpSocket<L, TXL> {
     /// Create an UDP socket with the given buffers.
-    pub fn new(socket_id: u8) -> UdpSocket<L> {
+    pub fn new(socket_id: u8) -> UdpSocket<L, TXL> {
+        Self::with_config(socket_id, UdpSocketConfig::default())
+    }
+
+    /// Create an UDP socket with the given configuration.
+    pub fn with_config(socket_id: u8, config: UdpSocketConfig) -> UdpSocket<L, TXL> {
         UdpSocket {
             meta: SocketMeta {
-                handle: SocketHandle(socket_id),
+                handle: SocketHandle::new(socket_id),
+                module_id: Some(socket_id),
             },
-            check_interval: Duration::from_secs(15),
+            check_interval: config.check_interval,
             state: State::Closed,
-            read_timeout: Some(Duration::from_secs(15)),
+            read_timeout: config.read_timeout,
             endpoint: None,
+            local_port: None,
             available_data: 0,
+            decrement_available_data: false,
             rx_buffer: SocketBuffer::new(),
+            chunks: Deque::new(),
+            tx_buffer: SocketBuffer::new(),
+            tx_datagrams: Deque::new(),
+            tx_host: String::new(),
+            mtu: config.mtu,
+            truncation_policy: config.truncation_policy,
+            broadcast: false,
+            multicast_groups: Vec::new(),
             last_check_time: None,
             closed_time: None,
+            close_reason: None,
+            deferred_check: false,
+            dropped_bytes: 0,
+            rx_waker: None,
+            #[cfg(feature = "stats")]
+            stats: UdpStats::default(),
         }
     }
 
@@ -60,25 +242,65 @@ impl<const L: usize> UdpSocket<L> {
         self.meta.handle
     }
 
+    #[deprecated(note = "the set assigns handles; use `set_module_id` to follow the module's id")]
     pub fn update_handle(&mut self, handle: SocketHandle) {
         debug!(
             "[UDP Socket] [{:?}] Updating handle {:?}",
             self.handle(),
             handle
         );
-        self.meta.update(handle)
+        self.meta.update(handle);
+        self.meta.module_id = Some(handle.id());
+    }
+
+    /// Return the socket id the module assigned to this socket, if any.
+    ///
+    /// This starts out as the id the socket was created with.
+    pub fn module_id(&self) -> Option<u8> {
+        self.meta.module_id
+    }
+
+    /// Set the socket id the module assigned to this socket, e.g. after the
+    /// module reassigned ids. The handle of the socket is left unchanged.
+    pub fn set_module_id(&mut self, module_id: Option<u8>) {
+        debug!(
+            "[UDP Socket] [{:?}] Updating module id {:?}",
+            self.handle(),
+            module_id
+        );
+        self.meta.module_id = module_id;
     }
 
-    /// Return the bound endpoint.
+    /// Return the remote endpoint the socket is connected to.
     pub fn endpoint(&self) -> Option<SocketAddr> {
         self.endpoint
     }
 
+    /// Return the local port the socket is bound to.
+    pub fn local_port(&self) -> Option<u16> {
+        self.local_port
+    }
+
     /// Return the connection state, in terms of the UDP connection.
     pub fn state(&self) -> State {
         self.state
     }
 
+    /// Return the connection status, as seen by the application.
+    ///
+    /// An open socket counts as connected until it is closed.
+    pub fn connection_status(&self) -> ConnectionStatus {
+        if self.close_reason.is_some() {
+            ConnectionStatus::Disconnected {
+                reason: self.close_reason,
+            }
+        } else if self.is_open() {
+            ConnectionStatus::Connected
+        } else {
+            ConnectionStatus::Disconnected { reason: None }
+        }
+    }
+
     pub fn set_state(&mut self, state: State) {
         debug!(
             "[UDP Socket] {:?}, state change: {:?} -> {:?}",
@@ -89,32 +311,196 @@ impl<const L: usize> UdpSocket<L> {
         self.state = state
     }
 
-    pub fn should_update_available_data(&mut self) -> bool {
+    pub fn should_update_available_data(&mut self, ts: Instant) -> bool {
+        // Data read now could not be stored, so hold off until the
+        // application has made room, and check right away once it has.
+        if self.rx_buffer.is_full() {
+            self.deferred_check = true;
+            return false;
+        }
+
+        let should_update = core::mem::take(&mut self.deferred_check)
+            || self
+                .last_check_time
+                .and_then(|last_check_time| ts.checked_duration_since(last_check_time))
+                .map(|dur| dur >= self.check_interval)
+                .unwrap_or(true);
+
+        if should_update {
+            self.last_check_time.replace(ts);
+        }
+
+        should_update
+    }
+
+    /// Return the time of the last check for available data, if any.
+    pub fn last_check_time(&self) -> Option<Instant> {
         self.last_check_time
-            .replace(Instant::now())
-            .and_then(|last_check_time| Instant::now().checked_duration_since(last_check_time))
-            .map(|dur| dur >= self.check_interval)
-            .unwrap_or(false)
     }
 
-    pub fn recycle(&self) -> bool {
-        if let Some(read_timeout) = self.read_timeout {
-            self.closed_time
-                .and_then(|closed_time| Instant::now().checked_duration_since(closed_time))
+    /// Return how long until [should_update_available_data](#method.should_update_available_data)
+    /// next returns true, or `None` if it will not until the state of the
+    /// socket changes; e.g. when not open, or while the receive buffer is
+    /// full.
+    pub fn time_until_next_check(&self, ts: Instant) -> Option<Duration> {
+        if !self.is_open() || self.rx_buffer.is_full() {
+            return None;
+        }
+        if self.deferred_check {
+            return Some(Duration::from_ticks(0));
+        }
+
+        match self.last_check_time {
+            Some(last_check_time) => last_check_time.checked_add(self.check_interval).map(|due| {
+                due.checked_duration_since(ts)
+                    .unwrap_or(Duration::from_ticks(0))
+            }),
+            None => Some(Duration::from_ticks(0)),
+        }
+    }
+
+    /// Set the interval between checks for data available on the module.
+    /// Defaults to 15 seconds.
+    pub fn set_check_interval(&mut self, interval: Duration) {
+        self.check_interval = interval;
+    }
+
+    /// Return the interval between checks for data available on the module.
+    pub fn check_interval(&self) -> Duration {
+        self.check_interval
+    }
+
+    /// Set the time a closed socket is kept for reading before it is
+    /// recycled, or `None` to keep it. Defaults to 15 seconds.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Return the time a closed socket is kept for reading.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Return whether the socket should be recycled at `ts`.
+    ///
+    /// Like [`TcpSocket::recycle`](crate::TcpSocket::recycle), this is the
+    /// case once the read timeout has elapsed after the socket was closed,
+    /// either by the remote end or with [close](#method.close). Without a
+    /// read timeout, a socket closed by the remote end is kept for reading,
+    /// while a socket closed locally has nothing left to read, and is
+    /// recycled right away. A socket that was never opened is not recycled.
+    pub fn recycle(&self, ts: Instant) -> bool {
+        let closed_time = match self.closed_time {
+            Some(closed_time) => closed_time,
+            None => return false,
+        };
+
+        match self.read_timeout {
+            Some(read_timeout) => ts
+                .checked_duration_since(closed_time)
                 .map(|dur| dur >= read_timeout)
-                .unwrap_or(false)
-        } else {
-            false
+                .unwrap_or(false),
+            None => !self.is_open(),
+        }
+    }
+
+    /// Record that the remote end closed the socket at `ts`.
+    pub fn closed_by_remote(&mut self, ts: Instant) {
+        self.closed_time.replace(ts);
+        self.close_reason.get_or_insert(CloseReason::RemoteFin);
+        self.wake_rx();
+        #[cfg(feature = "stats")]
+        {
+            self.stats.remote_closes += 1;
         }
     }
 
-    pub fn closed_by_remote(&mut self) {
-        self.closed_time.replace(Instant::now());
+    /// Register a waker to be woken when data is received, or the socket is
+    /// closed by the remote end.
+    ///
+    /// Only one waker is stored; registering a new one replaces the previous
+    /// one. The waker is consumed when woken, so it has to be registered again
+    /// on every poll.
+    pub fn register_rx_waker(&mut self, waker: &Waker) {
+        match self.rx_waker {
+            Some(ref w) if w.will_wake(waker) => {}
+            _ => self.rx_waker = Some(waker.clone()),
+        }
+    }
+
+    fn wake_rx(&mut self) {
+        if let Some(waker) = self.rx_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Return `Poll::Ready(Ok(()))` if received data is queued, or register
+    /// the waker of `cx` and return `Poll::Pending`.
+    ///
+    /// This returns `Poll::Ready(Err(Error::Illegal))` if the socket is not
+    /// open, and `Poll::Ready(Err(Error::SocketClosed))` once it is closed by
+    /// the remote end and no data is left.
+    pub fn poll_recv_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if !self.is_open() {
+            return Poll::Ready(Err(Error::Illegal));
+        }
+        // A queued zero-length datagram counts as data.
+        if self.recv_queue() > 0 || !self.chunks.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        if self.closed_time.is_some() {
+            return Poll::Ready(Err(Error::SocketClosed));
+        }
+
+        self.register_rx_waker(cx.waker());
+        Poll::Pending
+    }
+
+    /// Return the amount of octets queued in the receive buffer.
+    pub fn recv_queue(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.kind != ChunkKind::Padding)
+            .map(|chunk| chunk.len)
+            .sum()
+    }
+
+    /// Return the number of datagrams queued in the receive buffer.
+    ///
+    /// Unlike [recv_queue](#method.recv_queue), this also counts zero-length
+    /// datagrams.
+    pub fn datagrams_queued(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| matches!(chunk.kind, ChunkKind::Datagram(_)))
+            .count()
+    }
+
+    /// Return the traffic statistics of the socket.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> UdpStats {
+        self.stats
+    }
+
+    /// Reset the traffic statistics of the socket.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = UdpStats::default();
+    }
+
+    /// Count the recycling of the socket from a socket set.
+    #[cfg(all(feature = "stats", feature = "socket-udp"))]
+    pub(crate) fn count_recycle(&mut self) {
+        self.stats.recycles += 1;
     }
 
     /// Set available data.
     pub fn set_available_data(&mut self, available_data: usize) {
+        let was_empty = self.available_data == 0;
         self.available_data = available_data;
+        if available_data > 0 && was_empty {
+            self.wake_rx();
+        }
     }
 
     /// Get the number of bytes available to ingress.
@@ -122,32 +508,93 @@ impl<const L: usize> UdpSocket<L> {
         self.available_data
     }
 
+    /// Make [rx_enqueue_datagram](#method.rx_enqueue_datagram) and
+    /// [rx_enqueue_slice](#method.rx_enqueue_slice) subtract the data they
+    /// are given from the available data, so that
+    /// [get_available_data](#method.get_available_data) reflects what is
+    /// still pending on the module between polls. Dropped bytes count too, as
+    /// they were read from the module all the same. Defaults to `false`.
+    pub fn set_decrement_available_data(&mut self, enabled: bool) {
+        self.decrement_available_data = enabled;
+    }
+
+    /// Return the number of bytes to request in the next read from the
+    /// module; see [`TcpSocket::max_read_len`](crate::TcpSocket::max_read_len).
+    pub fn max_read_len(&self, at_chunk_limit: usize) -> usize {
+        self.available_data
+            .min(self.rx_window())
+            .min(at_chunk_limit)
+    }
+
     pub fn rx_window(&self) -> usize {
         self.rx_buffer.window()
     }
 
-    /// Bind the socket to the given endpoint.
+    /// Bind the socket to the given local port.
     ///
-    /// This function returns `Err(Error::Illegal)` if the socket was open
-    /// (see [is_open](#method.is_open)), and `Err(Error::Unaddressable)`
-    /// if the port in the given endpoint is zero.
-    pub fn bind<T: Into<SocketAddr>>(&mut self, endpoint: T) -> Result<()> {
-        if self.is_open() {
+    /// This function returns `Err(Error::Illegal)` if the socket was already
+    /// bound, and `Err(Error::Unaddressable)` if the port is zero.
+    pub fn bind(&mut self, port: u16) -> Result<()> {
+        if self.local_port.is_some() {
             return Err(Error::Illegal);
         }
+        if port == 0 {
+            return Err(Error::Unaddressable);
+        }
 
-        self.endpoint.replace(endpoint.into());
+        self.reopen();
+        self.local_port.replace(port);
         Ok(())
     }
 
-    /// Check whether the socket is open.
+    /// Connect the socket to the given remote endpoint, so that data is sent
+    /// there by [send_slice](#method.send_slice).
+    ///
+    /// This function returns `Err(Error::Illegal)` if the socket was already
+    /// connected, and `Err(Error::Unaddressable)` if the port in the given
+    /// endpoint is zero.
+    pub fn connect<T: Into<SocketAddr>>(&mut self, remote: T) -> Result<()> {
+        if self.endpoint.is_some() {
+            return Err(Error::Illegal);
+        }
+        let remote = remote.into();
+        if remote.port() == 0 {
+            return Err(Error::Unaddressable);
+        }
+
+        self.reopen();
+        self.endpoint.replace(remote);
+        self.set_state(State::Established);
+        Ok(())
+    }
+
+    /// Forget that a socket was closed, when it is opened again.
+    fn reopen(&mut self) {
+        if !self.is_open() {
+            self.closed_time = None;
+            self.close_reason = None;
+        }
+    }
+
+    /// Check whether the socket is open, i.e. bound or connected.
     pub fn is_open(&self) -> bool {
-        self.endpoint.is_some()
+        self.endpoint.is_some() || self.local_port.is_some()
+    }
+
+    /// Check whether the socket is able to send.
+    pub fn may_send(&self) -> bool {
+        self.is_open()
+    }
+
+    /// Check whether the socket is open (see [may_send](#method.may_send)),
+    /// and there is room left to queue a datagram.
+    pub fn can_send(&self) -> bool {
+        self.may_send() && self.tx_buffer.window() > 0 && !self.tx_datagrams.is_full()
     }
 
-    /// Check whether the receive buffer is full.
+    /// Check whether the socket is open and the receive buffer is not full.
     pub fn can_recv(&self) -> bool {
-        !self.rx_buffer.is_full()
+        self.is_open() && !self.rx_buffer.is_full()
     }
 
     // /// Return the maximum number packets the socket can receive.
@@ -162,9 +609,36 @@ impl<const L: usize> UdpSocket<L> {
     //     self.rx_buffer.payload_capacity()
     // }
 
+    /// Dequeue the padding in front of the next datagram, if any.
+    fn skip_padding(&mut self) {
+        while let Some(Chunk {
+            len,
+            kind: ChunkKind::Padding,
+        }) = self.chunks.front().copied()
+        {
+            self.rx_buffer.dequeue_allocated(len);
+            self.chunks.pop_front();
+        }
+    }
+
+    /// Skip any padding, and return the chunk at the front of the receive
+    /// buffer along with its sender.
+    fn front_chunk(&mut self) -> Option<(Chunk, SocketAddr)> {
+        self.skip_padding();
+        let chunk = self.chunks.front().copied()?;
+        let remote = match chunk.kind {
+            ChunkKind::Datagram(remote) => remote,
+            _ => self.endpoint.unwrap_or(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(0, 0, 0, 0),
+                0,
+            ))),
+        };
+        Some((chunk, remote))
+    }
+
     fn recv_impl<'b, F, R>(&'b mut self, f: F) -> Result<R>
     where
-        F: FnOnce(&'b mut SocketBuffer<L>) -> (usize, R),
+        F: FnOnce(&'b mut SocketBuffer<L>, Option<(Chunk, SocketAddr)>) -> (usize, R),
     {
         // We may have received some data inside the initial SYN, but until the connection
         // is fully open we must not dequeue any data, as it may be overwritten by e.g.
@@ -173,34 +647,506 @@ impl<const L: usize> UdpSocket<L> {
             return Err(Error::Illegal);
         }
 
-        let (_size, result) = f(&mut self.rx_buffer);
+        let front = self.front_chunk();
+        let (size, result) = f(&mut self.rx_buffer, front);
+        if let Some(chunk) = self.chunks.front_mut() {
+            chunk.len -= size;
+            if chunk.len == 0 {
+                #[cfg(feature = "stats")]
+                if matches!(chunk.kind, ChunkKind::Datagram(_)) {
+                    self.stats.datagrams_delivered += 1;
+                }
+                self.chunks.pop_front();
+            }
+        }
         Ok(result)
     }
 
-    /// Dequeue a packet received from a remote endpoint, and return the endpoint as well
-    /// as a pointer to the payload.
+    /// Copy the given chunk from the front of the buffer into `data`. What
+    /// does not fit is handled according to `policy` for a datagram, and left
+    /// in the buffer otherwise. Returns the number of bytes removed from the
+    /// buffer, and the number copied.
+    fn dequeue_chunk(
+        rx_buffer: &mut SocketBuffer<L>,
+        (chunk, remote): (Chunk, SocketAddr),
+        data: &mut [u8],
+        policy: TruncationPolicy,
+    ) -> (usize, Result<(usize, UdpMetadata)>) {
+        if matches!(chunk.kind, ChunkKind::Datagram(_))
+            && chunk.len > data.len()
+            && policy == TruncationPolicy::ErrorIfTooSmall
+        {
+            return (0, Err(Error::BadLength));
+        }
+
+        let len = min(data.len(), chunk.len);
+        let copied = rx_buffer.dequeue_slice(&mut data[..len]);
+        match chunk.kind {
+            ChunkKind::Datagram(_) => {
+                rx_buffer.dequeue_allocated(chunk.len - copied);
+                let meta = UdpMetadata {
+                    remote,
+                    len: chunk.len,
+                    truncated: copied < chunk.len,
+                };
+                (chunk.len, Ok((copied, meta)))
+            }
+            _ => {
+                let meta = UdpMetadata {
+                    remote,
+                    len: copied,
+                    truncated: false,
+                };
+                (copied, Ok((copied, meta)))
+            }
+        }
+    }
+
+    /// Dequeue a datagram received from a remote endpoint, and return the
+    /// result of calling `f` with its payload.
     ///
-    /// This function returns `Err(Error::Exhausted)` if the receive buffer is empty.
+    /// The whole datagram is dequeued, whatever `f` returns. Data enqueued
+    /// with [rx_enqueue_slice](#method.rx_enqueue_slice) is dequeued up to
+    /// the size returned by `f` instead, and `f` is called with an empty slice
+    /// if the receive buffer is empty.
     pub fn recv<'b, F, R>(&'b mut self, f: F) -> Result<R>
     where
         F: FnOnce(&'b mut [u8]) -> (usize, R),
     {
-        self.recv_impl(|rx_buffer| rx_buffer.dequeue_many_with(f))
+        self.recv_impl(|rx_buffer, front| match front {
+            Some((chunk, _)) => Self::dequeue_chunk_with(rx_buffer, chunk, f),
+            None => rx_buffer.dequeue_many_with(f),
+        })
     }
 
-    /// Dequeue a packet received from a remote endpoint, copy the payload into the given slice,
-    /// and return the amount of octets copied as well as the endpoint.
+    /// Call `f` with the given chunk from the front of the buffer, and
+    /// dequeue all of it for a datagram, or as much as `f` returns otherwise.
+    fn dequeue_chunk_with<'b, F, R>(
+        rx_buffer: &'b mut SocketBuffer<L>,
+        chunk: Chunk,
+        f: F,
+    ) -> (usize, R)
+    where
+        F: FnOnce(&'b mut [u8]) -> (usize, R),
+    {
+        match chunk.kind {
+            ChunkKind::Datagram(_) => rx_buffer.dequeue_many_with(|buf| {
+                // Datagrams are stored contiguously.
+                let (_, result) = f(&mut buf[..chunk.len]);
+                (chunk.len, result)
+            }),
+            _ => rx_buffer.dequeue_many_with(|buf| {
+                let len = min(buf.len(), chunk.len);
+                f(&mut buf[..len])
+            }),
+        }
+    }
+
+    /// Dequeue a datagram received from a remote endpoint, and return the
+    /// result of calling `f` with its payload and the endpoint.
     ///
-    /// See also [recv](#method.recv).
+    /// This otherwise behaves like [recv](#method.recv), but returns
+    /// `Err(Error::Exhausted)` if the receive buffer is empty.
+    pub fn recv_from_with<'b, F, R>(&'b mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&'b mut [u8], SocketAddr) -> (usize, R),
+    {
+        self.recv_impl(|rx_buffer, front| match front {
+            Some((chunk, remote)) => {
+                let (size, result) =
+                    Self::dequeue_chunk_with(rx_buffer, chunk, |buf| f(buf, remote));
+                (size, Ok(result))
+            }
+            None => (0, Err(Error::Exhausted)),
+        })?
+    }
+
+    /// Dequeue a datagram received from a remote endpoint, copy the payload
+    /// into the given slice, and return the length of the datagram.
+    ///
+    /// A datagram that does not fit in the slice is handled according to the
+    /// [truncation policy](#method.set_truncation_policy); when truncated,
+    /// the returned length exceeds that of the slice. This returns `Ok(0)` if
+    /// the receive buffer is empty.
     pub fn recv_slice(&mut self, data: &mut [u8]) -> Result<usize> {
-        self.recv_impl(|rx_buffer| {
-            let size = rx_buffer.dequeue_slice(data);
-            (size, size)
-        })
+        match self.recv_datagram(data) {
+            Ok((_, meta)) => Ok(meta.len),
+            Err(Error::Exhausted) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Dequeue a datagram received from a remote endpoint, copy the payload
+    /// into the given slice, and return the amount of octets copied as well
+    /// as the metadata of the datagram.
+    ///
+    /// A datagram that does not fit in the slice is handled according to the
+    /// [truncation policy](#method.set_truncation_policy); truncation is
+    /// indicated by [`UdpMetadata::truncated`]. This function returns
+    /// `Err(Error::Exhausted)` if the receive buffer is empty.
+    pub fn recv_datagram(&mut self, data: &mut [u8]) -> Result<(usize, UdpMetadata)> {
+        let policy = self.truncation_policy;
+        self.recv_impl(|rx_buffer, front| match front {
+            Some(chunk) => Self::dequeue_chunk(rx_buffer, chunk, data, policy),
+            None => (0, Err(Error::Exhausted)),
+        })?
+    }
+
+    /// Set what to do with a received datagram that does not fit in the
+    /// slice it is read into. Defaults to
+    /// [`TruncationPolicy::ErrorIfTooSmall`].
+    pub fn set_truncation_policy(&mut self, policy: TruncationPolicy) {
+        self.truncation_policy = policy;
+    }
+
+    /// Return what is done with a received datagram that does not fit.
+    pub fn truncation_policy(&self) -> TruncationPolicy {
+        self.truncation_policy
+    }
+
+    /// Dequeue a datagram received from a remote endpoint, copy the payload
+    /// into the given slice, and return the length of the datagram as well
+    /// as the endpoint.
+    ///
+    /// For data enqueued with [rx_enqueue_slice](#method.rx_enqueue_slice),
+    /// the connected endpoint is returned; see [`UdpMetadata::remote`]. This
+    /// otherwise behaves like [recv_datagram](#method.recv_datagram).
+    pub fn recv_from(&mut self, data: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        self.recv_datagram(data)
+            .map(|(_, meta)| (meta.len, meta.remote))
+    }
+
+    /// Dequeue a datagram into the remaining capacity of the given vector,
+    /// and return the amount of octets appended.
+    ///
+    /// This stops at the end of the datagram, and handles one that does not
+    /// fit like [recv_slice](#method.recv_slice).
+    pub fn recv_into_vec<const N: usize>(&mut self, out: &mut Vec<u8, N>) -> Result<usize> {
+        let policy = self.truncation_policy;
+        self.recv_impl(|rx_buffer, front| match front {
+            Some(chunk) => {
+                let start = out.len();
+                let end = min(out.capacity(), start + chunk.0.len);
+                // Cannot fail, as `end` is within the capacity.
+                let _ = out.resize(end, 0);
+                let (size, result) =
+                    Self::dequeue_chunk(rx_buffer, chunk, &mut out[start..], policy);
+                let copied = result.as_ref().map_or(0, |(copied, _)| *copied);
+                out.truncate(start + copied);
+                (size, result.map(|(copied, _)| copied))
+            }
+            None => (0, Ok(0)),
+        })?
+    }
+
+    /// Return the padding needed in front of a `len` byte datagram to keep
+    /// it contiguous, or `None` if either the buffer or the chunk slots
+    /// cannot hold it.
+    fn datagram_padding(&self, len: usize) -> Option<usize> {
+        // An empty buffer is rewound on enqueue, leaving all of it contiguous.
+        let contiguous = if self.rx_buffer.is_empty() {
+            self.rx_buffer.capacity()
+        } else {
+            self.rx_buffer.contiguous_window()
+        };
+        let padding = if len <= contiguous { 0 } else { contiguous };
+        let chunks = if padding > 0 { 2 } else { 1 };
+
+        if padding + len > self.rx_buffer.window()
+            || self.chunks.capacity() - self.chunks.len() < chunks
+        {
+            None
+        } else {
+            Some(padding)
+        }
+    }
+
+    /// Check whether a `len` byte datagram would be stored whole by
+    /// [rx_enqueue_datagram](#method.rx_enqueue_datagram).
+    ///
+    /// This accounts for the contiguous space and the metadata slot the
+    /// datagram needs, not just the free bytes in the buffer.
+    pub fn can_recv_bytes(&self, len: usize) -> bool {
+        self.is_open() && len <= self.mtu && self.datagram_padding(len).is_some()
+    }
+
+    /// Enqueue a datagram received from `remote`, and return the number of
+    /// bytes stored.
+    ///
+    /// The datagram is stored whole or not at all; one that does not fit,
+    /// exceeds [`MAX_DATAGRAMS`], or exceeds the [MTU](#method.set_mtu), is
+    /// dropped and counted; see [take_dropped_bytes](#method.take_dropped_bytes).
+    ///
+    /// A zero-length datagram is stored too, and read back once as an empty
+    /// datagram; [datagrams_queued](#method.datagrams_queued) tells it apart
+    /// from a dropped one.
+    pub fn rx_enqueue_datagram(&mut self, data: &[u8], remote: SocketAddr) -> usize {
+        if self.decrement_available_data {
+            self.available_data = self.available_data.saturating_sub(data.len());
+        }
+
+        if self.reject_oversized(data.len()).is_err() {
+            self.dropped_bytes = self.dropped_bytes.saturating_add(data.len());
+            return 0;
+        }
+
+        let padding = match self.datagram_padding(data.len()) {
+            Some(padding) => padding,
+            None => {
+                self.dropped_bytes = self.dropped_bytes.saturating_add(data.len());
+                #[cfg(feature = "stats")]
+                {
+                    self.stats.datagrams_dropped += 1;
+                }
+                return 0;
+            }
+        };
+
+        if padding > 0 {
+            self.rx_buffer.enqueue_many(padding);
+            // Room was checked above.
+            let _ = self.chunks.push_back(Chunk {
+                len: padding,
+                kind: ChunkKind::Padding,
+            });
+        }
+        let stored = self.rx_buffer.enqueue_slice(data);
+        debug_assert_eq!(stored, data.len());
+        let _ = self.chunks.push_back(Chunk {
+            len: stored,
+            kind: ChunkKind::Datagram(remote),
+        });
+        #[cfg(feature = "stats")]
+        {
+            self.stats.datagrams_enqueued += 1;
+            self.stats.rx_bytes += stored;
+        }
+        self.wake_rx();
+        stored
     }
 
+    /// Enqueue received data into the receive buffer, and return the number of
+    /// bytes that fit.
+    ///
+    /// This is the legacy path for stream-like use: no datagram boundaries
+    /// are recorded, and consecutive calls are read back as one run of bytes.
+    /// Prefer [rx_enqueue_datagram](#method.rx_enqueue_datagram).
+    ///
+    /// Bytes that did not fit are dropped, and counted; see
+    /// [take_dropped_bytes](#method.take_dropped_bytes).
     pub fn rx_enqueue_slice(&mut self, data: &[u8]) -> usize {
-        self.rx_buffer.enqueue_slice(data)
+        if self.decrement_available_data {
+            self.available_data = self.available_data.saturating_sub(data.len());
+        }
+
+        let stored = if let Some(Chunk {
+            len,
+            kind: ChunkKind::Stream,
+        }) = self.chunks.back_mut()
+        {
+            let stored = self.rx_buffer.enqueue_slice(data);
+            *len += stored;
+            stored
+        } else if !self.chunks.is_full() {
+            let stored = self.rx_buffer.enqueue_slice(data);
+            if stored > 0 {
+                let _ = self.chunks.push_back(Chunk {
+                    len: stored,
+                    kind: ChunkKind::Stream,
+                });
+            }
+            stored
+        } else {
+            0
+        };
+        self.dropped_bytes = self.dropped_bytes.saturating_add(data.len() - stored);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.rx_bytes += stored;
+        }
+        if stored > 0 {
+            self.wake_rx();
+        }
+        stored
+    }
+
+    /// Enqueue received data into the receive buffer, and return an error if
+    /// not all of it fit.
+    ///
+    /// See also [rx_enqueue_slice](#method.rx_enqueue_slice).
+    pub fn rx_enqueue_slice_checked(
+        &mut self,
+        data: &[u8],
+    ) -> core::result::Result<usize, RxOverflow> {
+        let stored = self.rx_enqueue_slice(data);
+        if stored < data.len() {
+            return Err(RxOverflow {
+                stored,
+                dropped: data.len() - stored,
+            });
+        }
+        Ok(stored)
+    }
+
+    /// Return the number of received bytes dropped because the receive buffer
+    /// was full, and reset the count.
+    pub fn take_dropped_bytes(&mut self) -> usize {
+        core::mem::take(&mut self.dropped_bytes)
+    }
+
+    /// Set the largest datagram accepted for sending or receiving. Defaults
+    /// to 512 bytes, the smallest limit of the supported modules.
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    /// Return the largest datagram accepted for sending or receiving.
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn reject_oversized(&mut self, len: usize) -> Result<()> {
+        if len > self.mtu {
+            #[cfg(feature = "stats")]
+            {
+                self.stats.mtu_rejections += 1;
+            }
+            return Err(Error::BadLength);
+        }
+        Ok(())
+    }
+
+    /// Enqueue a datagram to be sent to the connected endpoint, and return its
+    /// length.
+    ///
+    /// This function returns `Err(Error::Illegal)` if the socket is not
+    /// connected; otherwise it behaves like [send_to](#method.send_to).
+    pub fn send_slice(&mut self, data: &[u8]) -> Result<usize> {
+        let endpoint = self.endpoint.ok_or(Error::Illegal)?;
+        self.send_to(data, endpoint)?;
+        Ok(data.len())
+    }
+
+    /// Enqueue a datagram to be sent to `remote`.
+    ///
+    /// Datagrams are never split: this function returns
+    /// `Err(Error::BadLength)` if the datagram exceeds the MTU or the transmit
+    /// buffer, and `Err(Error::Exhausted)` if it does not fit in what is left
+    /// of the buffer, or [`MAX_DATAGRAMS`] are already queued.
+    pub fn send_to(&mut self, data: &[u8], remote: SocketAddr) -> Result<()> {
+        self.tx_enqueue_datagram(data, Destination::Ip(remote))
+    }
+
+    /// Enqueue a datagram to be sent to `hostname`, which the module resolves.
+    ///
+    /// This function returns `Err(Error::BadLength)` if the hostname is longer
+    /// than [`MAX_HOSTNAME_LEN`]; otherwise it behaves like
+    /// [send_to](#method.send_to).
+    pub fn send_to_host(&mut self, data: &[u8], hostname: &str, port: u16) -> Result<()> {
+        let mut host = String::new();
+        host.push_str(hostname).map_err(|_| Error::BadLength)?;
+        self.tx_enqueue_datagram(data, Destination::Host(host, port))
+    }
+
+    fn tx_enqueue_datagram(&mut self, data: &[u8], destination: Destination) -> Result<()> {
+        self.reject_oversized(data.len())?;
+        if data.len() > TXL {
+            return Err(Error::BadLength);
+        }
+        if data.len() > self.tx_buffer.window() || self.tx_datagrams.is_full() {
+            return Err(Error::Exhausted);
+        }
+
+        let stored = self.tx_buffer.enqueue_slice(data);
+        debug_assert_eq!(stored, data.len());
+        // Room was checked above.
+        let _ = self.tx_datagrams.push_back((stored, destination));
+        Ok(())
+    }
+
+    /// Return the amount of octets queued in the transmit buffer.
+    pub fn send_queue(&self) -> usize {
+        self.tx_buffer.len()
+    }
+
+    /// Dequeue the next datagram to be sent into `buf`, and return its length
+    /// and destination.
+    ///
+    /// This is meant for the driver, which sends one datagram per `+USOST`.
+    /// It returns `None` if no datagram is queued, or if the next one does not
+    /// fit in `buf`, in which case it stays queued; a buffer of the MTU always
+    /// fits.
+    pub fn tx_dequeue_datagram(&mut self, buf: &mut [u8]) -> Option<(usize, UdpDestination<'_>)> {
+        let len = self.tx_datagrams.front()?.0;
+        if len > buf.len() {
+            return None;
+        }
+
+        let (_, destination) = self.tx_datagrams.pop_front()?;
+        let destination = match destination {
+            Destination::Ip(remote) => UdpDestination::Ip(remote),
+            Destination::Host(host, port) => {
+                self.tx_host = host;
+                UdpDestination::Host(self.tx_host.as_str(), port)
+            }
+        };
+        let copied = self.tx_buffer.dequeue_slice(&mut buf[..len]);
+        debug_assert_eq!(copied, len);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.tx_bytes += len;
+        }
+        Some((len, destination))
+    }
+
+    /// Set whether the socket may send broadcast datagrams. Like the other
+    /// socket options, this is only recorded here, for the driver to apply
+    /// to the module.
+    pub fn set_broadcast(&mut self, enabled: bool) {
+        self.broadcast = enabled;
+    }
+
+    /// Return whether the socket may send broadcast datagrams.
+    pub fn broadcast(&self) -> bool {
+        self.broadcast
+    }
+
+    /// Record that the socket joined the given multicast group. Joining a
+    /// group twice has no effect.
+    ///
+    /// This function returns `Err(Error::Unaddressable)` if the address is
+    /// not a multicast address, and `Err(Error::Exhausted)` if
+    /// [`MAX_MULTICAST_GROUPS`] are already joined.
+    pub fn join_multicast(&mut self, group: Ipv4Addr) -> Result<()> {
+        if !group.is_multicast() {
+            return Err(Error::Unaddressable);
+        }
+        if self.multicast_groups.contains(&group) {
+            return Ok(());
+        }
+
+        self.multicast_groups
+            .push(group)
+            .map_err(|_| Error::Exhausted)
+    }
+
+    /// Record that the socket left the given multicast group.
+    ///
+    /// This function returns `Err(Error::Illegal)` if the group was not
+    /// joined.
+    pub fn leave_multicast(&mut self, group: Ipv4Addr) -> Result<()> {
+        let index = self
+            .multicast_groups
+            .iter()
+            .position(|joined| *joined == group)
+            .ok_or(Error::Illegal)?;
+        self.multicast_groups.swap_remove(index);
+        Ok(())
+    }
+
+    /// Return the multicast groups the socket joined.
+    pub fn multicast_groups(&self) -> &[Ipv4Addr] {
+        &self.multicast_groups
     }
 
     /// Peek at a packet received from a remote endpoint, and return the endpoint as well
@@ -213,6 +1159,8 @@ impl<const L: usize> UdpSocket<L> {
             return Err(Error::Illegal);
         }
 
+        self.skip_padding();
+        let size = min(size, self.chunks.front().map_or(0, |chunk| chunk.len));
         Ok(self.rx_buffer.get_allocated(0, size))
     }
 
@@ -229,20 +1177,1106 @@ impl<const L: usize> UdpSocket<L> {
         Ok(length)
     }
 
-    pub fn close(&mut self) {
+    /// Peek at the next datagram received from a remote endpoint, copy the
+    /// payload into the given slice, and return the length of the datagram as
+    /// well as the endpoint, without removing the datagram from the receive
+    /// buffer.
+    ///
+    /// This otherwise behaves like [recv_from](#method.recv_from), except that
+    /// a datagram that does not fit is never an error; only what fits is
+    /// copied.
+    pub fn peek_from(&mut self, data: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        if !self.is_open() {
+            return Err(Error::Illegal);
+        }
+
+        let (chunk, remote) = self.front_chunk().ok_or(Error::Exhausted)?;
+        let len = min(data.len(), chunk.len);
+        self.rx_buffer.read_allocated(0, &mut data[..len]);
+        Ok((chunk.len, remote))
+    }
+
+    /// Peek at the next datagram received from a remote endpoint, and return
+    /// the result of calling `f` with its payload and the endpoint, without
+    /// removing the datagram from the receive buffer.
+    ///
+    /// Data enqueued with [rx_enqueue_slice](#method.rx_enqueue_slice) may be
+    /// only partly passed to `f`, if it wraps around the end of the buffer.
+    /// This returns `Err(Error::Exhausted)` if the receive buffer is empty.
+    pub fn peek_from_with<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&[u8], SocketAddr) -> R,
+    {
+        if !self.is_open() {
+            return Err(Error::Illegal);
+        }
+
+        let (chunk, remote) = self.front_chunk().ok_or(Error::Exhausted)?;
+        Ok(f(self.rx_buffer.get_allocated(0, chunk.len), remote))
+    }
+
+    /// Close the socket at `ts`, forgetting both the local port and the
+    /// remote endpoint.
+    ///
+    /// Queued data in both directions is dropped, and the socket is recycled
+    /// once the read timeout has passed, unless it is opened again first.
+    pub fn close(&mut self, ts: Instant) {
         self.endpoint.take();
+        self.local_port.take();
+        self.set_state(State::Closed);
+
+        self.rx_buffer.clear();
+        self.chunks.clear();
+        self.tx_buffer.clear();
+        self.tx_datagrams.clear();
+        self.tx_host.clear();
+        self.available_data = 0;
+        self.last_check_time = None;
+        self.deferred_check = false;
+        self.closed_time.get_or_insert(ts);
+        self.close_reason.get_or_insert(CloseReason::LocalClose);
     }
 }
 
 #[cfg(feature = "defmt")]
-impl<const L: usize> defmt::Format for UdpSocket<L> {
+impl<const L: usize, const TXL: usize> defmt::Format for UdpSocket<L, TXL> {
     fn format(&self, fmt: defmt::Formatter) {
-        defmt::write!(fmt, "[{:?}, {:?}],", self.handle(), self.state())
+        defmt::write!(
+            fmt,
+            "[{:?}, {:?}, local_port: {:?}, endpoint: {:?}, recv_queue: {}, datagrams: {}, available_data: {}],",
+            self.handle(),
+            self.state(),
+            self.local_port,
+            self.endpoint.as_ref().map(defmt::Debug2Format),
+            self.recv_queue(),
+            self.datagrams_queued(),
+            self.available_data
+        )
     }
 }
 
-impl<const L: usize> Into<Socket<L>> for UdpSocket<L> {
-    fn into(self) -> Socket<L> {
-        Socket::Udp(self)
+impl<const L: usize, const TXL: usize> core::fmt::Debug for UdpSocket<L, TXL> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("UdpSocket")
+            .field("handle", &self.handle())
+            .field("state", &self.state)
+            .field("local_port", &self.local_port)
+            .field("endpoint", &self.endpoint)
+            .field("available_data", &self.available_data)
+            .field("recv_queue", &self.recv_queue())
+            .field("datagrams_queued", &self.datagrams_queued())
+            .field("send_queue", &self.send_queue())
+            .field("read_timeout", &self.read_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "socket-udp")]
+impl<const L: usize, const TXL: usize> From<UdpSocket<L, TXL>> for crate::Socket<L, TXL> {
+    fn from(socket: UdpSocket<L, TXL>) -> Self {
+        crate::Socket::Udp(socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "socket-udp")]
+    use crate::Socket;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    fn remote() -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080))
+    }
+
+    fn other() -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 53))
+    }
+
+    #[test]
+    fn datagram_boundaries() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.rx_enqueue_datagram(b"abc", remote()), 3);
+        assert_eq!(socket.rx_enqueue_datagram(b"", other()), 0);
+        assert_eq!(socket.rx_enqueue_datagram(b"de", other()), 2);
+
+        let mut data = [0; 16];
+        assert_eq!(
+            socket.recv_datagram(&mut data),
+            Ok((
+                3,
+                UdpMetadata {
+                    remote: remote(),
+                    len: 3,
+                    truncated: false
+                }
+            ))
+        );
+        assert_eq!(&data[..3], b"abc");
+        assert_eq!(
+            socket.recv_datagram(&mut data),
+            Ok((
+                0,
+                UdpMetadata {
+                    remote: other(),
+                    len: 0,
+                    truncated: false
+                }
+            ))
+        );
+        assert_eq!(socket.recv_slice(&mut data), Ok(2));
+        assert_eq!(&data[..2], b"de");
+        assert_eq!(socket.recv_datagram(&mut data), Err(Error::Exhausted));
+        assert_eq!(socket.recv_slice(&mut data), Ok(0));
+    }
+
+    #[test]
+    fn datagram_truncated() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.set_truncation_policy(TruncationPolicy::TruncateAndDiscardTail);
+        socket.rx_enqueue_datagram(b"abcdef", remote());
+        socket.rx_enqueue_datagram(b"gh", remote());
+
+        let mut data = [0; 4];
+        assert_eq!(
+            socket.recv_datagram(&mut data),
+            Ok((
+                4,
+                UdpMetadata {
+                    remote: remote(),
+                    len: 6,
+                    truncated: true
+                }
+            ))
+        );
+        assert_eq!(&data, b"abcd");
+        assert_eq!(socket.recv_slice(&mut data), Ok(2));
+        assert_eq!(&data[..2], b"gh");
+        assert!(socket.rx_buffer.is_empty());
+    }
+
+    #[test]
+    fn datagram_too_small() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        assert_eq!(
+            socket.truncation_policy(),
+            TruncationPolicy::ErrorIfTooSmall
+        );
+        socket.rx_enqueue_datagram(b"", other());
+        socket.rx_enqueue_datagram(b"abcdef", remote());
+
+        // A zero-length datagram fits anywhere.
+        assert_eq!(socket.recv_from(&mut []), Ok((0, other())));
+
+        let mut data = [0; 4];
+        assert_eq!(socket.recv_slice(&mut data), Err(Error::BadLength));
+        assert_eq!(socket.recv_datagram(&mut data), Err(Error::BadLength));
+        let mut out: Vec<u8, 4> = Vec::new();
+        assert_eq!(socket.recv_into_vec(&mut out), Err(Error::BadLength));
+        assert!(out.is_empty());
+
+        let mut data = [0; 6];
+        assert_eq!(socket.recv_slice(&mut data), Ok(6));
+        assert_eq!(&data, b"abcdef");
+    }
+
+    #[test]
+    fn datagram_truncated_length() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.set_truncation_policy(TruncationPolicy::TruncateAndDiscardTail);
+        socket.rx_enqueue_datagram(b"", other());
+        socket.rx_enqueue_datagram(b"abcdef", remote());
+        socket.rx_enqueue_datagram(b"gh", other());
+
+        assert_eq!(socket.recv_slice(&mut []), Ok(0));
+        let mut data = [0; 4];
+        assert_eq!(socket.recv_slice(&mut data), Ok(6));
+        assert_eq!(&data, b"abcd");
+        assert_eq!(socket.recv_from(&mut data[..1]), Ok((2, other())));
+        assert_eq!(&data[..1], b"g");
+        assert!(socket.rx_buffer.is_empty());
+    }
+
+    #[test]
+    fn datagram_wraps_around() {
+        let mut socket = UdpSocket::<8>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.rx_enqueue_datagram(b"abc", remote());
+        socket.rx_enqueue_datagram(b"defg", remote());
+        let mut data = [0; 8];
+        assert_eq!(socket.recv_slice(&mut data), Ok(3));
+
+        // Only one byte is left before the end of the buffer, so the datagram
+        // is stored from the start instead.
+        assert_eq!(socket.rx_enqueue_datagram(b"hij", other()), 3);
+        assert_eq!(socket.rx_window(), 0);
+        assert_eq!(socket.recv_slice(&mut data), Ok(4));
+        assert_eq!(&data[..4], b"defg");
+        assert_eq!(socket.peek(8), Ok(&b"hij"[..]));
+        assert_eq!(
+            socket.recv(|payload| {
+                assert_eq!(payload, b"hij");
+                (0, ())
+            }),
+            Ok(())
+        );
+        assert!(socket.rx_buffer.is_empty());
+        assert!(socket.chunks.is_empty());
+
+        // An empty buffer is rewound, so a full-size datagram fits.
+        assert_eq!(socket.rx_enqueue_datagram(b"klmnopqr", remote()), 8);
+        assert_eq!(socket.recv_slice(&mut data), Ok(8));
+        assert_eq!(&data, b"klmnopqr");
+    }
+
+    #[test]
+    fn datagram_dropped_whole() {
+        let mut socket = UdpSocket::<8>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.rx_enqueue_datagram(b"abc", remote());
+        socket.rx_enqueue_datagram(b"defg", remote());
+        let mut data = [0; 8];
+        assert_eq!(socket.recv_slice(&mut data), Ok(3));
+
+        // Four bytes are free, but not contiguously.
+        assert_eq!(socket.rx_window(), 4);
+        assert_eq!(socket.rx_enqueue_datagram(b"hijk", remote()), 0);
+        assert_eq!(socket.take_dropped_bytes(), 4);
+        assert_eq!(socket.rx_enqueue_datagram(b"abcdefghi", remote()), 0);
+        assert_eq!(socket.take_dropped_bytes(), 9);
+
+        assert_eq!(socket.recv_slice(&mut data), Ok(4));
+        assert_eq!(&data[..4], b"defg");
+        assert_eq!(socket.recv_slice(&mut data), Ok(0));
+    }
+
+    #[test]
+    fn datagram_limit() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        for i in 0..MAX_DATAGRAMS as u8 {
+            assert_eq!(socket.rx_enqueue_datagram(&[i], remote()), 1);
+        }
+        assert_eq!(socket.rx_enqueue_datagram(b"x", remote()), 0);
+        assert_eq!(socket.rx_enqueue_slice(b"x"), 0);
+        assert_eq!(socket.take_dropped_bytes(), 2);
+
+        let mut data = [0; 4];
+        for i in 0..MAX_DATAGRAMS as u8 {
+            assert_eq!(socket.recv_slice(&mut data), Ok(1));
+            assert_eq!(data[0], i);
+        }
+    }
+
+    #[test]
+    fn datagrams_and_stream() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.rx_enqueue_slice(b"ab");
+        socket.rx_enqueue_slice(b"cd");
+        socket.rx_enqueue_datagram(b"ef", other());
+        socket.rx_enqueue_slice(b"gh");
+
+        let mut data = [0; 3];
+        assert_eq!(
+            socket.recv_datagram(&mut data),
+            Ok((
+                3,
+                UdpMetadata {
+                    remote: remote(),
+                    len: 3,
+                    truncated: false
+                }
+            ))
+        );
+        assert_eq!(&data, b"abc");
+        assert_eq!(socket.recv_slice(&mut data), Ok(1));
+        assert_eq!(&data[..1], b"d");
+        assert_eq!(socket.recv_from(&mut data), Ok((2, other())));
+        assert_eq!(socket.recv_slice(&mut data), Ok(2));
+        assert_eq!(&data[..2], b"gh");
+    }
+
+    #[test]
+    fn recv_from() {
+        let mut socket = UdpSocket::<64>::new(0);
+        let mut data = [0; 8];
+        assert_eq!(socket.recv_from(&mut data), Err(Error::Illegal));
+
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.recv_from(&mut data), Err(Error::Exhausted));
+        assert_eq!(socket.recv_from_with(|_, _| (0, ())), Err(Error::Exhausted));
+
+        socket.rx_enqueue_datagram(b"abc", other());
+        socket.rx_enqueue_slice(b"def");
+        socket.rx_enqueue_datagram(b"ghi", other());
+        assert_eq!(socket.recv_from(&mut data), Ok((3, other())));
+        assert_eq!(&data[..3], b"abc");
+
+        // Data without boundaries comes from the bound endpoint.
+        assert_eq!(
+            socket.recv_from_with(|payload, remote| (1, (payload.len(), remote))),
+            Ok((3, remote()))
+        );
+        assert_eq!(socket.recv_from(&mut data), Ok((2, remote())));
+        assert_eq!(&data[..2], b"ef");
+
+        // A datagram is dequeued whole, whatever the closure consumed.
+        assert_eq!(
+            socket.recv_from_with(|payload, remote| (0, (payload.to_vec(), remote))),
+            Ok((b"ghi".to_vec(), other()))
+        );
+        assert!(socket.rx_buffer.is_empty());
+    }
+
+    #[test]
+    fn send_to() {
+        let mut socket = UdpSocket::<64, 8>::new(0);
+        let mut buf = [0; 8];
+        assert_eq!(socket.tx_dequeue_datagram(&mut buf), None);
+        assert_eq!(socket.send_slice(b"abc"), Err(Error::Illegal));
+
+        assert_eq!(socket.send_to(b"abc", other()), Ok(()));
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.send_slice(b"defg"), Ok(4));
+        assert_eq!(socket.send_queue(), 7);
+        assert_eq!(socket.send_to(b"hi", other()), Err(Error::Exhausted));
+        assert_eq!(socket.send_to(b"abcdefghi", other()), Err(Error::BadLength));
+
+        assert_eq!(
+            socket.tx_dequeue_datagram(&mut buf),
+            Some((3, UdpDestination::Ip(other())))
+        );
+        assert_eq!(&buf[..3], b"abc");
+
+        // Wraps around the end of the transmit buffer.
+        assert_eq!(socket.send_to(b"hi", other()), Ok(()));
+        let mut small = [0; 3];
+        assert_eq!(socket.tx_dequeue_datagram(&mut small), None);
+        assert_eq!(
+            socket.tx_dequeue_datagram(&mut buf),
+            Some((4, UdpDestination::Ip(remote())))
+        );
+        assert_eq!(&buf[..4], b"defg");
+        assert_eq!(
+            socket.tx_dequeue_datagram(&mut small),
+            Some((2, UdpDestination::Ip(other())))
+        );
+        assert_eq!(&small[..2], b"hi");
+        assert_eq!(socket.send_queue(), 0);
+    }
+
+    #[test]
+    fn can_send_until_tx_buffer_full() {
+        let mut socket = UdpSocket::<64, 4>::new(0);
+        assert!(!socket.can_send());
+
+        socket.connect(remote()).unwrap();
+        assert!(socket.can_send());
+        assert_eq!(socket.send_slice(b"abcd"), Ok(4));
+        assert!(socket.may_send());
+        assert!(!socket.can_send());
+
+        let mut buf = [0; 4];
+        socket.tx_dequeue_datagram(&mut buf).unwrap();
+        for _ in 0..MAX_DATAGRAMS {
+            socket.send_slice(b"").unwrap();
+        }
+        assert!(!socket.can_send());
+    }
+
+    #[test]
+    fn send_to_mtu() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.set_mtu(4);
+        assert_eq!(socket.send_to(b"abcde", remote()), Err(Error::BadLength));
+        assert_eq!(socket.send_to(b"abcd", remote()), Ok(()));
+        for _ in 1..MAX_DATAGRAMS {
+            assert_eq!(socket.send_to(b"a", remote()), Ok(()));
+        }
+        assert_eq!(socket.send_to(b"a", remote()), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn zero_length_datagram() {
+        let mut socket = UdpSocket::<8>::new(0);
+        socket.connect(remote()).unwrap();
+        let mut data = [0; 8];
+
+        assert_eq!(socket.rx_enqueue_datagram(b"", other()), 0);
+        assert_eq!(socket.recv_queue(), 0);
+        assert_eq!(socket.datagrams_queued(), 1);
+        assert_eq!(socket.take_dropped_bytes(), 0);
+
+        assert_eq!(socket.peek_from(&mut data), Ok((0, other())));
+        assert_eq!(socket.recv_from(&mut data), Ok((0, other())));
+        assert_eq!(socket.datagrams_queued(), 0);
+        assert_eq!(socket.recv_from(&mut data), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn zero_length_datagram_between_others() {
+        let mut socket = UdpSocket::<8>::new(0);
+        socket.connect(remote()).unwrap();
+        let mut data = [0; 8];
+
+        assert_eq!(socket.rx_enqueue_datagram(b"abcdefgh", other()), 8);
+        // Fits in a full buffer, as it only takes a metadata slot.
+        assert_eq!(socket.rx_enqueue_datagram(b"", remote()), 0);
+        assert_eq!(socket.datagrams_queued(), 2);
+        assert_eq!(socket.recv_queue(), 8);
+
+        assert_eq!(socket.recv_from(&mut data), Ok((8, other())));
+        assert_eq!(
+            socket.recv_datagram(&mut data).map(|(n, m)| (n, m.remote)),
+            Ok((0, remote()))
+        );
+        assert_eq!(socket.recv_datagram(&mut data), Err(Error::Exhausted));
+        assert_eq!(socket.datagrams_queued(), 0);
+    }
+
+    #[test]
+    fn zero_length_datagram_wakes() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut socket = UdpSocket::<8>::new(0);
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.poll_recv_ready(&mut cx), Poll::Pending);
+        socket.rx_enqueue_datagram(b"", other());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(socket.poll_recv_ready(&mut cx), Poll::Ready(Ok(())));
+    }
+
+    #[test]
+    fn debug_output() {
+        let mut socket = UdpSocket::<8>::new(2);
+        socket.connect(remote()).unwrap();
+        socket.rx_enqueue_datagram(b"abc", other());
+
+        let debug = format!("{:?}", socket);
+        assert!(debug.starts_with(
+            "UdpSocket { handle: Handle { id: 2, generation: 0 }, state: Established,"
+        ));
+        assert!(debug.contains("local_port: None, endpoint: Some("));
+        assert!(debug.contains("recv_queue: 3, datagrams_queued: 1"));
+        assert!(debug.ends_with(".. }"));
+    }
+
+    #[test]
+    fn send_to_host() {
+        let mut socket = UdpSocket::<64>::new(0);
+        let mut buf = [0; 8];
+        assert_eq!(socket.send_to_host(b"abc", "example.com", 53), Ok(()));
+        assert_eq!(socket.send_to(b"de", other()), Ok(()));
+
+        let too_long = [b'a'; MAX_HOSTNAME_LEN + 1];
+        assert_eq!(
+            socket.send_to_host(b"f", core::str::from_utf8(&too_long).unwrap(), 53),
+            Err(Error::BadLength)
+        );
+        assert_eq!(socket.send_queue(), 5);
+
+        assert_eq!(
+            socket.tx_dequeue_datagram(&mut buf),
+            Some((3, UdpDestination::Host("example.com", 53)))
+        );
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(
+            socket.tx_dequeue_datagram(&mut buf),
+            Some((2, UdpDestination::Ip(other())))
+        );
+    }
+
+    #[test]
+    fn mtu_boundaries() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert_eq!(socket.mtu(), 512);
+        socket.set_mtu(8);
+        assert_eq!(socket.mtu(), 8);
+        socket.connect(remote()).unwrap();
+
+        for (len, accepted) in [(0, true), (8, true), (9, false)] {
+            let data = [0xab; 9];
+            let data = &data[..len];
+
+            let sent = socket.send_to(data, remote());
+            assert_eq!(sent.is_ok(), accepted, "send {}", len);
+            if !accepted {
+                assert_eq!(sent, Err(Error::BadLength));
+            }
+            assert_eq!(socket.tx_dequeue_datagram(&mut [0; 64]).is_some(), accepted);
+
+            assert_eq!(socket.can_recv_bytes(len), accepted, "can_recv {}", len);
+            let stored = socket.rx_enqueue_datagram(data, other());
+            assert_eq!(stored, if accepted { len } else { 0 });
+            let mut buf = [0; 64];
+            assert_eq!(
+                socket
+                    .recv_datagram(&mut buf)
+                    .map(|(n, meta)| (n, meta.len)),
+                if accepted {
+                    Ok((len, len))
+                } else {
+                    Err(Error::Exhausted)
+                },
+                "recv {}",
+                len
+            );
+        }
+        assert_eq!(socket.take_dropped_bytes(), 9);
+
+        #[cfg(feature = "stats")]
+        assert_eq!(socket.stats().mtu_rejections, 2);
+    }
+
+    #[test]
+    fn peek_from() {
+        let mut socket = UdpSocket::<8>::new(0);
+        let mut data = [0; 8];
+        assert_eq!(socket.peek_from(&mut data), Err(Error::Illegal));
+
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.peek_from(&mut data), Err(Error::Exhausted));
+        assert_eq!(socket.peek_from_with(|_, _| ()), Err(Error::Exhausted));
+
+        socket.rx_enqueue_datagram(b"abc", other());
+        socket.rx_enqueue_datagram(b"defg", remote());
+        socket.recv_slice(&mut data).unwrap();
+        // Stored after padding, at the start of the buffer.
+        socket.rx_enqueue_datagram(b"hij", other());
+        socket.recv_slice(&mut data).unwrap();
+
+        let mut small = [0; 2];
+        assert_eq!(socket.peek_from(&mut small), Ok((3, other())));
+        assert_eq!(&small, b"hi");
+        assert_eq!(
+            socket.peek_from_with(|payload, remote| (payload.len(), remote)),
+            Ok((3, other()))
+        );
+        assert_eq!(socket.peek_from(&mut data), Ok((3, other())));
+        assert_eq!(&data[..3], b"hij");
+
+        // The datagram is still whole.
+        assert_eq!(socket.recv_from(&mut data), Ok((3, other())));
+        assert_eq!(&data[..3], b"hij");
+        assert_eq!(socket.peek_from(&mut data), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn recv_into_vec_stops_at_datagram() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.set_truncation_policy(TruncationPolicy::TruncateAndDiscardTail);
+        socket.rx_enqueue_datagram(b"abc", remote());
+        socket.rx_enqueue_datagram(b"defgh", remote());
+
+        let mut out: Vec<u8, 6> = Vec::new();
+        assert_eq!(socket.recv_into_vec(&mut out), Ok(3));
+        assert_eq!(&out[..], b"abc");
+        assert_eq!(socket.recv_into_vec(&mut out), Ok(3));
+        assert_eq!(&out[..], b"abcdef");
+        assert_eq!(socket.recv_into_vec(&mut out), Ok(0));
+    }
+
+    #[test]
+    fn no_polling_while_rx_full() {
+        let mut socket = UdpSocket::<4>::new(0);
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.rx_enqueue_slice(b"abcd"), 4);
+
+        assert!(!socket.should_update_available_data(Instant::from_secs(15)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(30)));
+
+        let mut data = [0; 1];
+        assert_eq!(socket.recv_slice(&mut data), Ok(1));
+        assert!(socket.should_update_available_data(Instant::from_secs(31)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(32)));
+    }
+
+    #[test]
+    fn first_check_is_immediate() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        assert!(socket.should_update_available_data(Instant::from_secs(100)));
+        assert_eq!(socket.last_check_time(), Some(Instant::from_secs(100)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(101)));
+    }
+
+    #[test]
+    fn frequent_checks_do_not_reset_interval() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        assert!(socket.should_update_available_data(Instant::from_secs(0)));
+
+        for secs in 1..15 {
+            assert!(!socket.should_update_available_data(Instant::from_secs(secs)));
+        }
+        assert_eq!(socket.last_check_time(), Some(Instant::from_secs(0)));
+        assert!(socket.should_update_available_data(Instant::from_secs(15)));
+        assert!(!socket.should_update_available_data(Instant::from_secs(16)));
+    }
+
+    #[test]
+    fn recv_into_vec() {
+        let mut socket = UdpSocket::<8>::new(0);
+        let mut out: Vec<u8, 4> = Vec::new();
+        assert_eq!(socket.recv_into_vec(&mut out), Err(Error::Illegal));
+
+        socket.connect(remote()).unwrap();
+        socket.rx_enqueue_slice(b"abcdef");
+        assert_eq!(socket.recv_into_vec(&mut out), Ok(4));
+        assert_eq!(&out[..], b"abcd");
+        assert_eq!(socket.rx_buffer.len(), 2);
+    }
+
+    #[test]
+    fn decrement_available_data() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.set_available_data(10);
+        socket.rx_enqueue_slice(b"abc");
+        assert_eq!(socket.get_available_data(), 10);
+
+        socket.set_decrement_available_data(true);
+        socket.rx_enqueue_slice(b"abc");
+        assert_eq!(socket.get_available_data(), 7);
+        socket.rx_enqueue_slice(b"abcdefgh");
+        assert_eq!(socket.get_available_data(), 0);
+    }
+
+    #[test]
+    fn time_until_next_check() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert_eq!(socket.time_until_next_check(Instant::from_secs(0)), None);
+
+        socket.connect(remote()).unwrap();
+        assert_eq!(
+            socket.time_until_next_check(Instant::from_secs(0)),
+            Some(Duration::from_secs(0))
+        );
+
+        socket.last_check_time = Some(Instant::from_secs(10));
+        assert_eq!(
+            socket.time_until_next_check(Instant::from_secs(20)),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            socket.time_until_next_check(Instant::from_secs(30)),
+            Some(Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn with_config() {
+        let config = UdpSocketConfig {
+            read_timeout: None,
+            ..Default::default()
+        };
+        let socket = UdpSocket::<64>::with_config(2, config);
+        assert_eq!(socket.handle(), SocketHandle::new(2));
+        assert_eq!(socket.check_interval, Duration::from_secs(15));
+        assert_eq!(socket.read_timeout, None);
+    }
+
+    #[test]
+    fn bind_and_connect() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert!(!socket.is_open());
+        assert_eq!(socket.bind(0), Err(Error::Unaddressable));
+
+        assert_eq!(socket.bind(5000), Ok(()));
+        assert!(socket.is_open());
+        assert_eq!(socket.state(), State::Closed);
+        assert_eq!(socket.local_port(), Some(5000));
+        assert_eq!(socket.endpoint(), None);
+        assert_eq!(socket.bind(5001), Err(Error::Illegal));
+
+        assert_eq!(
+            socket.connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+            Err(Error::Unaddressable)
+        );
+        assert_eq!(socket.connect(remote()), Ok(()));
+        assert_eq!(socket.state(), State::Established);
+        assert_eq!(socket.endpoint(), Some(remote()));
+        assert_eq!(socket.connect(other()), Err(Error::Illegal));
+    }
+
+    #[test]
+    fn rebind_after_close() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.bind(5000).unwrap();
+        socket.connect(remote()).unwrap();
+
+        socket.rx_enqueue_datagram(b"stale", remote());
+        socket.rx_enqueue_slice(b"stale");
+        socket.send_slice(b"stale").unwrap();
+        socket.set_available_data(10);
+        socket.should_update_available_data(Instant::from_secs(0));
+
+        socket.close(Instant::from_secs(5));
+        assert!(!socket.is_open());
+        assert_eq!(socket.state(), State::Closed);
+        assert_eq!(socket.local_port(), None);
+        assert_eq!(socket.endpoint(), None);
+        assert_eq!(socket.get_available_data(), 0);
+        assert_eq!(socket.last_check_time(), None);
+        assert_eq!(socket.send_queue(), 0);
+        assert!(!socket.recycle(Instant::from_secs(19)));
+        assert!(socket.recycle(Instant::from_secs(20)));
+
+        assert_eq!(socket.bind(5001), Ok(()));
+        assert_eq!(socket.local_port(), Some(5001));
+        assert_eq!(socket.connect(other()), Ok(()));
+        assert_eq!(socket.endpoint(), Some(other()));
+        assert_eq!(socket.connection_status(), ConnectionStatus::Connected);
+        assert!(!socket.recycle(Instant::from_secs(20)));
+
+        let mut data = [0; 8];
+        assert_eq!(socket.recv_queue(), 0);
+        assert_eq!(socket.recv_from(&mut data), Err(Error::Exhausted));
+        assert_eq!(socket.tx_dequeue_datagram(&mut data), None);
+    }
+
+    #[test]
+    fn recv_bound_only() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.bind(5000).unwrap();
+        socket.rx_enqueue_datagram(b"abc", other());
+        socket.rx_enqueue_slice(b"def");
+
+        let mut data = [0; 8];
+        assert_eq!(socket.recv_from(&mut data), Ok((3, other())));
+        assert_eq!(
+            socket.recv_from(&mut data),
+            Ok((
+                3,
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0))
+            ))
+        );
+        assert_eq!(socket.send_slice(b"abc"), Err(Error::Illegal));
+    }
+
+    #[test]
+    fn timeout_setters() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert_eq!(socket.check_interval(), Duration::from_secs(15));
+        assert_eq!(socket.read_timeout(), Some(Duration::from_secs(15)));
+
+        socket.set_check_interval(Duration::from_secs(2));
+        socket.set_read_timeout(None);
+        assert_eq!(socket.check_interval(), Duration::from_secs(2));
+        assert_eq!(socket.read_timeout(), None);
+
+        socket.connect(remote()).unwrap();
+        socket.last_check_time = Some(Instant::from_secs(10));
+        assert!(!socket.should_update_available_data(Instant::from_secs(11)));
+        assert!(socket.should_update_available_data(Instant::from_secs(13)));
+    }
+
+    #[test]
+    fn no_recycle_without_read_timeout() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert!(!socket.recycle(Instant::from_secs(14)));
+        assert!(socket.recycle(Instant::from_secs(15)));
+
+        socket.set_read_timeout(None);
+        assert!(!socket.recycle(Instant::from_secs(15)));
+        assert!(!socket.recycle(Instant::from_secs(3600)));
+    }
+
+    #[test]
+    fn recycle_after_local_close() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert!(!socket.recycle(Instant::from_secs(100)));
+        socket.connect(remote()).unwrap();
+        assert!(!socket.recycle(Instant::from_secs(100)));
+
+        socket.close(Instant::from_secs(100));
+        assert!(!socket.recycle(Instant::from_secs(114)));
+        assert!(socket.recycle(Instant::from_secs(115)));
+
+        socket.set_read_timeout(None);
+        assert!(socket.recycle(Instant::from_secs(100)));
+    }
+
+    #[test]
+    fn long_running_past_u32_ticks() {
+        // Where a 32-bit tick count at 1 MHz would wrap, and far beyond
+        for start in [(1u64 << 32) - 20_000_000, 100 * 365 * 24 * 3600 * 1_000_000] {
+            let mut socket = UdpSocket::<64>::new(0);
+            socket.connect(remote()).unwrap();
+            let mut checks = 0;
+            for step in 0..(2 * 24 * 3600) {
+                if socket
+                    .should_update_available_data(Instant::from_micros(start + step * 1_000_000))
+                {
+                    checks += 1;
+                }
+            }
+            assert_eq!(checks, 2 * 24 * 3600 / 15);
+
+            let closed = start + 10_000_000;
+            socket.close(Instant::from_micros(closed));
+            assert!(!socket.recycle(Instant::from_micros(closed + 14_000_000)));
+            assert!(socket.recycle(Instant::from_micros(closed + 15_000_000)));
+        }
+    }
+
+    #[test]
+    fn recycle_after_remote_close_without_read_timeout() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.set_read_timeout(None);
+        socket.connect(remote()).unwrap();
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert!(!socket.recycle(Instant::from_secs(3600)));
+
+        // Closing it locally as well leaves nothing to read.
+        socket.close(Instant::from_secs(3600));
+        assert!(socket.recycle(Instant::from_secs(3600)));
+    }
+
+    #[test]
+    fn closed_by_remote() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        assert!(!socket.recycle(Instant::from_secs(100)));
+
+        socket.closed_by_remote(Instant::from_secs(100));
+        assert!(!socket.recycle(Instant::from_secs(114)));
+        assert!(socket.recycle(Instant::from_secs(115)));
+    }
+
+    #[test]
+    fn broadcast() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert!(!socket.broadcast());
+        socket.set_broadcast(true);
+        assert!(socket.broadcast());
+    }
+
+    #[test]
+    fn multicast_groups() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert_eq!(
+            socket.join_multicast(Ipv4Addr::new(10, 0, 0, 1)),
+            Err(Error::Unaddressable)
+        );
+
+        for i in 0..MAX_MULTICAST_GROUPS as u8 {
+            assert_eq!(socket.join_multicast(Ipv4Addr::new(239, 0, 0, i)), Ok(()));
+        }
+        // Joining again is a no-op, even at capacity.
+        assert_eq!(socket.join_multicast(Ipv4Addr::new(239, 0, 0, 0)), Ok(()));
+        assert_eq!(socket.multicast_groups().len(), MAX_MULTICAST_GROUPS);
+        assert_eq!(
+            socket.join_multicast(Ipv4Addr::new(224, 0, 0, 251)),
+            Err(Error::Exhausted)
+        );
+
+        assert_eq!(socket.leave_multicast(Ipv4Addr::new(239, 0, 0, 0)), Ok(()));
+        assert_eq!(
+            socket.leave_multicast(Ipv4Addr::new(239, 0, 0, 0)),
+            Err(Error::Illegal)
+        );
+        assert!(!socket
+            .multicast_groups()
+            .contains(&Ipv4Addr::new(239, 0, 0, 0)));
+        assert_eq!(socket.join_multicast(Ipv4Addr::new(224, 0, 0, 251)), Ok(()));
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn traffic_stats() {
+        let mut socket = UdpSocket::<8>::new(0);
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.stats(), UdpStats::default());
+
+        socket.rx_enqueue_datagram(b"abc", other());
+        socket.rx_enqueue_datagram(b"defghi", other());
+        socket.rx_enqueue_slice(b"jk");
+        socket.recv_slice(&mut [0; 4]).unwrap();
+        socket.recv_slice(&mut [0; 4]).unwrap();
+        socket.send_slice(b"lmn").unwrap();
+        socket.tx_dequeue_datagram(&mut [0; 8]).unwrap();
+        socket.closed_by_remote(Instant::from_secs(0));
+
+        assert_eq!(
+            socket.stats(),
+            UdpStats {
+                datagrams_enqueued: 1,
+                datagrams_delivered: 1,
+                datagrams_dropped: 1,
+                rx_bytes: 5,
+                tx_bytes: 3,
+                remote_closes: 1,
+                recycles: 0,
+                mtu_rejections: 0,
+            }
+        );
+
+        socket.reset_stats();
+        assert_eq!(socket.stats(), UdpStats::default());
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn rx_waker() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let woken = || counter.0.load(Ordering::SeqCst);
+
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.register_rx_waker(&waker);
+
+        // Once per burst, as the waker is consumed when woken.
+        socket.rx_enqueue_datagram(b"abc", other());
+        socket.rx_enqueue_datagram(b"def", other());
+        socket.rx_enqueue_slice(b"ghi");
+        assert_eq!(woken(), 1);
+
+        socket.register_rx_waker(&waker);
+        socket.rx_enqueue_slice(b"");
+        assert_eq!(woken(), 1);
+        socket.set_available_data(10);
+        assert_eq!(woken(), 2);
+
+        socket.register_rx_waker(&waker);
+        socket.set_available_data(20);
+        assert_eq!(woken(), 2);
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert_eq!(woken(), 3);
+    }
+
+    #[test]
+    fn poll_recv_ready() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut socket = UdpSocket::<64>::new(0);
+        assert_eq!(
+            socket.poll_recv_ready(&mut cx),
+            Poll::Ready(Err(Error::Illegal))
+        );
+
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.poll_recv_ready(&mut cx), Poll::Pending);
+        socket.rx_enqueue_datagram(b"", other());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(socket.recv_queue(), 0);
+        assert_eq!(socket.poll_recv_ready(&mut cx), Poll::Ready(Ok(())));
+
+        socket.recv_slice(&mut []).unwrap();
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert_eq!(
+            socket.poll_recv_ready(&mut cx),
+            Poll::Ready(Err(Error::SocketClosed))
+        );
+    }
+
+    #[test]
+    fn connection_status() {
+        let mut socket = UdpSocket::<64>::new(0);
+        assert_eq!(
+            socket.connection_status(),
+            ConnectionStatus::Disconnected { reason: None }
+        );
+
+        socket.connect(remote()).unwrap();
+        assert_eq!(socket.connection_status(), ConnectionStatus::Connected);
+
+        socket.closed_by_remote(Instant::from_secs(0));
+        assert_eq!(
+            socket.connection_status(),
+            ConnectionStatus::Disconnected {
+                reason: Some(CloseReason::RemoteFin)
+            }
+        );
+        socket.close(Instant::from_secs(1));
+        assert_eq!(
+            socket.connection_status(),
+            ConnectionStatus::Disconnected {
+                reason: Some(CloseReason::RemoteFin)
+            }
+        );
+
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        socket.close(Instant::from_secs(0));
+        assert_eq!(
+            socket.connection_status(),
+            ConnectionStatus::Disconnected {
+                reason: Some(CloseReason::LocalClose)
+            }
+        );
+    }
+
+    #[test]
+    fn can_recv_bytes() {
+        let mut socket = UdpSocket::<8>::new(0);
+        assert!(!socket.can_recv());
+        assert!(!socket.can_recv_bytes(1));
+
+        socket.connect(remote()).unwrap();
+        assert!(socket.can_recv());
+        assert!(socket.can_recv_bytes(8));
+        assert!(!socket.can_recv_bytes(9));
+
+        assert_eq!(socket.rx_enqueue_datagram(b"abcde", remote()), 5);
+        assert_eq!(socket.rx_enqueue_datagram(b"fg", remote()), 2);
+        assert_eq!(socket.recv_slice(&mut [0; 8]), Ok(5));
+        // One byte is left at the end, which a longer datagram skips.
+        assert_eq!(socket.rx_window(), 6);
+        assert!(socket.can_recv_bytes(5));
+        assert!(!socket.can_recv_bytes(6));
+    }
+
+    #[test]
+    fn can_recv_bytes_chunk_slots() {
+        let mut socket = UdpSocket::<64>::new(0);
+        socket.connect(remote()).unwrap();
+        for i in 0..MAX_DATAGRAMS as u8 {
+            assert!(socket.can_recv_bytes(1));
+            assert_eq!(socket.rx_enqueue_datagram(&[i], remote()), 1);
+        }
+        assert!(socket.can_recv());
+        assert!(!socket.can_recv_bytes(1));
+    }
+
+    #[cfg(feature = "socket-udp")]
+    #[test]
+    #[allow(irrefutable_let_patterns)]
+    fn socket_rx_enqueue() {
+        let mut udp = UdpSocket::<64>::new(0);
+        udp.bind(1234).unwrap();
+        let mut socket: Socket<64> = udp.into();
+        assert!(socket.can_recv_bytes(64));
+        assert_eq!(socket.rx_enqueue(b"abc", Some(other())), 3);
+        assert_eq!(socket.rx_enqueue(b"de", None), 2);
+        assert!(!socket.can_recv_bytes(60));
+
+        let Socket::Udp(udp) = &mut socket else {
+            unreachable!()
+        };
+        let mut buf = [0; 8];
+        assert_eq!(udp.recv_from(&mut buf), Ok((3, other())));
+        assert_eq!(udp.recv_slice(&mut buf), Ok(2));
     }
 }
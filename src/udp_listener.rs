@@ -1,58 +1,309 @@
-use hash32::Hash;
 use heapless::{spsc::Queue, FnvIndexMap};
 use no_std_net::SocketAddr;
 
-use crate::{Error, SocketHandle};
+use crate::{
+    local_addr::{self, LocalAddr},
+    Error, ListenerError, PortAllocator, SocketHandle, SocketSet, TeardownEvent, UdpSocket,
+};
 
-pub struct UdpListener<const N: usize, const L: usize> {
-    /// Maps Server Socket handles to ports
-    handles: FnvIndexMap<SocketHandle, u16, N>,
+pub use crate::local_addr::SocketAddrWrapper;
+
+/// Listener for UDP server sockets.
+///
+/// Per binding, up to `L` incoming connections are queued, and up to `L`
+/// peers are mapped to their connection socket, see
+/// [`insert_peer`](#method.insert_peer). Both `N` and `L` must be powers of
+/// two.
+///
+/// A listener created [with a port allocator](#method.with_port_allocator)
+/// of up to `P` ports reserves every port it binds in it, so client ports
+/// allocated with [`allocate_port`](#method.allocate_port) never collide with
+/// a server port.
+pub struct UdpListener<const N: usize, const L: usize, const P: usize = 0> {
+    /// Maps Server Socket handles to local addresses
+    handles: FnvIndexMap<SocketHandle, SocketAddr, N>,
     /// Maps Connection Sockets to remote socket address
-    connections: FnvIndexMap<u16, Queue<(SocketHandle, SocketAddr), L>, N>,
+    connections: FnvIndexMap<SocketAddrWrapper, Queue<(SocketHandle, SocketAddr), L>, N>,
+    /// Counters and peers per local address
+    ports: FnvIndexMap<SocketAddrWrapper, PortState<L>, N>,
+    /// Allocator the bound ports are reserved in
+    allocator: Option<PortAllocator<P>>,
+}
+
+#[derive(Default)]
+struct PortState<const L: usize> {
+    /// Number of incoming connections dropped, as the queue was full
+    dropped: usize,
+    /// Number of incoming connections rejected
+    rejected: usize,
+    /// Maps remote socket addresses to Connection Sockets
+    peers: FnvIndexMap<SocketAddrWrapper, SocketHandle, L>,
+    #[cfg(feature = "stats")]
+    stats: crate::ListenerStats,
 }
 
-impl<const N: usize, const L: usize> UdpListener<N, L> {
+impl<const N: usize, const L: usize, const P: usize> Default for UdpListener<N, L, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const L: usize, const P: usize> UdpListener<N, L, P> {
     pub fn new() -> Self {
         Self {
             handles: FnvIndexMap::new(),
             connections: FnvIndexMap::new(),
+            ports: FnvIndexMap::new(),
+            allocator: None,
+        }
+    }
+
+    /// Create a listener that reserves every port it binds in `ports`, and
+    /// hands out client ports from it; see [`allocate_port`](#method.allocate_port).
+    pub fn with_port_allocator(ports: PortAllocator<P>) -> Self {
+        Self {
+            allocator: Some(ports),
+            ..Self::new()
+        }
+    }
+
+    /// Return the port allocator of the listener, if it has one.
+    pub fn port_allocator(&self) -> Option<&PortAllocator<P>> {
+        self.allocator.as_ref()
+    }
+
+    /// Allocate a local port for a client socket, which is never a port bound
+    /// by a server socket of the listener.
+    ///
+    /// Returns `None` if the listener has no port allocator, or it has no
+    /// free port left.
+    pub fn allocate_port(&mut self) -> Option<u16> {
+        self.allocator.as_mut()?.allocate()
+    }
+
+    /// Release a port allocated with [`allocate_port`](#method.allocate_port).
+    ///
+    /// Returns `Err(ListenerError::PortInUse)` if a server socket of the
+    /// listener is bound to `port`, and `Err(ListenerError::NotBound)` if the
+    /// port is not allocated, or the listener has no port allocator.
+    pub fn release_port(&mut self, port: u16) -> Result<(), ListenerError> {
+        if self.holds_port(port) {
+            return Err(ListenerError::PortInUse);
+        }
+        self.allocator
+            .as_mut()
+            .ok_or(ListenerError::NotBound)?
+            .release(port)
+    }
+
+    fn holds_port(&self, port: u16) -> bool {
+        self.handles.values().any(|local| local.port() == port)
+    }
+
+    /// Reserve `port` for a new binding, unless another binding holds it.
+    fn reserve_port(&mut self, port: u16) -> Result<(), ListenerError> {
+        if self.holds_port(port) {
+            return Ok(());
+        }
+        match self.allocator.as_mut() {
+            Some(ports) => ports.reserve(port),
+            None => Ok(()),
+        }
+    }
+
+    /// Release `port` once no binding holds it anymore.
+    fn release_unbound_port(&mut self, port: u16) {
+        if self.holds_port(port) {
+            return;
+        }
+        if let Some(ports) = self.allocator.as_mut() {
+            ports.release(port).ok();
         }
     }
 
-    /// Bind sockethandle to port, and create queue for incomming sockets
-    pub fn bind(&mut self, handle: SocketHandle, port: u16) -> Result<(), Error> {
+    /// Bind sockethandle to local address, and create queue for incomming
+    /// sockets
+    ///
+    /// Binding to the unspecified address gives a wildcard binding of the
+    /// port, which can coexist with bindings of the port to specific
+    /// addresses; see [`LocalAddr`] for which one a lookup picks.
+    ///
+    /// Returns `Err(ListenerError::PortInUse)` if the port is allocated to a
+    /// client socket; see [`allocate_port`](#method.allocate_port).
+    pub fn bind(&mut self, handle: SocketHandle, local: SocketAddr) -> Result<(), ListenerError> {
         if self.handles.contains_key(&handle) {
-            return Err(Error::ListenerError);
+            return Err(ListenerError::AlreadyBound);
+        }
+        if local_addr::conflicts(&self.connections, local) {
+            return Err(ListenerError::PortInUse);
         }
 
-        self.handles
-            .insert(handle, port)
-            .map_err(|_| Error::ListenerError)?;
-        self.connections
-            .insert(port, Queue::new())
-            .map_err(|_| Error::ListenerError)?;
+        self.reserve_port(local.port())?;
+        let key = SocketAddrWrapper(local);
+        if self.handles.insert(handle, local).is_err() {
+            self.release_unbound_port(local.port());
+            return Err(ListenerError::Full);
+        }
+        if self.connections.insert(key, Queue::new()).is_err() {
+            self.handles.remove(&handle);
+            self.release_unbound_port(local.port());
+            return Err(ListenerError::Full);
+        }
+        if self.ports.insert(key, PortState::default()).is_err() {
+            self.handles.remove(&handle);
+            self.connections.remove(&key);
+            self.release_unbound_port(local.port());
+            return Err(ListenerError::Full);
+        }
 
         Ok(())
     }
 
-    /// Unbind sockethandle to port, and create queue for incomming sockets
-    pub fn unbind(&mut self, handle: SocketHandle) -> Result<(), Error> {
-        if let Some(port) = self.handles.remove(&handle) {
-            self.connections.remove(&port);
-            Ok(())
-        } else {
-            Err(Error::ListenerError)
+    /// Bind sockethandle to port on any local address; see
+    /// [`bind`](#method.bind).
+    pub fn bind_port(&mut self, handle: SocketHandle, port: u16) -> Result<(), ListenerError> {
+        self.bind(handle, SocketAddrWrapper::any(port).0)
+    }
+
+    /// Unbind the server socket `handle` from its port, calling `f` for every
+    /// queued connection and then every mapped peer that is dropped, so the
+    /// caller can close their sockets on the module.
+    ///
+    /// The port can be bound again afterwards.
+    pub fn unbind<F>(&mut self, handle: SocketHandle, mut f: F) -> Result<(), ListenerError>
+    where
+        F: FnMut(TeardownEvent),
+    {
+        let local = self
+            .handles
+            .remove(&handle)
+            .ok_or(ListenerError::NotBound)?;
+        self.release_unbound_port(local.port());
+        self.drop_binding(handle, local, &mut f);
+        Ok(())
+    }
+
+    /// Tear down the listener, calling `f` for every queued connection and
+    /// mapped peer, followed by the binding they belong to.
+    ///
+    /// `f` should neither allocate nor panic.
+    pub fn teardown<F>(mut self, mut f: F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        let handles = core::mem::take(&mut self.handles);
+        for (&listener, &local) in handles.iter() {
+            self.drop_binding(listener, local, &mut f);
+            f(TeardownEvent::Listener {
+                handle: listener,
+                port: local.port(),
+            });
         }
     }
 
-    /// Get incomming connection queue for port
-    pub fn incoming(&mut self, port: u16) -> Option<&mut Queue<(SocketHandle, SocketAddr), L>> {
-        self.connections.get_mut(&port)
+    /// Drop the queued connections and the peers of every binding, calling `f`
+    /// for each, but keep the bindings; see [`teardown`](crate::teardown).
+    #[cfg(feature = "socket-tcp")]
+    pub(crate) fn drop_connections<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        let handles = self.handles.clone();
+        for (&listener, &local) in handles.iter() {
+            self.drop_binding(listener, local, f);
+        }
     }
 
-    /// Returns true if port is UDP server port
-    pub fn is_port_bound(&self, port: u16) -> bool {
-        self.connections.get(&port).is_some()
+    /// Remove every binding, calling `f` for each.
+    #[cfg(feature = "socket-tcp")]
+    pub(crate) fn drop_bindings<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        for (&handle, local) in self.handles.iter() {
+            f(TeardownEvent::Listener {
+                handle,
+                port: local.port(),
+            });
+        }
+        self.handles.clear();
+    }
+
+    /// Drop the queue and the peers of the binding of `listener` to `local`.
+    ///
+    /// A peer socket that is also queued is only reported once, as pending.
+    fn drop_binding<F>(&mut self, listener: SocketHandle, local: SocketAddr, f: &mut F)
+    where
+        F: FnMut(TeardownEvent),
+    {
+        let key = SocketAddrWrapper(local);
+        let queue = self.connections.remove(&key);
+        let queued = |handle: SocketHandle| {
+            queue
+                .as_ref()
+                .is_some_and(|queue| queue.iter().any(|(h, _)| *h == handle))
+        };
+        if let Some(queue) = &queue {
+            for &(handle, _) in queue.iter() {
+                f(TeardownEvent::Pending { listener, handle });
+            }
+        }
+        if let Some(state) = self.ports.remove(&key) {
+            for &handle in state.peers.values().filter(|&&handle| !queued(handle)) {
+                f(TeardownEvent::Peer { listener, handle });
+            }
+        }
+    }
+
+    /// Queue an incoming connection from `addr` on the binding that `local`
+    /// matches, which the module gave socket `handle`.
+    ///
+    /// If the queue of the binding is full, the connection is dropped and
+    /// counted, see [`dropped`](#method.dropped), and
+    /// `Err(ListenerError::BacklogFull)` is returned; the driver should close
+    /// the socket on the module.
+    pub fn push_incoming(
+        &mut self,
+        local: impl Into<LocalAddr>,
+        handle: SocketHandle,
+        addr: SocketAddr,
+    ) -> Result<(), ListenerError> {
+        let key = self.key(local).ok_or(ListenerError::NotBound)?;
+        let queue = self
+            .connections
+            .get_mut(&key)
+            .ok_or(ListenerError::NotBound)?;
+        let state = self.ports.get_mut(&key).ok_or(ListenerError::NotBound)?;
+        if queue.enqueue((handle, addr)).is_err() {
+            state.dropped += 1;
+            #[cfg(feature = "stats")]
+            {
+                state.stats.dropped += 1;
+            }
+            return Err(ListenerError::BacklogFull);
+        }
+        #[cfg(feature = "stats")]
+        {
+            state.stats.peak_backlog = state.stats.peak_backlog.max(queue.len());
+        }
+        Ok(())
+    }
+
+    /// Get incomming connection queue for a port or a full local address
+    ///
+    /// Connections queued here directly are not counted as dropped when the
+    /// queue is full; see [`push_incoming`](#method.push_incoming).
+    pub fn incoming(
+        &mut self,
+        local: impl Into<LocalAddr>,
+    ) -> Option<&mut Queue<(SocketHandle, SocketAddr), L>> {
+        let key = self.key(local)?;
+        self.connections.get_mut(&key)
+    }
+
+    /// Returns true if a port or a full local address is UDP server binding
+    pub fn is_port_bound(&self, local: impl Into<LocalAddr>) -> bool {
+        self.key(local).is_some()
     }
 
     /// Returns true if socket is UDP server socket
@@ -60,82 +311,767 @@ impl<const N: usize, const L: usize> UdpListener<N, L> {
         self.handles.get(&handle).is_some()
     }
 
+    /// Iterate the server sockets and the ports they are bound to, e.g. to
+    /// bind them again after a module reboot.
+    pub fn bound_ports(&self) -> impl Iterator<Item = (SocketHandle, u16)> + '_ {
+        self.bound_addrs()
+            .map(|(handle, local)| (handle, local.port()))
+    }
+
+    /// Iterate the server sockets and the local addresses they are bound to.
+    pub fn bound_addrs(&self) -> impl Iterator<Item = (SocketHandle, SocketAddr)> + '_ {
+        self.handles.iter().map(|(&handle, &local)| (handle, local))
+    }
+
+    /// Return the number of ports the listener can bind.
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Return the number of bound ports.
+    pub fn bound_count(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Return the number of connections queued on the binding that `local`
+    /// matches, or 0 if there is no such binding.
+    pub fn pending(&self, local: impl Into<LocalAddr>) -> usize {
+        self.key(local)
+            .and_then(|key| self.connections.get(&key))
+            .map_or(0, |queue| queue.len())
+    }
+
+    /// Return the number of connections that can be queued per port.
+    pub fn backlog_capacity(&self) -> usize {
+        // `spsc::Queue` keeps one slot free.
+        L - 1
+    }
+
+    /// Return the number of connections queued on all ports.
+    pub fn total_pending(&self) -> usize {
+        self.connections.values().map(|queue| queue.len()).sum()
+    }
+
     /// See if a connection is available for server
-    pub fn available(&mut self, handle: SocketHandle) -> Result<bool, Error> {
-        let port = self.handles.get(&handle).ok_or(Error::ListenerError)?;
-        Ok(!self
-            .connections
-            .get_mut(port)
-            .ok_or(Error::ListenerError)?
-            .is_empty())
+    pub fn available(&mut self, handle: SocketHandle) -> Result<bool, ListenerError> {
+        Ok(!self.queue(handle)?.is_empty())
     }
 
     /// Peek from queue of incomming connections for socket.
     pub fn peek_remote(
         &mut self,
         handle: SocketHandle,
-    ) -> Result<&(SocketHandle, SocketAddr), Error> {
-        let port = self.handles.get(&handle).ok_or(Error::ListenerError)?;
-        self.connections
-            .get_mut(port)
-            .ok_or(Error::ListenerError)?
-            .peek()
-            .ok_or(Error::ListenerError)
+    ) -> Result<&(SocketHandle, SocketAddr), ListenerError> {
+        self.queue(handle)?.peek().ok_or(ListenerError::WouldBlock)
     }
 
     /// Pop from queue of incomming connections for socket.
     pub fn get_remote(
         &mut self,
         handle: SocketHandle,
-    ) -> Result<(SocketHandle, SocketAddr), Error> {
-        let port = self.handles.get(&handle).ok_or(Error::ListenerError)?;
+    ) -> Result<(SocketHandle, SocketAddr), ListenerError> {
+        let (connection, backlog) = self.dequeue(handle)?;
+        #[cfg(feature = "stats")]
+        self.record(handle, backlog, |stats| stats.accepted += 1);
+        #[cfg(not(feature = "stats"))]
+        let _ = backlog;
+        Ok(connection)
+    }
+
+    /// Refuse the next incoming connection for socket, and count it, see
+    /// [`rejected`](#method.rejected).
+    ///
+    /// The connection is returned like with [`get_remote`](#method.get_remote),
+    /// so the caller can close its socket on the module.
+    pub fn reject(
+        &mut self,
+        handle: SocketHandle,
+    ) -> Result<(SocketHandle, SocketAddr), ListenerError> {
+        let local = self.get_local_addr(handle)?;
+        let (connection, backlog) = self.dequeue(handle)?;
+        if let Some(state) = self.ports.get_mut(&SocketAddrWrapper(local)) {
+            state.rejected += 1;
+        }
+        #[cfg(feature = "stats")]
+        self.record(handle, backlog, |stats| stats.rejected += 1);
+        #[cfg(not(feature = "stats"))]
+        let _ = backlog;
+        Ok(connection)
+    }
+
+    /// Return the number of incoming connections dropped on the binding that
+    /// `local` matches since it was bound, as its queue was full, or `None`
+    /// if there is no such binding; see [`push_incoming`](#method.push_incoming).
+    pub fn dropped(&self, local: impl Into<LocalAddr>) -> Option<usize> {
+        let key = self.key(local)?;
+        self.ports.get(&key).map(|state| state.dropped)
+    }
+
+    /// Return the number of incoming connections rejected on the binding
+    /// that `local` matches since it was bound, or `None` if there is no such
+    /// binding.
+    pub fn rejected(&self, local: impl Into<LocalAddr>) -> Option<usize> {
+        let key = self.key(local)?;
+        self.ports.get(&key).map(|state| state.rejected)
+    }
+
+    /// Return the statistics of the binding that `local` matches, since it
+    /// was bound, or `None` if there is no such binding.
+    ///
+    /// The peak backlog is sampled as connections are queued with
+    /// [`push_incoming`](#method.push_incoming), and as they are taken from
+    /// the queue, for those queued through [`incoming`](#method.incoming).
+    /// Queued connections do not expire, so `expired` stays 0.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self, local: impl Into<LocalAddr>) -> Option<&crate::ListenerStats> {
+        let key = self.key(local)?;
+        self.ports.get(&key).map(|state| &state.stats)
+    }
+
+    /// Get the connection socket that datagrams from `addr` on the binding
+    /// that `local` matches are mapped to.
+    pub fn lookup_peer(
+        &self,
+        local: impl Into<LocalAddr>,
+        addr: SocketAddr,
+    ) -> Option<SocketHandle> {
+        let key = self.key(local)?;
+        self.ports
+            .get(&key)?
+            .peers
+            .get(&SocketAddrWrapper(addr))
+            .copied()
+    }
+
+    /// Map datagrams from `addr` on the binding that `local` matches to
+    /// connection socket `handle`, returning the socket it was mapped to
+    /// before, if any.
+    ///
+    /// Peers are never evicted: if `L` peers are already mapped on the
+    /// binding, `Err(ListenerError::Full)` is returned, until one is removed
+    /// with [`remove_peer`](#method.remove_peer).
+    pub fn insert_peer(
+        &mut self,
+        local: impl Into<LocalAddr>,
+        addr: SocketAddr,
+        handle: SocketHandle,
+    ) -> Result<Option<SocketHandle>, ListenerError> {
+        self.state_mut(local)
+            .ok_or(ListenerError::NotBound)?
+            .peers
+            .insert(SocketAddrWrapper(addr), handle)
+            .map_err(|_| ListenerError::Full)
+    }
+
+    /// Remove the mapping of `addr` on the binding that `local` matches,
+    /// returning the connection socket it was mapped to.
+    pub fn remove_peer(
+        &mut self,
+        local: impl Into<LocalAddr>,
+        addr: SocketAddr,
+    ) -> Option<SocketHandle> {
+        self.state_mut(local)?
+            .peers
+            .remove(&SocketAddrWrapper(addr))
+    }
+
+    pub fn get_port(&self, handle: SocketHandle) -> Result<u16, ListenerError> {
+        self.get_local_addr(handle).map(|local| local.port())
+    }
+
+    /// Get the local address server socket `handle` is bound to.
+    pub fn get_local_addr(&self, handle: SocketHandle) -> Result<SocketAddr, ListenerError> {
+        self.handles
+            .get(&handle)
+            .copied()
+            .ok_or(ListenerError::NotBound)
+    }
+
+    /// Pop the next connection for socket, with the length of the queue
+    /// before.
+    fn dequeue(
+        &mut self,
+        handle: SocketHandle,
+    ) -> Result<((SocketHandle, SocketAddr), usize), ListenerError> {
+        let queue = self.queue(handle)?;
+        let backlog = queue.len();
+        let connection = queue.dequeue().ok_or(ListenerError::WouldBlock)?;
+        Ok((connection, backlog))
+    }
+
+    #[cfg(feature = "stats")]
+    fn record<F>(&mut self, handle: SocketHandle, backlog: usize, f: F)
+    where
+        F: FnOnce(&mut crate::ListenerStats),
+    {
+        let local = match self.handles.get(&handle) {
+            Some(local) => SocketAddrWrapper(*local),
+            None => return,
+        };
+        if let Some(state) = self.ports.get_mut(&local) {
+            state.stats.peak_backlog = state.stats.peak_backlog.max(backlog);
+            f(&mut state.stats);
+        }
+    }
+
+    fn queue(
+        &mut self,
+        handle: SocketHandle,
+    ) -> Result<&mut Queue<(SocketHandle, SocketAddr), L>, ListenerError> {
+        let local = self.handles.get(&handle).ok_or(ListenerError::NotBound)?;
         self.connections
-            .get_mut(port)
-            .ok_or(Error::ListenerError)?
-            .dequeue()
-            .ok_or(Error::ListenerError)
+            .get_mut(&SocketAddrWrapper(*local))
+            .ok_or(ListenerError::NotBound)
     }
 
-    pub fn get_port(&mut self, handle: SocketHandle) -> Result<u16, Error> {
-        let port = self.handles.get(&handle).ok_or(Error::ListenerError)?;
-        Ok(*port)
+    fn key(&self, local: impl Into<LocalAddr>) -> Option<SocketAddrWrapper> {
+        local_addr::resolve(&self.connections, local.into())
     }
 
-    /// Gives an outgoing connection, if first in queue matches socketaddr
-    /// Removes it from stack.
+    fn state_mut(&mut self, local: impl Into<LocalAddr>) -> Option<&mut PortState<L>> {
+        let key = self.key(local)?;
+        self.ports.get_mut(&key)
+    }
+
+    /// Gives the outgoing connection to `addr` queued for socket, and
+    /// removes it from the queue, keeping the others in order.
     pub fn get_outgoing(
         &mut self,
         handle: &SocketHandle,
         addr: SocketAddr,
     ) -> Option<SocketHandle> {
-        let port = self.handles.get(handle)?;
-        let queue = self.connections.get_mut(port)?;
-        let (_, queue_addr) = queue.peek()?;
-        if *queue_addr == addr {
-            let (handle, _) = queue.dequeue()?;
-            return Some(handle);
+        let local = self.handles.get(handle)?;
+        let queue = self.connections.get_mut(&SocketAddrWrapper(*local))?;
+        if !queue.iter().any(|(_, queue_addr)| *queue_addr == addr) {
+            return None;
+        }
+
+        // `spsc::Queue` can only be popped from the front, so rotate the
+        // whole queue once, leaving out the first match.
+        let mut found = None;
+        let backlog = queue.len();
+        for _ in 0..backlog {
+            let entry = queue.dequeue()?;
+            if found.is_none() && entry.1 == addr {
+                found = Some(entry.0);
+            } else {
+                // An entry was just dequeued, so there is room for it.
+                queue.enqueue(entry).ok();
+            }
         }
-        None
+        #[cfg(feature = "stats")]
+        if found.is_some() {
+            self.record(*handle, backlog, |stats| stats.accepted += 1);
+        }
+        found
+    }
+
+    /// Find the outgoing connection to `addr` queued for socket, without
+    /// removing it.
+    pub fn find_remote(
+        &self,
+        handle: SocketHandle,
+        addr: SocketAddr,
+    ) -> Option<&(SocketHandle, SocketAddr)> {
+        let local = self.handles.get(&handle)?;
+        self.connections
+            .get(&SocketAddrWrapper(*local))?
+            .iter()
+            .find(|(_, queue_addr)| *queue_addr == addr)
     }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq)]
-pub struct SocketAddrWrapper(SocketAddr);
+#[cfg(feature = "defmt")]
+impl<const N: usize, const L: usize, const P: usize> defmt::Format for UdpListener<N, L, P> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "[");
+        for (handle, local) in self.bound_addrs() {
+            defmt::write!(
+                fmt,
+                "[{:?}, port: {}, pending: {}/{}],",
+                handle,
+                local.port(),
+                self.pending(local),
+                self.backlog_capacity()
+            );
+        }
+        defmt::write!(fmt, "] bound: {}/{}", self.bound_count(), self.capacity());
+    }
+}
 
-impl Hash for SocketAddrWrapper {
-    fn hash<H>(&self, state: &mut H)
-    where
-        H: hash32::Hasher,
-    {
-        match self.0 {
-            SocketAddr::V4(ip) => {
-                ip.ip().octets().hash(state);
-                ip.port().hash(state);
-            }
-            SocketAddr::V6(ip) => {
-                ip.ip().octets().hash(state);
-                ip.port().hash(state);
-            }
+/// Turn the next incoming connection on a UDP server socket into a socket in
+/// `set`, and return its handle.
+///
+/// The new socket takes the handle assigned by the module, and is connected
+/// to the remote peer of the connection. The connection is only removed from
+/// the listener queue once the socket has been added to the set, so on error
+/// the listener is left untouched.
+pub fn spawn_udp_connection<
+    const N: usize,
+    const L: usize,
+    const P: usize,
+    const SN: usize,
+    const SL: usize,
+    const STXL: usize,
+>(
+    set: &mut SocketSet<SN, SL, STXL>,
+    listener: &mut UdpListener<N, L, P>,
+    server_handle: SocketHandle,
+) -> Result<SocketHandle, Error> {
+    let (handle, remote) = *listener.peek_remote(server_handle)?;
+
+    let mut socket = UdpSocket::new(handle.id());
+    socket.connect(remote)?;
+    let handle = set.add(socket)?;
+
+    listener.get_remote(server_handle)?;
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "socket-tcp")]
+    use crate::TcpSocket;
+    use no_std_net::{Ipv4Addr, SocketAddrV4};
+
+    fn remote() -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000))
+    }
+
+    #[test]
+    fn spawn_connection() {
+        let mut set = SocketSet::<2, 64>::new();
+        let mut listener = UdpListener::<2, 2>::new();
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        listener
+            .incoming(5000)
+            .unwrap()
+            .enqueue((SocketHandle::new(1), remote()))
+            .unwrap();
+
+        assert_eq!(
+            spawn_udp_connection(&mut set, &mut listener, SocketHandle::new(0)),
+            Ok(SocketHandle::new(1))
+        );
+        assert_eq!(listener.available(SocketHandle::new(0)), Ok(false));
+        assert_eq!(
+            listener.get_remote(SocketHandle::new(0)),
+            Err(ListenerError::WouldBlock)
+        );
+
+        let socket = set.get::<UdpSocket<64>>(SocketHandle::new(1)).unwrap();
+        assert_eq!(socket.endpoint(), Some(remote()));
+    }
+
+    #[test]
+    fn bound_ports() {
+        let mut listener = UdpListener::<4, 2>::new();
+        assert_eq!(listener.bound_ports().count(), 0);
+
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        listener.bind_port(SocketHandle::new(1), 5001).unwrap();
+        let ports = listener.bound_ports().collect::<heapless::Vec<_, 4>>();
+        assert_eq!(
+            ports,
+            [(SocketHandle::new(0), 5000), (SocketHandle::new(1), 5001)]
+        );
+
+        listener.unbind(SocketHandle::new(0), |_| ()).unwrap();
+        let ports = listener.bound_ports().collect::<heapless::Vec<_, 4>>();
+        assert_eq!(ports, [(SocketHandle::new(1), 5001)]);
+    }
+
+    #[test]
+    fn unbind_reports_dropped_sockets() {
+        let peer = |port| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), port));
+        let server = SocketHandle::new(0);
+
+        let mut listener = UdpListener::<2, 4>::new();
+        listener.bind_port(server, 5000).unwrap();
+        let queue = listener.incoming(5000).unwrap();
+        queue.enqueue((SocketHandle::new(1), peer(4001))).unwrap();
+        queue.enqueue((SocketHandle::new(2), peer(4002))).unwrap();
+        // Socket 2 is both queued and mapped, so it is reported once
+        listener
+            .insert_peer(5000, peer(4002), SocketHandle::new(2))
+            .unwrap();
+        listener
+            .insert_peer(5000, peer(4003), SocketHandle::new(3))
+            .unwrap();
+
+        let mut events: heapless::Vec<TeardownEvent, 4> = heapless::Vec::new();
+        listener
+            .unbind(server, |event| events.push(event).unwrap())
+            .unwrap();
+        assert_eq!(
+            &events[..],
+            &[
+                TeardownEvent::Pending {
+                    listener: server,
+                    handle: SocketHandle::new(1)
+                },
+                TeardownEvent::Pending {
+                    listener: server,
+                    handle: SocketHandle::new(2)
+                },
+                TeardownEvent::Peer {
+                    listener: server,
+                    handle: SocketHandle::new(3)
+                },
+            ]
+        );
+        assert_eq!(listener.lookup_peer(5000, peer(4003)), None);
+
+        // Teardown reports the peers too, before the binding
+        listener.bind_port(server, 5000).unwrap();
+        listener
+            .insert_peer(5000, peer(4003), SocketHandle::new(3))
+            .unwrap();
+        events.clear();
+        listener.teardown(|event| events.push(event).unwrap());
+        assert_eq!(
+            &events[..],
+            &[
+                TeardownEvent::Peer {
+                    listener: server,
+                    handle: SocketHandle::new(3)
+                },
+                TeardownEvent::Listener {
+                    handle: server,
+                    port: 5000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reject() {
+        let mut listener = UdpListener::<2, 2>::new();
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        listener
+            .incoming(5000)
+            .unwrap()
+            .enqueue((SocketHandle::new(1), remote()))
+            .unwrap();
+
+        assert_eq!(listener.rejected(5000), Some(0));
+        assert_eq!(
+            listener.reject(SocketHandle::new(0)),
+            Ok((SocketHandle::new(1), remote()))
+        );
+        assert_eq!(listener.rejected(5000), Some(1));
+        assert_eq!(
+            listener.reject(SocketHandle::new(0)),
+            Err(ListenerError::WouldBlock)
+        );
+        assert_eq!(listener.rejected(5000), Some(1));
+
+        listener.unbind(SocketHandle::new(0), |_| ()).unwrap();
+        assert_eq!(listener.rejected(5000), None);
+        assert_eq!(
+            listener.reject(SocketHandle::new(0)),
+            Err(ListenerError::NotBound)
+        );
+    }
+
+    #[test]
+    fn interleaved_peers() {
+        let peer = |port| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), port));
+
+        let mut listener = UdpListener::<2, 4>::new();
+        let server = SocketHandle::new(0);
+        listener.bind_port(server, 5000).unwrap();
+        let queue = listener.incoming(5000).unwrap();
+        queue.enqueue((SocketHandle::new(1), peer(4001))).unwrap();
+        queue.enqueue((SocketHandle::new(2), peer(4002))).unwrap();
+        queue.enqueue((SocketHandle::new(3), peer(4001))).unwrap();
+
+        assert_eq!(
+            listener.find_remote(server, peer(4002)),
+            Some(&(SocketHandle::new(2), peer(4002)))
+        );
+        assert_eq!(listener.find_remote(server, peer(4003)), None);
+        assert_eq!(listener.find_remote(SocketHandle::new(1), peer(4002)), None);
+
+        // The second peer is matched, even though it is not first in queue.
+        assert_eq!(
+            listener.get_outgoing(&server, peer(4002)),
+            Some(SocketHandle::new(2))
+        );
+        assert_eq!(listener.get_outgoing(&server, peer(4002)), None);
+        assert_eq!(listener.get_outgoing(&server, peer(4003)), None);
+        assert_eq!(
+            listener.get_outgoing(&server, peer(4001)),
+            Some(SocketHandle::new(1))
+        );
+
+        listener
+            .incoming(5000)
+            .unwrap()
+            .enqueue((SocketHandle::new(4), peer(4002)))
+            .unwrap();
+        assert_eq!(
+            listener.get_remote(server),
+            Ok((SocketHandle::new(3), peer(4001)))
+        );
+        assert_eq!(
+            listener.get_remote(server),
+            Ok((SocketHandle::new(4), peer(4002)))
+        );
+    }
+
+    #[test]
+    fn peers() {
+        let peer = |port| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), port));
+
+        let mut listener = UdpListener::<2, 2>::new();
+        assert_eq!(
+            listener.insert_peer(5000, peer(4001), SocketHandle::new(1)),
+            Err(ListenerError::NotBound)
+        );
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        listener.bind_port(SocketHandle::new(5), 5001).unwrap();
+
+        assert_eq!(
+            listener.insert_peer(5000, peer(4001), SocketHandle::new(1)),
+            Ok(None)
+        );
+        assert_eq!(
+            listener.insert_peer(5000, peer(4002), SocketHandle::new(2)),
+            Ok(None)
+        );
+        assert_eq!(
+            listener.insert_peer(5000, peer(4003), SocketHandle::new(3)),
+            Err(ListenerError::Full)
+        );
+        assert_eq!(
+            listener.insert_peer(5000, peer(4001), SocketHandle::new(3)),
+            Ok(Some(SocketHandle::new(1)))
+        );
+        assert_eq!(
+            listener.lookup_peer(5000, peer(4001)),
+            Some(SocketHandle::new(3))
+        );
+        assert_eq!(listener.lookup_peer(5001, peer(4001)), None);
+
+        // The queue is independent of the peers.
+        listener
+            .incoming(5000)
+            .unwrap()
+            .enqueue((SocketHandle::new(4), peer(4004)))
+            .unwrap();
+        assert_eq!(
+            listener.get_outgoing(&SocketHandle::new(0), peer(4004)),
+            Some(SocketHandle::new(4))
+        );
+
+        assert_eq!(
+            listener.remove_peer(5000, peer(4002)),
+            Some(SocketHandle::new(2))
+        );
+        assert_eq!(listener.remove_peer(5000, peer(4002)), None);
+        assert_eq!(
+            listener.insert_peer(5000, peer(4003), SocketHandle::new(4)),
+            Ok(None)
+        );
+
+        listener.unbind(SocketHandle::new(0), |_| ()).unwrap();
+        assert_eq!(listener.lookup_peer(5000, peer(4001)), None);
+    }
+
+    #[test]
+    fn occupancy() {
+        let mut listener = UdpListener::<2, 2>::new();
+        assert_eq!(listener.capacity(), 2);
+        assert_eq!(listener.backlog_capacity(), 1);
+        assert_eq!(listener.total_pending(), 0);
+
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        listener.bind_port(SocketHandle::new(1), 5001).unwrap();
+        assert_eq!(listener.bound_count(), listener.capacity());
+
+        let queue = listener.incoming(5000).unwrap();
+        queue.enqueue((SocketHandle::new(2), remote())).unwrap();
+        assert!(queue.enqueue((SocketHandle::new(3), remote())).is_err());
+        assert_eq!(listener.pending(5000), listener.backlog_capacity());
+        assert_eq!(listener.pending(5001), 0);
+        assert_eq!(listener.pending(6000), 0);
+        assert_eq!(listener.total_pending(), 1);
+
+        listener.unbind(SocketHandle::new(0), |_| ()).unwrap();
+        assert_eq!(listener.bound_count(), 1);
+        assert_eq!(listener.total_pending(), 0);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() {
+        let peer = |port| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), port));
+
+        let mut listener = UdpListener::<2, 4>::new();
+        let server = SocketHandle::new(0);
+        listener.bind_port(server, 5000).unwrap();
+        assert_eq!(listener.stats(5001), None);
+
+        let queue = listener.incoming(5000).unwrap();
+        for id in 1..4 {
+            queue
+                .enqueue((SocketHandle::new(id), peer(4000 + id as u16)))
+                .unwrap();
         }
+        listener.get_remote(server).unwrap();
+        listener.reject(server).unwrap();
+        listener.get_outgoing(&server, peer(4003)).unwrap();
+        assert_eq!(listener.get_remote(server), Err(ListenerError::WouldBlock));
+
+        assert_eq!(
+            listener.stats(5000),
+            Some(&crate::ListenerStats {
+                accepted: 2,
+                rejected: 1,
+                peak_backlog: 3,
+                ..Default::default()
+            })
+        );
+
+        listener.unbind(server, |_| ()).unwrap();
+        listener.bind_port(server, 5000).unwrap();
+        assert_eq!(listener.stats(5000), Some(&crate::ListenerStats::default()));
+    }
+
+    #[test]
+    fn push_incoming() {
+        let peer = |port| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), port));
+
+        // A queue of capacity 4 holds 3 connections.
+        let mut listener = UdpListener::<2, 4>::new();
+        let server = SocketHandle::new(0);
+        assert_eq!(
+            listener.push_incoming(5000, SocketHandle::new(1), peer(4001)),
+            Err(ListenerError::NotBound)
+        );
+        listener.bind_port(server, 5000).unwrap();
+        for id in 1..4 {
+            listener
+                .push_incoming(5000, SocketHandle::new(id), peer(4000 + id as u16))
+                .unwrap();
+        }
+        assert_eq!(
+            listener.push_incoming(5000, SocketHandle::new(4), peer(4004)),
+            Err(ListenerError::BacklogFull)
+        );
+        assert_eq!(listener.dropped(5000), Some(1));
+        assert_eq!(listener.dropped(5001), None);
+        assert_eq!(
+            listener.get_remote(server),
+            Ok((SocketHandle::new(1), peer(4001)))
+        );
+
+        #[cfg(feature = "stats")]
+        assert_eq!(
+            listener.stats(5000),
+            Some(&crate::ListenerStats {
+                accepted: 1,
+                dropped: 1,
+                peak_backlog: 3,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn reserved_ports() {
+        let mut listener =
+            UdpListener::<2, 2, 4>::with_port_allocator(PortAllocator::new(5000..5002));
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        assert_eq!(listener.allocate_port(), Some(5001));
+        assert_eq!(listener.allocate_port(), None);
+        assert_eq!(listener.release_port(5000), Err(ListenerError::PortInUse));
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(1), 5001),
+            Err(ListenerError::PortInUse)
+        );
+
+        listener.unbind(SocketHandle::new(0), |_| ()).unwrap();
+        assert_eq!(listener.release_port(5001), Ok(()));
+        assert_eq!(listener.allocate_port(), Some(5000));
+    }
+
+    #[test]
+    fn errors() {
+        let mut listener = UdpListener::<2, 2>::new();
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(0), 5001),
+            Err(ListenerError::AlreadyBound)
+        );
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(1), 5000),
+            Err(ListenerError::PortInUse)
+        );
+        assert!(!listener.is_bound(SocketHandle::new(1)));
+        assert_eq!(listener.get_port(SocketHandle::new(0)), Ok(5000));
+        listener.bind_port(SocketHandle::new(1), 5001).unwrap();
+        assert_eq!(
+            listener.bind_port(SocketHandle::new(2), 5002),
+            Err(ListenerError::Full)
+        );
+
+        assert_eq!(
+            listener.peek_remote(SocketHandle::new(0)),
+            Err(ListenerError::WouldBlock)
+        );
+        assert_eq!(
+            listener.get_port(SocketHandle::new(2)),
+            Err(ListenerError::NotBound)
+        );
+        assert_eq!(
+            listener.available(SocketHandle::new(2)),
+            Err(ListenerError::NotBound)
+        );
+        assert_eq!(listener.unbind(SocketHandle::new(1), |_| ()), Ok(()));
+        assert_eq!(
+            listener.unbind(SocketHandle::new(1), |_| ()),
+            Err(ListenerError::NotBound)
+        );
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    #[test]
+    fn spawn_connection_set_full() {
+        let mut set = SocketSet::<1, 64>::new();
+        set.add(TcpSocket::new(2)).unwrap();
+
+        let mut listener = UdpListener::<2, 2>::new();
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+        listener
+            .incoming(5000)
+            .unwrap()
+            .enqueue((SocketHandle::new(1), remote()))
+            .unwrap();
+
+        assert_eq!(
+            spawn_udp_connection(&mut set, &mut listener, SocketHandle::new(0)),
+            Err(Error::SocketSetFull)
+        );
+        assert_eq!(
+            listener.peek_remote(SocketHandle::new(0)),
+            Ok(&(SocketHandle::new(1), remote()))
+        );
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn spawn_connection_nothing_pending() {
+        let mut set = SocketSet::<1, 64>::new();
+        let mut listener = UdpListener::<2, 2>::new();
+        listener.bind_port(SocketHandle::new(0), 5000).unwrap();
+
+        assert_eq!(
+            spawn_udp_connection(&mut set, &mut listener, SocketHandle::new(0)),
+            Err(Error::ListenerError)
+        );
+        assert!(set.is_empty());
     }
 }